@@ -0,0 +1,405 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use crate::room::Room;
+use crate::store::{StoreError, Storer};
+
+/// The async counterpart of [`Storer`], for backends whose I/O shouldn't block the
+/// runtime. Every method mirrors the [`Storer`] method of the same name.
+pub trait AsyncStorer<T: Clone> {
+    fn save_filter_id(
+        &mut self,
+        user_id: String,
+        filter_id: String,
+    ) -> impl Future<Output = Result<(), StoreError>> + Send;
+    fn load_filter_id(
+        &self,
+        user_id: String,
+    ) -> impl Future<Output = Result<Option<String>, StoreError>> + Send;
+    fn save_next_batch(
+        &mut self,
+        user_id: String,
+        next_batch_token: String,
+    ) -> impl Future<Output = Result<(), StoreError>> + Send;
+    fn load_next_batch(
+        &self,
+        user_id: String,
+    ) -> impl Future<Output = Result<Option<String>, StoreError>> + Send;
+    fn save_prev_batch(
+        &mut self,
+        room_id: String,
+        prev_batch: Option<String>,
+    ) -> impl Future<Output = Result<(), StoreError>> + Send;
+    fn load_prev_batch(
+        &self,
+        room_id: String,
+    ) -> impl Future<Output = Result<Option<String>, StoreError>> + Send;
+    fn save_room(&mut self, room: Room<T>) -> impl Future<Output = Result<(), StoreError>> + Send;
+    fn load_room(
+        &self,
+        room_id: String,
+    ) -> impl Future<Output = Result<Option<Room<T>>, StoreError>> + Send;
+    fn delete_room(&mut self, room_id: &str) -> impl Future<Output = Result<(), StoreError>> + Send;
+    fn list_room_ids(&self) -> impl Future<Output = Result<Vec<String>, StoreError>> + Send;
+    fn clear(&mut self) -> impl Future<Output = Result<(), StoreError>> + Send;
+    fn save_account_data(
+        &mut self,
+        user_id: String,
+        etype: String,
+        content: HashMap<String, T>,
+    ) -> impl Future<Output = Result<(), StoreError>> + Send;
+    fn load_account_data(
+        &self,
+        user_id: String,
+        etype: String,
+    ) -> impl Future<Output = Result<Option<HashMap<String, T>>, StoreError>> + Send;
+    fn save_room_account_data(
+        &mut self,
+        user_id: String,
+        room_id: String,
+        etype: String,
+        content: HashMap<String, T>,
+    ) -> impl Future<Output = Result<(), StoreError>> + Send;
+    fn load_room_account_data(
+        &self,
+        user_id: String,
+        room_id: String,
+        etype: String,
+    ) -> impl Future<Output = Result<Option<HashMap<String, T>>, StoreError>> + Send;
+}
+
+/// Adapts a synchronous [`Storer`] to [`AsyncStorer`] by running each call on
+/// tokio's blocking thread pool with `spawn_blocking`.
+///
+/// Calls must be made from inside a tokio runtime. Clones share the wrapped store.
+///
+/// # Examples
+///
+/// ```rust
+/// use matrux::async_store::{AsyncStorer, BlockingStore};
+/// use matrux::room::Room;
+/// use matrux::store::InMemoryStore;
+///
+/// # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+/// let mut store = BlockingStore::new(InMemoryStore::<String>::new());
+/// store.save_room(Room::new("!room:example.org".to_string())).await.unwrap();
+///
+/// assert_eq!(store.list_room_ids().await.unwrap(), ["!room:example.org"]);
+/// # });
+/// ```
+#[derive(Debug)]
+pub struct BlockingStore<S> {
+    store: Arc<Mutex<S>>,
+}
+
+impl<S> BlockingStore<S> {
+    pub fn new(store: S) -> Self {
+        BlockingStore {
+            store: Arc::new(Mutex::new(store)),
+        }
+    }
+
+    /// Runs `call` against the wrapped store on the blocking thread pool.
+    fn run<R, F>(&self, call: F) -> impl Future<Output = Result<R, StoreError>> + Send
+    where
+        S: Send + 'static,
+        R: Send + 'static,
+        F: FnOnce(&mut S) -> Result<R, StoreError> + Send + 'static,
+    {
+        let store = Arc::clone(&self.store);
+
+        async move {
+            tokio::task::spawn_blocking(move || {
+                let mut store = store
+                    .lock()
+                    .map_err(|_| StoreError::Backend("store lock poisoned".to_string()))?;
+                call(&mut store)
+            })
+            .await
+            .map_err(|err| StoreError::Backend(err.to_string()))?
+        }
+    }
+}
+
+impl<S> Clone for BlockingStore<S> {
+    fn clone(&self) -> Self {
+        BlockingStore {
+            store: Arc::clone(&self.store),
+        }
+    }
+}
+
+impl<T, S> AsyncStorer<T> for BlockingStore<S>
+where
+    T: Clone + Send + 'static,
+    S: Storer<T> + Send + 'static,
+{
+    fn save_filter_id(
+        &mut self,
+        user_id: String,
+        filter_id: String,
+    ) -> impl Future<Output = Result<(), StoreError>> + Send {
+        self.run(move |store| store.save_filter_id(user_id, filter_id))
+    }
+
+    fn load_filter_id(
+        &self,
+        user_id: String,
+    ) -> impl Future<Output = Result<Option<String>, StoreError>> + Send {
+        self.run(move |store| store.load_filter_id(user_id))
+    }
+
+    fn save_next_batch(
+        &mut self,
+        user_id: String,
+        next_batch_token: String,
+    ) -> impl Future<Output = Result<(), StoreError>> + Send {
+        self.run(move |store| store.save_next_batch(user_id, next_batch_token))
+    }
+
+    fn load_next_batch(
+        &self,
+        user_id: String,
+    ) -> impl Future<Output = Result<Option<String>, StoreError>> + Send {
+        self.run(move |store| store.load_next_batch(user_id))
+    }
+
+    fn save_prev_batch(
+        &mut self,
+        room_id: String,
+        prev_batch: Option<String>,
+    ) -> impl Future<Output = Result<(), StoreError>> + Send {
+        self.run(move |store| store.save_prev_batch(room_id, prev_batch))
+    }
+
+    fn load_prev_batch(
+        &self,
+        room_id: String,
+    ) -> impl Future<Output = Result<Option<String>, StoreError>> + Send {
+        self.run(move |store| store.load_prev_batch(room_id))
+    }
+
+    fn save_room(&mut self, room: Room<T>) -> impl Future<Output = Result<(), StoreError>> + Send {
+        self.run(move |store| store.save_room(room))
+    }
+
+    fn load_room(
+        &self,
+        room_id: String,
+    ) -> impl Future<Output = Result<Option<Room<T>>, StoreError>> + Send {
+        self.run(move |store| store.load_room(room_id))
+    }
+
+    fn delete_room(&mut self, room_id: &str) -> impl Future<Output = Result<(), StoreError>> + Send {
+        let room_id = room_id.to_string();
+        self.run(move |store| store.delete_room(&room_id))
+    }
+
+    fn list_room_ids(&self) -> impl Future<Output = Result<Vec<String>, StoreError>> + Send {
+        self.run(|store| store.list_room_ids())
+    }
+
+    fn clear(&mut self) -> impl Future<Output = Result<(), StoreError>> + Send {
+        self.run(|store| store.clear())
+    }
+
+    fn save_account_data(
+        &mut self,
+        user_id: String,
+        etype: String,
+        content: HashMap<String, T>,
+    ) -> impl Future<Output = Result<(), StoreError>> + Send {
+        self.run(move |store| store.save_account_data(user_id, etype, content))
+    }
+
+    fn load_account_data(
+        &self,
+        user_id: String,
+        etype: String,
+    ) -> impl Future<Output = Result<Option<HashMap<String, T>>, StoreError>> + Send {
+        self.run(move |store| store.load_account_data(user_id, etype))
+    }
+
+    fn save_room_account_data(
+        &mut self,
+        user_id: String,
+        room_id: String,
+        etype: String,
+        content: HashMap<String, T>,
+    ) -> impl Future<Output = Result<(), StoreError>> + Send {
+        self.run(move |store| store.save_room_account_data(user_id, room_id, etype, content))
+    }
+
+    fn load_room_account_data(
+        &self,
+        user_id: String,
+        room_id: String,
+        etype: String,
+    ) -> impl Future<Output = Result<Option<HashMap<String, T>>, StoreError>> + Send {
+        self.run(move |store| store.load_room_account_data(user_id, room_id, etype))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::InMemoryStore;
+
+    fn blocking_store() -> BlockingStore<InMemoryStore<String>> {
+        BlockingStore::new(InMemoryStore::new())
+    }
+
+    #[tokio::test]
+    async fn batch_tokens_and_filters() {
+        let mut store = blocking_store();
+        let user_id = "@alice:example.org".to_string();
+        let room_id = "!room:example.org".to_string();
+
+        store
+            .save_filter_id(user_id.clone(), "f1".to_string())
+            .await
+            .unwrap();
+        store
+            .save_next_batch(user_id.clone(), "s1".to_string())
+            .await
+            .unwrap();
+        store
+            .save_prev_batch(room_id.clone(), Some("p1".to_string()))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            store
+                .load_filter_id(user_id.clone())
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("f1")
+        );
+        assert_eq!(
+            store.load_next_batch(user_id).await.unwrap().as_deref(),
+            Some("s1")
+        );
+        assert_eq!(
+            store
+                .load_prev_batch(room_id.clone())
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("p1")
+        );
+
+        store.save_prev_batch(room_id.clone(), None).await.unwrap();
+        assert_eq!(store.load_prev_batch(room_id).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn rooms() {
+        let mut store = blocking_store();
+
+        store
+            .save_room(Room::new("!b:example.org".to_string()))
+            .await
+            .unwrap();
+        store
+            .save_room(Room::new("!a:example.org".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(
+            store.list_room_ids().await.unwrap(),
+            ["!a:example.org", "!b:example.org"]
+        );
+
+        let room = store.load_room("!a:example.org".to_string()).await.unwrap();
+        assert_eq!(room.map(|room| room.id).as_deref(), Some("!a:example.org"));
+
+        store.delete_room("!a:example.org").await.unwrap();
+        assert!(store
+            .load_room("!a:example.org".to_string())
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(store.list_room_ids().await.unwrap(), ["!b:example.org"]);
+
+        store.clear().await.unwrap();
+        assert!(store.list_room_ids().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn account_data() {
+        let mut store = blocking_store();
+        let user_id = "@alice:example.org".to_string();
+        let room_id = "!room:example.org".to_string();
+
+        let mut content = HashMap::new();
+        content.insert("theme".to_string(), "dark".to_string());
+
+        store
+            .save_account_data(
+                user_id.clone(),
+                "org.example.settings".to_string(),
+                content.clone(),
+            )
+            .await
+            .unwrap();
+        store
+            .save_room_account_data(
+                user_id.clone(),
+                room_id.clone(),
+                "m.tag".to_string(),
+                HashMap::new(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            store
+                .load_account_data(user_id.clone(), "org.example.settings".to_string())
+                .await
+                .unwrap(),
+            Some(content)
+        );
+        assert_eq!(
+            store
+                .load_room_account_data(user_id.clone(), room_id.clone(), "m.tag".to_string())
+                .await
+                .unwrap(),
+            Some(HashMap::new())
+        );
+        assert_eq!(
+            store
+                .load_account_data(user_id, "m.tag".to_string())
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn clones_share_the_store() {
+        let mut store = blocking_store();
+        let clone = store.clone();
+
+        store
+            .save_room(Room::new("!room:example.org".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(clone.list_room_ids().await.unwrap(), ["!room:example.org"]);
+    }
+
+    #[tokio::test]
+    async fn panics_and_poisoning_are_backend_errors() {
+        let store = blocking_store();
+
+        let panicked = store
+            .run(|_| -> Result<(), StoreError> { panic!("the backend fell over") })
+            .await;
+        assert!(matches!(panicked, Err(StoreError::Backend(_))));
+
+        match store.list_room_ids().await {
+            Err(StoreError::Backend(message)) => assert_eq!(message, "store lock poisoned"),
+            other => panic!("expected a poisoned lock, got {:?}", other),
+        }
+    }
+}
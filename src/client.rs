@@ -0,0 +1,894 @@
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use reqwest::{Method, RequestBuilder};
+use serde_json::{json, Value};
+
+use crate::consts::{EVENT_MESSAGE, STATE_MEMBER};
+use crate::events::{Event, MessageContent};
+use crate::filter::Filter;
+use crate::response::{ErrorKind, RespError};
+use crate::room::Room;
+use crate::store::{InMemoryStore, StoreError, Storer};
+use crate::sync::{ParseOptions, SyncResponse};
+
+impl From<reqwest::Error> for RespError {
+    fn from(err: reqwest::Error) -> Self {
+        RespError::transport(err.status().map(|status| status.as_u16()), Box::new(err))
+    }
+}
+
+impl From<StoreError> for RespError {
+    fn from(err: StoreError) -> Self {
+        RespError::transport(None, Box::new(err))
+    }
+}
+
+/// How many times a send is attempted before giving up.
+const SEND_ATTEMPTS: usize = 3;
+
+/// How long to wait after `M_LIMIT_EXCEEDED` when the server doesn't say.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Generates transaction ids for sending events: a counter that only goes up, plus
+/// a random suffix so ids stay unique across restarts of the same device.
+///
+/// Clones share the counter.
+#[derive(Debug, Clone)]
+pub struct TransactionId {
+    counter: Arc<AtomicU64>,
+    suffix: String,
+}
+
+impl TransactionId {
+    pub fn new() -> TransactionId {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since_epoch| since_epoch.as_nanos()),
+        );
+
+        TransactionId {
+            counter: Arc::new(AtomicU64::new(0)),
+            suffix: format!("{:016x}", hasher.finish()),
+        }
+    }
+
+    /// Returns a transaction id that hasn't been handed out before.
+    pub fn generate(&self) -> String {
+        let count = self.counter.fetch_add(1, Ordering::Relaxed);
+        format!("{}.{}", count, self.suffix)
+    }
+}
+
+impl Default for TransactionId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Calls `send` with `txn_id` until it succeeds, reusing the same id every time so
+/// the server can drop duplicates.
+///
+/// Rate-limited attempts wait for the server's `retry_after` (or one second) and
+/// attempts that failed before the server answered are retried straight away. Any
+/// other error is returned immediately, as is the last one after `max_attempts`.
+pub async fn retry_with_txn_id<T, F, Fut>(
+    txn_id: &str,
+    max_attempts: usize,
+    mut send: F,
+) -> Result<T, RespError>
+where
+    F: FnMut(&str) -> Fut,
+    Fut: Future<Output = Result<T, RespError>>,
+{
+    let mut attempt = 1;
+
+    loop {
+        let err = match send(txn_id).await {
+            Ok(response) => return Ok(response),
+            Err(err) => err,
+        };
+
+        if attempt >= max_attempts {
+            return Err(err);
+        }
+
+        match err.kind() {
+            ErrorKind::LimitExceeded { retry_after } => {
+                tokio::time::sleep(retry_after.unwrap_or(DEFAULT_RETRY_AFTER)).await;
+            }
+            _ if err.status().is_none() => {}
+            _ => return Err(err),
+        }
+
+        attempt += 1;
+    }
+}
+
+/// A connection to a homeserver's client-server API, persisting what it syncs in a
+/// [`Storer`].
+#[derive(Debug, Clone)]
+pub struct Client<S = InMemoryStore<String>> {
+    homeserver: String,
+    http: reqwest::Client,
+    access_token: Option<String>,
+    user_id: Option<String>,
+    device_id: Option<String>,
+    store: S,
+    transaction_ids: TransactionId,
+    parse_options: ParseOptions,
+}
+
+impl Client {
+    /// Creates a client for the homeserver at `homeserver`, e.g. `https://matrix.org`,
+    /// that keeps its state in memory.
+    pub fn new(homeserver: impl Into<String>) -> Client {
+        Client::with_store(homeserver, InMemoryStore::new())
+    }
+}
+
+impl<S: Storer<String>> Client<S> {
+    /// Creates a client that keeps its state in `store`.
+    pub fn with_store(homeserver: impl Into<String>, store: S) -> Client<S> {
+        Client {
+            homeserver: homeserver.into().trim_end_matches('/').to_string(),
+            http: reqwest::Client::new(),
+            access_token: None,
+            user_id: None,
+            device_id: None,
+            store,
+            transaction_ids: TransactionId::new(),
+            parse_options: ParseOptions::default(),
+        }
+    }
+
+    /// Sets what [`Client::sync`] drops from responses before they are stored.
+    pub fn set_parse_options(&mut self, parse_options: ParseOptions) {
+        self.parse_options = parse_options;
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    pub fn store_mut(&mut self) -> &mut S {
+        &mut self.store
+    }
+
+    pub fn homeserver(&self) -> &str {
+        &self.homeserver
+    }
+
+    pub fn access_token(&self) -> Option<&str> {
+        self.access_token.as_deref()
+    }
+
+    /// The fully-qualified id of the logged-in user.
+    pub fn user_id(&self) -> Option<&str> {
+        self.user_id.as_deref()
+    }
+
+    pub fn device_id(&self) -> Option<&str> {
+        self.device_id.as_deref()
+    }
+
+    /// Logs in with `m.login.password`, keeping the returned access token and
+    /// device id for later requests.
+    pub async fn login(&mut self, user: &str, password: &str) -> Result<(), RespError> {
+        let body = json!({
+            "type": "m.login.password",
+            "identifier": { "type": "m.id.user", "user": user },
+            "password": password,
+        });
+
+        let response = self
+            .send(self.request(Method::POST, "/_matrix/client/v3/login").json(&body))
+            .await?;
+
+        self.access_token = response["access_token"].as_str().map(String::from);
+        self.user_id = response["user_id"].as_str().map(String::from);
+        self.device_id = response["device_id"].as_str().map(String::from);
+        Ok(())
+    }
+
+    /// Uploads `filter` for the logged-in user and saves the returned filter id in
+    /// the store, so later syncs use it.
+    pub async fn upload_filter(&mut self, filter: &Filter) -> Result<String, RespError> {
+        let user_id = self.logged_in_user()?;
+        let path = format!("/_matrix/client/v3/user/{}/filter", path_segment(&user_id));
+
+        let response = self
+            .send(self.request(Method::POST, &path).json(&filter.to_json()))
+            .await?;
+        let filter_id = response["filter_id"].as_str().map(String::from).ok_or_else(|| {
+            RespError::matrix(
+                ErrorKind::Unknown("M_UNKNOWN".to_string()),
+                "response has no filter_id".to_string(),
+            )
+        })?;
+
+        self.store.save_filter_id(user_id, filter_id.clone())?;
+        Ok(filter_id)
+    }
+
+    /// Fetches the changes since `since`, or a full initial sync when it is `None`.
+    ///
+    /// The filter saved by [`Client::upload_filter`] is applied. Joined and left rooms
+    /// are merged into the rooms held by the store along with their `prev_batch`
+    /// tokens, with the logged-in user as their own user, and the new `next_batch`
+    /// token is saved for the logged-in user.
+    pub async fn sync(&mut self, since: Option<String>) -> Result<SyncResponse, RespError> {
+        let mut request = self.request(Method::GET, "/_matrix/client/v3/sync");
+
+        if let Some(since) = &since {
+            request = request.query(&[("since", since)]);
+        }
+
+        if let Some(user_id) = &self.user_id {
+            if let Some(filter_id) = self.store.load_filter_id(user_id.clone())? {
+                request = request.query(&[("filter", filter_id)]);
+            }
+        }
+
+        let body = self.send(request).await?;
+        let response = SyncResponse::from_json_with(&body, &self.parse_options)
+            .map_err(|err| RespError::transport(None, Box::new(err)))?;
+
+        for room in response.joined.values().chain(response.left.values()) {
+            let mut stored = self
+                .store
+                .load_room(room.id.clone())?
+                .unwrap_or_else(|| Room::new(room.id.clone()));
+            if let Some(user_id) = &self.user_id {
+                stored.set_own_user_id(user_id);
+            }
+            stored.merge(room.clone());
+            self.store
+                .save_prev_batch(stored.id.clone(), stored.prev_batch().map(String::from))?;
+            self.store.save_room(stored)?;
+        }
+
+        if let Some(user_id) = &self.user_id {
+            self.store
+                .save_next_batch(user_id.clone(), response.next_batch.clone())?;
+        }
+
+        Ok(response)
+    }
+
+    /// Loads up to `limit` events from before the start of `room_id`'s timeline
+    /// and returns them in chronological order.
+    ///
+    /// Pagination continues from the room's saved `prev_batch` token, which is
+    /// updated to the server's `end` token afterwards. Once the server returns no
+    /// `end` the start of the room has been reached, and later calls return no
+    /// events without making a request. The events are prepended to the room held
+    /// by the store.
+    pub async fn paginate_messages(
+        &mut self,
+        room_id: &str,
+        limit: u32,
+    ) -> Result<Vec<Event<String>>, RespError> {
+        let Some(from) = self.store.load_prev_batch(room_id.to_string())? else {
+            return Ok(Vec::new());
+        };
+
+        let path = format!("/_matrix/client/v3/rooms/{}/messages", path_segment(room_id));
+        let request = self
+            .request(Method::GET, &path)
+            .query(&[("dir", "b"), ("from", &from)])
+            .query(&[("limit", limit)]);
+        let response = self.send(request).await?;
+
+        let parse = |raw: &Value| {
+            let mut event = Event::from_sync_json(raw).ok()?;
+            if event.room_id.is_empty() {
+                event.room_id = room_id.to_string();
+            }
+            Some(Event::from(event))
+        };
+
+        // Going backwards, the chunk starts with the newest event.
+        let mut events: Vec<Event<String>> = response["chunk"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(parse)
+            .collect();
+        events.reverse();
+
+        let end = response["end"].as_str().map(String::from);
+        let mut room = self
+            .store
+            .load_room(room_id.to_string())?
+            .unwrap_or_else(|| Room::new(room_id.to_string()));
+
+        // Lazy-loaded members for the senders of the chunk.
+        for event in response["state"].as_array().into_iter().flatten().filter_map(parse) {
+            if event.etype == STATE_MEMBER && room.resolve_member(&event.state_key).is_none() {
+                room.update_state(event);
+            }
+        }
+
+        room.prepend_timeline(events.clone());
+        room.set_prev_batch(end.clone());
+        self.store.save_prev_batch(room_id.to_string(), end)?;
+        self.store.save_room(room)?;
+
+        Ok(events)
+    }
+
+    /// Sends an `m.room.message` to `room_id` and returns the id of the new event.
+    ///
+    /// A fresh transaction id is generated for the message and reused if the request
+    /// has to be retried (see [`retry_with_txn_id`]), so the server never creates the
+    /// event twice.
+    pub async fn send_message(
+        &self,
+        room_id: &str,
+        content: MessageContent,
+    ) -> Result<String, RespError> {
+        if let MessageContent::Unknown = content {
+            return Err(RespError::matrix(
+                ErrorKind::BadJSON,
+                "unknown message content can't be sent".to_string(),
+            ));
+        }
+
+        let txn_id = self.transaction_ids.generate();
+        let response = retry_with_txn_id(&txn_id, SEND_ATTEMPTS, |txn_id| {
+            let path = format!(
+                "/_matrix/client/v3/rooms/{}/send/{}/{}",
+                path_segment(room_id),
+                EVENT_MESSAGE,
+                path_segment(txn_id),
+            );
+            self.send(self.request(Method::PUT, &path).json(&content))
+        })
+        .await?;
+
+        response["event_id"]
+            .as_str()
+            .map(String::from)
+            .ok_or_else(|| {
+                RespError::matrix(
+                    ErrorKind::Unknown("M_UNKNOWN".to_string()),
+                    "response has no event_id".to_string(),
+                )
+            })
+    }
+
+    fn logged_in_user(&self) -> Result<String, RespError> {
+        self.user_id.clone().ok_or_else(|| {
+            RespError::matrix(
+                ErrorKind::from_errcode("M_MISSING_TOKEN"),
+                "the client isn't logged in".to_string(),
+            )
+        })
+    }
+
+    /// Starts a request to `path`, authenticated if the client has logged in.
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let request = self
+            .http
+            .request(method, format!("{}{}", self.homeserver, path));
+
+        match &self.access_token {
+            Some(access_token) => request.bearer_auth(access_token),
+            None => request,
+        }
+    }
+
+    /// Sends `request` and returns its JSON body. Error responses are parsed with
+    /// [`RespError::from_json`].
+    async fn send(&self, request: RequestBuilder) -> Result<Value, RespError> {
+        let response = request.send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+
+        if !status.is_success() {
+            return Err(RespError::from_json(status.as_u16(), &body));
+        }
+
+        serde_json::from_str(&body)
+            .map_err(|err| RespError::transport(Some(status.as_u16()), Box::new(err)))
+    }
+}
+
+/// Percent-encodes `segment` for use as a single URL path segment.
+fn path_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::Mutex;
+    use std::thread;
+
+    use super::*;
+
+    /// A request received by [`MockServer`].
+    #[derive(Debug)]
+    struct Request {
+        method: String,
+        /// The path including its query string.
+        path: String,
+        authorization: Option<String>,
+        body: String,
+    }
+
+    impl Request {
+        fn json(&self) -> Value {
+            serde_json::from_str(&self.body).unwrap()
+        }
+    }
+
+    /// A homeserver on localhost answering each connection with the next canned
+    /// `(status, body)` response and recording the requests it was sent.
+    struct MockServer {
+        url: String,
+        requests: Arc<Mutex<Vec<Request>>>,
+    }
+
+    impl MockServer {
+        fn start(responses: Vec<(u16, Value)>) -> MockServer {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            let requests = Arc::new(Mutex::new(Vec::new()));
+            let recorded = Arc::clone(&requests);
+
+            thread::spawn(move || {
+                for (status, body) in responses {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    let request = read_request(&mut BufReader::new(&stream));
+                    recorded.lock().unwrap().push(request);
+
+                    let body = match body {
+                        Value::String(body) => body,
+                        body => body.to_string(),
+                    };
+                    let response = format!(
+                        "HTTP/1.1 {} Mock\r\ncontent-type: application/json\r\n\
+                         content-length: {}\r\nconnection: close\r\n\r\n{}",
+                        status,
+                        body.len(),
+                        body
+                    );
+                    stream.write_all(response.as_bytes()).unwrap();
+                }
+            });
+
+            MockServer { url, requests }
+        }
+
+        fn requests(&self) -> std::sync::MutexGuard<'_, Vec<Request>> {
+            self.requests.lock().unwrap()
+        }
+    }
+
+    fn read_request(reader: &mut impl BufRead) -> Request {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let mut parts = line.split_whitespace();
+        let method = parts.next().unwrap().to_string();
+        let path = parts.next().unwrap().to_string();
+
+        let mut content_length = 0;
+        let mut authorization = None;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+
+            let (name, value) = header.split_once(':').unwrap();
+            match name.to_ascii_lowercase().as_str() {
+                "content-length" => content_length = value.trim().parse().unwrap(),
+                "authorization" => authorization = Some(value.trim().to_string()),
+                _ => {}
+            }
+        }
+
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+
+        Request {
+            method,
+            path,
+            authorization,
+            body: String::from_utf8(body).unwrap(),
+        }
+    }
+
+    fn logged_in(server: &MockServer) -> Client {
+        let mut client = Client::new(format!("{}/", server.url));
+        client.access_token = Some("token".to_string());
+        client.user_id = Some("@alice:example.org".to_string());
+        client
+    }
+
+    fn message(id: &str, time_stamp: i64, body: &str) -> Value {
+        json!({
+            "type": "m.room.message",
+            "event_id": id,
+            "sender": "@bob:example.org",
+            "origin_server_ts": time_stamp,
+            "content": { "msgtype": "m.text", "body": body },
+        })
+    }
+
+    #[tokio::test]
+    async fn login_keeps_the_access_token() {
+        let server = MockServer::start(vec![(
+            200,
+            json!({
+                "access_token": "abc123",
+                "user_id": "@alice:example.org",
+                "device_id": "DEVICE",
+            }),
+        )]);
+        let mut client = Client::new(server.url.clone());
+
+        client.login("alice", "hunter2").await.unwrap();
+
+        assert_eq!(client.access_token(), Some("abc123"));
+        assert_eq!(client.user_id(), Some("@alice:example.org"));
+        assert_eq!(client.device_id(), Some("DEVICE"));
+
+        let requests = server.requests();
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].path, "/_matrix/client/v3/login");
+        assert_eq!(requests[0].authorization, None);
+        assert_eq!(
+            requests[0].json(),
+            json!({
+                "type": "m.login.password",
+                "identifier": { "type": "m.id.user", "user": "alice" },
+                "password": "hunter2",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn sync_uses_the_uploaded_filter_and_saves_the_batch() {
+        let server = MockServer::start(vec![
+            (200, json!({ "filter_id": "f1" })),
+            (
+                200,
+                json!({
+                    "next_batch": "s2",
+                    "rooms": { "join": { "!room:example.org": {
+                        "timeline": {
+                            "events": [message("$1", 10, "hello")],
+                            "limited": true,
+                            "prev_batch": "p1",
+                        },
+                    } } },
+                }),
+            ),
+        ]);
+        let mut client = logged_in(&server);
+
+        let filter_id = client.upload_filter(&Filter::default()).await.unwrap();
+        let response = client.sync(Some("s1".to_string())).await.unwrap();
+
+        assert_eq!(filter_id, "f1");
+        assert_eq!(response.next_batch, "s2");
+
+        let requests = server.requests();
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(
+            requests[0].path,
+            "/_matrix/client/v3/user/%40alice%3Aexample.org/filter"
+        );
+        assert_eq!(requests[0].authorization.as_deref(), Some("Bearer token"));
+        assert_eq!(requests[0].json(), Filter::default().to_json());
+        assert_eq!(requests[1].method, "GET");
+        assert_eq!(
+            requests[1].path,
+            "/_matrix/client/v3/sync?since=s1&filter=f1"
+        );
+
+        let store = client.store();
+        let user_id = "@alice:example.org".to_string();
+        let room = store
+            .load_room("!room:example.org".to_string())
+            .unwrap()
+            .unwrap();
+        assert_eq!(room.recent_messages(usize::MAX).len(), 1);
+        assert_eq!(
+            store.load_next_batch(user_id.clone()).unwrap().as_deref(),
+            Some("s2")
+        );
+        assert_eq!(
+            store.load_filter_id(user_id).unwrap().as_deref(),
+            Some("f1")
+        );
+        assert_eq!(
+            store
+                .load_prev_batch("!room:example.org".to_string())
+                .unwrap()
+                .as_deref(),
+            Some("p1")
+        );
+    }
+
+    async fn sync_error(client: &mut Client) -> RespError {
+        match client.sync(None).await {
+            Ok(_) => panic!("the sync succeeded"),
+            Err(err) => err,
+        }
+    }
+
+    #[tokio::test]
+    async fn error_responses_are_mapped() {
+        let server = MockServer::start(vec![
+            (
+                403,
+                json!({ "errcode": "M_FORBIDDEN", "error": "You are not invited" }),
+            ),
+            (
+                404,
+                json!({ "errcode": "M_UNRECOGNIZED", "error": "Unrecognized request" }),
+            ),
+            (502, Value::String("<html>Bad Gateway</html>".to_string())),
+            (200, Value::String("not json".to_string())),
+        ]);
+        let mut client = logged_in(&server);
+
+        let err = sync_error(&mut client).await;
+        assert_eq!(err.kind(), &ErrorKind::Forbidden);
+        assert_eq!(err.status(), Some(403));
+        assert_eq!(err.message(), Some("You are not invited"));
+
+        let err = sync_error(&mut client).await;
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::Unknown("M_UNRECOGNIZED".to_string())
+        );
+        assert_eq!(err.status(), Some(404));
+
+        let err = sync_error(&mut client).await;
+        assert_eq!(err.kind(), &ErrorKind::Unknown("M_UNKNOWN".to_string()));
+        assert_eq!(err.status(), Some(502));
+
+        let err = sync_error(&mut client).await;
+        assert_eq!(err.status(), Some(200));
+        assert!(std::error::Error::source(&err).is_some());
+    }
+
+    #[tokio::test]
+    async fn rate_limited_sends_are_retried_with_the_same_txn_id() {
+        let limited = json!({ "errcode": "M_LIMIT_EXCEEDED", "retry_after_ms": 10 });
+        let server = MockServer::start(vec![
+            (429, limited.clone()),
+            (429, limited.clone()),
+            (200, json!({ "event_id": "$sent" })),
+            (429, limited.clone()),
+            (429, limited.clone()),
+            (429, limited),
+        ]);
+        let client = logged_in(&server);
+        let content: MessageContent =
+            serde_json::from_value(json!({ "msgtype": "m.text", "body": "hello" })).unwrap();
+
+        let event_id = client
+            .send_message("!room:example.org", content.clone())
+            .await
+            .unwrap();
+        assert_eq!(event_id, "$sent");
+
+        let err = client
+            .send_message("!room:example.org", content)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::LimitExceeded {
+                retry_after: Some(Duration::from_millis(10))
+            }
+        );
+        assert_eq!(err.retry_after(), Some(Duration::from_millis(10)));
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3 + SEND_ATTEMPTS);
+        assert!(requests.iter().all(|request| request.method == "PUT"));
+        assert!(requests[0]
+            .path
+            .starts_with("/_matrix/client/v3/rooms/%21room%3Aexample.org/send/m.room.message/"));
+        assert_eq!(requests[0].path, requests[1].path);
+        assert_eq!(requests[0].path, requests[2].path);
+        assert_ne!(requests[2].path, requests[3].path);
+        assert_eq!(
+            requests[0].json(),
+            json!({ "msgtype": "m.text", "body": "hello" })
+        );
+    }
+
+    #[tokio::test]
+    async fn paginate_messages_prepends_older_events() {
+        let server = MockServer::start(vec![
+            (
+                200,
+                json!({
+                    "start": "t1",
+                    "end": "t2",
+                    "chunk": [message("$2", 20, "second"), message("$1", 10, "first")],
+                    "state": [{
+                        "type": "m.room.member",
+                        "state_key": "@bob:example.org",
+                        "event_id": "$member",
+                        "sender": "@bob:example.org",
+                        "origin_server_ts": 5,
+                        "content": { "membership": "join", "displayname": "Bob" },
+                    }],
+                }),
+            ),
+            (
+                200,
+                json!({ "start": "t2", "chunk": [message("$0", 5, "zeroth")] }),
+            ),
+        ]);
+        let mut client = logged_in(&server);
+        let room_id = "!room:example.org";
+
+        let mut room = Room::new(room_id.to_string());
+        room.append_timeline(Event::from(
+            Event::from_sync_json(&message("$3", 30, "third")).unwrap(),
+        ));
+        room.set_prev_batch(Some("t1".to_string()));
+        client.store_mut().save_room(room).unwrap();
+        client
+            .store_mut()
+            .save_prev_batch(room_id.to_string(), Some("t1".to_string()))
+            .unwrap();
+
+        let events = client.paginate_messages(room_id, 2).await.unwrap();
+        let ids: Vec<&str> = events.iter().map(|event| event.id.as_str()).collect();
+        assert_eq!(ids, ["$1", "$2"]);
+        assert_eq!(events[0].room_id, room_id);
+
+        let room = client
+            .store()
+            .load_room(room_id.to_string())
+            .unwrap()
+            .unwrap();
+        let ids: Vec<&str> = room
+            .recent_messages(usize::MAX)
+            .iter()
+            .map(|event| event.id.as_str())
+            .collect();
+        assert_eq!(ids, ["$1", "$2", "$3"]);
+        assert_eq!(room.prev_batch(), Some("t2"));
+        assert_eq!(
+            room.resolve_member("@bob:example.org")
+                .unwrap()
+                .displayname
+                .as_deref(),
+            Some("Bob")
+        );
+
+        // Without an `end` the start of the room has been reached.
+        let events = client.paginate_messages(room_id, 2).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert!(client
+            .paginate_messages(room_id, 2)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            client.store().load_prev_batch(room_id.to_string()).unwrap(),
+            None
+        );
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].method, "GET");
+        assert_eq!(
+            requests[0].path,
+            "/_matrix/client/v3/rooms/%21room%3Aexample.org/messages?dir=b&from=t1&limit=2"
+        );
+        assert_eq!(
+            requests[1].path,
+            "/_matrix/client/v3/rooms/%21room%3Aexample.org/messages?dir=b&from=t2&limit=2"
+        );
+    }
+
+
+    #[tokio::test]
+    async fn send_message_returns_the_event_id() {
+        let server = MockServer::start(vec![(200, json!({ "event_id": "$sent" }))]);
+        let client = logged_in(&server);
+        let content: MessageContent =
+            serde_json::from_value(json!({ "msgtype": "m.text", "body": "hello" })).unwrap();
+
+        let event_id = client
+            .send_message("!room:example.org", content)
+            .await
+            .unwrap();
+        assert_eq!(event_id, "$sent");
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "PUT");
+        assert_eq!(requests[0].authorization.as_deref(), Some("Bearer token"));
+        assert!(requests[0]
+            .path
+            .starts_with("/_matrix/client/v3/rooms/%21room%3Aexample.org/send/m.room.message/"));
+    }
+
+
+    #[test]
+    fn transaction_ids_are_unique() {
+        let ids = TransactionId::new();
+        let clone = ids.clone();
+        let other_device = TransactionId::new();
+
+        let generated = [ids.generate(), clone.generate(), ids.generate()];
+        assert_ne!(generated[0], generated[1]);
+        assert_ne!(generated[1], generated[2]);
+        assert_ne!(generated[0], generated[2]);
+        assert_ne!(other_device.generate(), generated[0]);
+    }
+
+    #[tokio::test]
+    async fn retries_reuse_the_txn_id_and_wait_for_retry_after() {
+        let started = std::time::Instant::now();
+        let txn_ids = Mutex::new(Vec::new());
+
+        let response = retry_with_txn_id("txn", SEND_ATTEMPTS, |txn_id| {
+            let mut txn_ids = txn_ids.lock().unwrap();
+            txn_ids.push(txn_id.to_string());
+            let attempt = txn_ids.len();
+            async move {
+                if attempt < SEND_ATTEMPTS {
+                    Err(RespError::from_json(
+                        429,
+                        r#"{"errcode": "M_LIMIT_EXCEEDED", "retry_after_ms": 20}"#,
+                    ))
+                } else {
+                    Ok(attempt)
+                }
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(response, SEND_ATTEMPTS);
+        assert_eq!(txn_ids.into_inner().unwrap(), vec!["txn"; SEND_ATTEMPTS]);
+        assert!(started.elapsed() >= Duration::from_millis(20 * (SEND_ATTEMPTS as u64 - 1)));
+    }
+
+    #[tokio::test]
+    async fn other_errors_are_not_retried() {
+        let mut attempts = 0;
+
+        let err = retry_with_txn_id("txn", SEND_ATTEMPTS, |_| {
+            attempts += 1;
+            async { Err::<(), _>(RespError::from_json(403, r#"{"errcode": "M_FORBIDDEN"}"#)) }
+        })
+        .await
+        .unwrap_err();
+
+        assert_eq!(err.kind(), &ErrorKind::Forbidden);
+        assert_eq!(attempts, 1);
+    }
+}
@@ -0,0 +1,107 @@
+//! Event types, content keys and other strings defined by the Matrix spec.
+//!
+//! Matching on these instead of string literals turns a typo into a compile error.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use matrux::consts::{EVENT_MESSAGE, KEY_BODY, MSGTYPE_TEXT, STATE_MEMBER};
+//!
+//! assert_eq!(EVENT_MESSAGE, "m.room.message");
+//! assert_eq!(STATE_MEMBER, "m.room.member");
+//! assert_eq!(KEY_BODY, "body");
+//! assert_eq!(MSGTYPE_TEXT, "m.text");
+//! ```
+
+// Message and ephemeral event types.
+pub const EVENT_MESSAGE: &str = "m.room.message";
+pub const EVENT_STICKER: &str = "m.sticker";
+pub const EVENT_REACTION: &str = "m.reaction";
+pub const EVENT_REDACTION: &str = "m.room.redaction";
+pub const EVENT_PRESENCE: &str = "m.presence";
+pub const EVENT_RECEIPT: &str = "m.receipt";
+pub const EVENT_TYPING: &str = "m.typing";
+
+// State event types.
+pub const STATE_MEMBER: &str = "m.room.member";
+pub const STATE_CREATE: &str = "m.room.create";
+pub const STATE_NAME: &str = "m.room.name";
+pub const STATE_TOPIC: &str = "m.room.topic";
+pub const STATE_AVATAR: &str = "m.room.avatar";
+pub const STATE_CANONICAL_ALIAS: &str = "m.room.canonical_alias";
+pub const STATE_ALIASES: &str = "m.room.aliases";
+pub const STATE_JOIN_RULES: &str = "m.room.join_rules";
+pub const STATE_POWER_LEVELS: &str = "m.room.power_levels";
+pub const STATE_HISTORY_VISIBILITY: &str = "m.room.history_visibility";
+pub const STATE_GUEST_ACCESS: &str = "m.room.guest_access";
+pub const STATE_ENCRYPTION: &str = "m.room.encryption";
+pub const STATE_TOMBSTONE: &str = "m.room.tombstone";
+
+// Account data types.
+pub const ACCOUNT_DATA_TAG: &str = "m.tag";
+pub const ACCOUNT_DATA_DIRECT: &str = "m.direct";
+pub const ACCOUNT_DATA_FULLY_READ: &str = "m.fully_read";
+pub const ACCOUNT_DATA_IGNORED_USER_LIST: &str = "m.ignored_user_list";
+
+// Room tags.
+pub const TAG_FAVOURITE: &str = "m.favourite";
+pub const TAG_LOW_PRIORITY: &str = "m.lowpriority";
+
+// Content keys.
+pub const KEY_BODY: &str = "body";
+pub const KEY_MSGTYPE: &str = "msgtype";
+pub const KEY_FORMAT: &str = "format";
+pub const KEY_FORMATTED_BODY: &str = "formatted_body";
+pub const KEY_MEMBERSHIP: &str = "membership";
+pub const KEY_DISPLAYNAME: &str = "displayname";
+pub const KEY_RELATES_TO: &str = "m.relates_to";
+pub const KEY_IN_REPLY_TO: &str = "m.in_reply_to";
+pub const KEY_NEW_CONTENT: &str = "m.new_content";
+pub const KEY_MENTIONS: &str = "m.mentions";
+
+// Message types.
+pub const MSGTYPE_TEXT: &str = "m.text";
+pub const MSGTYPE_NOTICE: &str = "m.notice";
+pub const MSGTYPE_EMOTE: &str = "m.emote";
+pub const MSGTYPE_IMAGE: &str = "m.image";
+pub const MSGTYPE_VIDEO: &str = "m.video";
+pub const MSGTYPE_AUDIO: &str = "m.audio";
+pub const MSGTYPE_FILE: &str = "m.file";
+pub const MSGTYPE_LOCATION: &str = "m.location";
+
+/// The only `format` defined for `formatted_body`.
+pub const FORMAT_HTML: &str = "org.matrix.custom.html";
+
+// Relation types.
+pub const REL_ANNOTATION: &str = "m.annotation";
+pub const REL_REPLACE: &str = "m.replace";
+pub const REL_THREAD: &str = "m.thread";
+
+// Membership states.
+pub const MEMBERSHIP_JOIN: &str = "join";
+pub const MEMBERSHIP_INVITE: &str = "invite";
+pub const MEMBERSHIP_LEAVE: &str = "leave";
+pub const MEMBERSHIP_BAN: &str = "ban";
+pub const MEMBERSHIP_KNOCK: &str = "knock";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constants_match_the_spec() {
+        assert_eq!(EVENT_MESSAGE, "m.room.message");
+        assert_eq!(EVENT_REDACTION, "m.room.redaction");
+        assert_eq!(STATE_MEMBER, "m.room.member");
+        assert_eq!(STATE_POWER_LEVELS, "m.room.power_levels");
+        assert_eq!(ACCOUNT_DATA_IGNORED_USER_LIST, "m.ignored_user_list");
+        assert_eq!(TAG_LOW_PRIORITY, "m.lowpriority");
+        assert_eq!(KEY_BODY, "body");
+        assert_eq!(KEY_MSGTYPE, "msgtype");
+        assert_eq!(KEY_RELATES_TO, "m.relates_to");
+        assert_eq!(MSGTYPE_TEXT, "m.text");
+        assert_eq!(FORMAT_HTML, "org.matrix.custom.html");
+        assert_eq!(REL_REPLACE, "m.replace");
+        assert_eq!(MEMBERSHIP_JOIN, "join");
+    }
+}
@@ -1,14 +1,33 @@
-use std::collections::HashMap;
-use std::time::Duration;
-use regex::Regex;
-use html_escape::decode_html_entities;
-use lazy_static::lazy_static;
+use html_escape::{decode_html_entities, encode_double_quoted_attribute, encode_text};
+use pulldown_cmark::{html, Options, Parser};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-lazy_static! {
-  static ref HTML_REGEX: Regex = Regex::new(r#"<[^<]+?>"#).unwrap();
-}
+use crate::consts::{
+    ACCOUNT_DATA_DIRECT, ACCOUNT_DATA_IGNORED_USER_LIST, ACCOUNT_DATA_TAG, EVENT_PRESENCE,
+    EVENT_STICKER, FORMAT_HTML, KEY_BODY, KEY_DISPLAYNAME, KEY_FORMAT, KEY_FORMATTED_BODY,
+    KEY_IN_REPLY_TO, KEY_MEMBERSHIP, KEY_MENTIONS, KEY_MSGTYPE, KEY_NEW_CONTENT, KEY_RELATES_TO,
+    MSGTYPE_AUDIO, MSGTYPE_EMOTE, MSGTYPE_FILE, MSGTYPE_IMAGE, MSGTYPE_LOCATION, MSGTYPE_NOTICE,
+    MSGTYPE_TEXT, MSGTYPE_VIDEO, REL_REPLACE, REL_THREAD, STATE_ALIASES, STATE_CREATE,
+    STATE_HISTORY_VISIBILITY, STATE_JOIN_RULES, STATE_MEMBER, STATE_POWER_LEVELS, TAG_FAVOURITE,
+    TAG_LOW_PRIORITY,
+};
+use crate::html::{sanitize_html, strip_html_into};
+use crate::ids::{RoomId, UserId};
+use crate::store::{StoreError, Storer};
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(bound(deserialize = "T: Deserialize<'de>"))
+)]
 /// The `Event` struct represents a single event in the system.
 ///
 /// It contains various fields that describe the event, including the state key, sender, event type, timestamp, and more.
@@ -16,6 +35,7 @@ lazy_static! {
 /// # Fields
 ///
 /// * `state_key`: The state key associated with the event.
+/// * `has_state_key`: Whether the event has a state key, i.e. is a state event.
 /// * `sender`: The sender of the event.
 /// * `etype`: The type of the event.
 /// * `time_stamp`: The timestamp of the event.
@@ -37,9 +57,11 @@ lazy_static! {
 ///
 /// ```rust
 /// use std::collections::HashMap;
+/// use matrux::events::Event;
 ///
-/// let event = Event {
+/// let event: Event<String> = Event {
 ///     state_key: "state_key".to_string(),
+///     has_state_key: true,
 ///     sender: "sender".to_string(),
 ///     etype: "etype".to_string(),
 ///     time_stamp: 1234567890,
@@ -53,16 +75,46 @@ lazy_static! {
 /// };
 /// ```
 pub struct Event<T: Clone> {
+    #[cfg_attr(feature = "serde", serde(default))]
     pub state_key: String,
+    /// Set when the event has a `state_key`, even an empty one, which is what
+    /// makes it a state event.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "wire::is_false")
+    )]
+    pub has_state_key: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub sender: String,
+    #[cfg_attr(feature = "serde", serde(rename = "type"))]
     pub etype: String,
+    #[cfg_attr(feature = "serde", serde(rename = "origin_server_ts", default))]
     pub time_stamp: i64,
+    #[cfg_attr(feature = "serde", serde(rename = "event_id", default))]
     pub id: String,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "String::is_empty")
+    )]
     pub room_id: String,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "String::is_empty")
+    )]
     pub redacts: String,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "HashMap::is_empty")
+    )]
     pub unsigned: HashMap<String, T>,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub content: HashMap<String, T>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "HashMap::is_empty")
+    )]
     pub prev_content: HashMap<String, T>,
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub is_init: bool,
 }
 
@@ -70,8 +122,11 @@ impl<T: Clone + 'static> Event<T>
 where
     String: for<'a> From<&'a T>,
 {
+    /// Creates an event. `state_key` is `None` for message events and `Some` for
+    /// state events, including an empty key like that of `m.room.name`.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        state_key: String,
+        state_key: Option<String>,
         sender: String,
         etype: String,
         time_stamp: i64,
@@ -84,7 +139,8 @@ where
         is_init: bool,
     ) -> Self {
         Self {
-            state_key,
+            has_state_key: state_key.is_some(),
+            state_key: state_key.unwrap_or_default(),
             sender,
             etype,
             time_stamp,
@@ -94,131 +150,3116 @@ where
             unsigned,
             content,
             prev_content,
-            is_init
+            is_init,
+        }
+    }
+
+    /// Returns `time_stamp` as a [`SystemTime`]. Zero or negative timestamps are
+    /// clamped to the Unix epoch.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::time::{Duration, UNIX_EPOCH};
+    /// use matrux::events::Event;
+    ///
+    /// let mut event: Event<String> = Event::default();
+    /// event.set_timestamp(UNIX_EPOCH + Duration::from_millis(1_718_000_000_123));
+    ///
+    /// assert_eq!(event.time_stamp, 1_718_000_000_123);
+    /// assert_eq!(event.timestamp(), UNIX_EPOCH + Duration::from_millis(1_718_000_000_123));
+    /// ```
+    pub fn timestamp(&self) -> SystemTime {
+        let millis = u64::try_from(self.time_stamp).unwrap_or(0);
+        UNIX_EPOCH + Duration::from_millis(millis)
+    }
+
+    /// Sets `time_stamp` from a [`SystemTime`], clamping times before the epoch to zero.
+    pub fn set_timestamp(&mut self, time: SystemTime) {
+        let millis = time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since_epoch| since_epoch.as_millis());
+        self.time_stamp = i64::try_from(millis).unwrap_or(i64::MAX);
+    }
+
+    /// Decodes a nested content field, which is held as its JSON encoding.
+    pub(crate) fn content_json(&self, key: &str) -> Option<serde_json::Value> {
+        let raw = String::from(self.content.get(key)?);
+        serde_json::from_str(&raw).ok()
+    }
+
+    /// Content as a JSON object. Fields the spec nests are decoded from their JSON
+    /// encoding; all others stay strings, even ones that look like JSON.
+    pub(crate) fn content_value(&self) -> Value {
+        Value::Object(
+            self.content
+                .iter()
+                .map(|(key, raw)| (key.clone(), decode_content_value(key, &String::from(raw))))
+                .collect(),
+        )
+    }
+
+    /// Parses the content of an `m.room.member` event. Returns `None` for other event
+    /// types or when `membership` is missing.
+    pub fn member_content(&self) -> Option<MemberEventContent> {
+        if self.etype != STATE_MEMBER {
+            return None;
         }
+
+        MemberEventContent::parse(&self.content_value())
+    }
+
+    pub fn body(&self) -> Option<&T> {
+        self.content.get(KEY_BODY)
     }
 
-    fn body(&self) -> Option<&T> {
-        self.content.get("body")
+    pub fn message_type(&self) -> Option<&T> {
+        self.content.get(KEY_MSGTYPE)
+    }
+
+    /// Returns the `body` content field as an owned string.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::collections::HashMap;
+    /// use matrux::events::Event;
+    ///
+    /// let mut content = HashMap::new();
+    /// content.insert("body".to_string(), "Hello, world!".to_string());
+    ///
+    /// let event: Event<String> = Event {
+    ///     etype: "m.room.message".to_string(),
+    ///     content,
+    ///     ..Default::default()
+    /// };
+    ///
+    /// assert_eq!(event.body_str(), Some("Hello, world!".to_string()));
+    /// ```
+    pub fn body_str(&self) -> Option<String> {
+        self.body().map(String::from)
+    }
+
+    /// Returns the `msgtype` content field as an owned string.
+    pub fn msgtype_str(&self) -> Option<String> {
+        self.message_type().map(String::from)
+    }
+
+    /// Checks the event against the spec's size limits before it is sent: at most
+    /// [`MAX_EVENT_SIZE`] bytes as JSON, a `sender` that is a user id, a `room_id`
+    /// that is a room id, and a `type` and `state_key` of at most 255 bytes. The
+    /// `state_key` of an `m.room.member` event must be a user id. Empty ids are
+    /// allowed, since the server fills them in.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::collections::HashMap;
+    /// use matrux::events::{Event, ValidationError};
+    ///
+    /// let message = |body: String| {
+    ///     let mut content = HashMap::new();
+    ///     content.insert("msgtype".to_string(), "m.text".to_string());
+    ///     content.insert("body".to_string(), body);
+    ///     Event {
+    ///         etype: "m.room.message".to_string(),
+    ///         sender: "@alice:example.org".to_string(),
+    ///         room_id: "!room:example.org".to_string(),
+    ///         content,
+    ///         ..Default::default()
+    ///     }
+    /// };
+    ///
+    /// assert_eq!(message("x".repeat(65_000)).validate_size(), Ok(()));
+    /// assert!(matches!(
+    ///     message("x".repeat(65_536)).validate_size(),
+    ///     Err(ValidationError::TooLarge(_))
+    /// ));
+    /// ```
+    pub fn validate_size(&self) -> Result<(), ValidationError> {
+        if !self.sender.is_empty() {
+            UserId::parse(&self.sender).map_err(|err| ValidationError::InvalidId("sender", err))?;
+        }
+
+        if !self.room_id.is_empty() {
+            RoomId::parse(&self.room_id)
+                .map_err(|err| ValidationError::InvalidId("room_id", err))?;
+        }
+
+        if self.etype.len() > MAX_KEY_LENGTH {
+            return Err(ValidationError::FieldTooLong("type"));
+        }
+
+        if self.state_key.len() > MAX_KEY_LENGTH {
+            return Err(ValidationError::FieldTooLong("state_key"));
+        }
+
+        if self.etype == STATE_MEMBER {
+            UserId::parse(&self.state_key)
+                .map_err(|err| ValidationError::InvalidId("state_key", err))?;
+        }
+
+        let size = self.to_wire_json().to_string().len();
+
+        if size > MAX_EVENT_SIZE {
+            return Err(ValidationError::TooLarge(size));
+        }
+
+        Ok(())
     }
 
+    /// The event as the client-server API sends it, with nested content decoded.
+    fn to_wire_json(&self) -> Value {
+        let decode = |map: &HashMap<String, T>| -> serde_json::Map<String, Value> {
+            map.iter()
+                .map(|(key, raw)| (key.clone(), decode_content_value(key, &String::from(raw))))
+                .collect()
+        };
+
+        let mut event = serde_json::Map::new();
+        event.insert("type".to_string(), json!(self.etype));
+        event.insert("sender".to_string(), json!(self.sender));
+        event.insert("origin_server_ts".to_string(), json!(self.time_stamp));
+        event.insert("content".to_string(), Value::Object(decode(&self.content)));
+
+        for (key, value) in [
+            ("event_id", &self.id),
+            ("room_id", &self.room_id),
+            ("redacts", &self.redacts),
+        ] {
+            if !value.is_empty() {
+                event.insert(key.to_string(), json!(value));
+            }
+        }
+
+        if self.is_state_event() {
+            event.insert("state_key".to_string(), json!(self.state_key));
+        }
+
+        if !self.unsigned.is_empty() {
+            event.insert(
+                "unsigned".to_string(),
+                Value::Object(decode(&self.unsigned)),
+            );
+        }
+
+        Value::Object(event)
+    }
 
-    fn message_type(&self) -> Option<&T> {
-      self.content.get("msgtype")
+    /// Overlays `other` onto `unsigned`. Keys in `other` replace ours, and keys it
+    /// doesn't have, such as a local `transaction_id` or `redacted_because`, are
+    /// kept.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::collections::HashMap;
+    /// use matrux::events::Event;
+    ///
+    /// let mut event: Event<String> = Event::default();
+    /// event.unsigned.insert("transaction_id".to_string(), "1.abc".to_string());
+    /// event.unsigned.insert("age".to_string(), "100".to_string());
+    ///
+    /// let mut newer = HashMap::new();
+    /// newer.insert("age".to_string(), "250".to_string());
+    /// event.merge_unsigned(&newer);
+    ///
+    /// assert_eq!(event.unsigned["transaction_id"], "1.abc");
+    /// assert_eq!(event.unsigned["age"], "250");
+    /// ```
+    pub fn merge_unsigned(&mut self, other: &HashMap<String, T>) {
+        self.unsigned.extend(
+            other
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone())),
+        );
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct TextMessage {
-  pub message_type: String,
-  pub body: String,
-  pub formatted_body: String,
-  pub format: String
+impl<T: Clone> Event<T> {
+    /// Whether this is a state event. An empty `state_key` still counts, as it does
+    /// for `m.room.name` and most other room state.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use matrux::events::Event;
+    /// use serde_json::json;
+    ///
+    /// let name = Event::from_sync_json(&json!({
+    ///     "type": "m.room.name",
+    ///     "state_key": "",
+    ///     "event_id": "$name",
+    ///     "sender": "@alice:example.org",
+    ///     "origin_server_ts": 1_718_000_000_000_i64,
+    ///     "content": { "name": "Book club" }
+    /// }))
+    /// .unwrap();
+    ///
+    /// let message = Event::from_sync_json(&json!({
+    ///     "type": "m.room.message",
+    ///     "event_id": "$message",
+    ///     "sender": "@alice:example.org",
+    ///     "origin_server_ts": 1_718_000_000_000_i64,
+    ///     "content": { "msgtype": "m.text", "body": "Hello" }
+    /// }))
+    /// .unwrap();
+    ///
+    /// assert!(name.is_state_event());
+    /// assert!(!message.is_state_event());
+    /// ```
+    pub fn is_state_event(&self) -> bool {
+        // Events stored before `has_state_key` existed only have the key itself.
+        self.has_state_key || !self.state_key.is_empty()
+    }
+
+    /// Whether the event has been redacted, which the server marks with
+    /// `redacted_because` in `unsigned`.
+    pub fn is_redacted(&self) -> bool {
+        self.unsigned.contains_key("redacted_because")
+    }
+
+    /// Strips the content down to the keys the redaction algorithm preserves for this
+    /// event type and records the id of the redaction in `unsigned["redacted_because"]`.
+    pub(crate) fn redact(&mut self, redaction_id: &str)
+    where
+        T: From<String>,
+    {
+        let preserved: &[&str] = match self.etype.as_str() {
+            STATE_MEMBER => &[KEY_MEMBERSHIP],
+            STATE_CREATE => &["creator"],
+            STATE_JOIN_RULES => &["join_rule"],
+            STATE_POWER_LEVELS => &[
+                "ban",
+                "events",
+                "events_default",
+                "kick",
+                "redact",
+                "state_default",
+                "users",
+                "users_default",
+            ],
+            STATE_ALIASES => &["aliases"],
+            STATE_HISTORY_VISIBILITY => &["history_visibility"],
+            _ => &[],
+        };
+
+        self.content
+            .retain(|key, _| preserved.contains(&key.as_str()));
+        self.unsigned.insert(
+            "redacted_because".to_string(),
+            T::from(redaction_id.to_string()),
+        );
+    }
+
+    /// Orders events chronologically by `time_stamp`, with ties broken by `id`, so
+    /// sorting a timeline with it is deterministic. `==` compares every field
+    /// instead, so an edited or redacted copy of an event differs from the original
+    /// even though both sort to the same place.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use matrux::events::{Event, EventBuilder};
+    ///
+    /// let at = |time_stamp: i64, id: &str| -> Event<String> {
+    ///     EventBuilder::new().time_stamp(time_stamp).id(id.to_string()).build()
+    /// };
+    ///
+    /// let mut timeline = vec![at(20, "$b"), at(10, "$c"), at(20, "$a")];
+    /// timeline.sort_by(Event::timeline_order);
+    ///
+    /// let ids: Vec<&str> = timeline.iter().map(|event| event.id.as_str()).collect();
+    /// assert_eq!(ids, ["$c", "$a", "$b"]);
+    /// ```
+    pub fn timeline_order(&self, other: &Self) -> Ordering {
+        self.time_stamp
+            .cmp(&other.time_stamp)
+            .then_with(|| self.id.cmp(&other.id))
+    }
+
+    /// Whether both events say the same thing: the same `etype`, `state_key`,
+    /// `sender` and `content`. Fields the server adds or updates, such as the
+    /// timestamp, event id and `unsigned`, are ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::collections::HashMap;
+    /// use matrux::events::EventBuilder;
+    ///
+    /// let mut content = HashMap::new();
+    /// content.insert("body".to_string(), "Hello".to_string());
+    ///
+    /// let with_age = |age: &str| {
+    ///     let mut unsigned = HashMap::new();
+    ///     unsigned.insert("age".to_string(), age.to_string());
+    ///     EventBuilder::new()
+    ///         .etype("m.room.message".to_string())
+    ///         .sender("@alice:example.org".to_string())
+    ///         .content(content.clone())
+    ///         .unsigned(unsigned)
+    ///         .build()
+    /// };
+    ///
+    /// assert!(with_age("100").content_eq(&with_age("2500")));
+    /// ```
+    pub fn content_eq(&self, other: &Event<T>) -> bool
+    where
+        T: PartialEq,
+    {
+        self.etype == other.etype
+            && self.state_key == other.state_key
+            && self.is_state_event() == other.is_state_event()
+            && self.sender == other.sender
+            && self.content == other.content
+    }
+
+    /// Converts the event to another value type by applying `f` to every value in
+    /// `content`, `prev_content` and `unsigned`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use matrux::events::Event;
+    /// use serde_json::{json, Value};
+    ///
+    /// let event = Event::from_sync_json(&json!({
+    ///     "type": "m.room.message",
+    ///     "event_id": "$message",
+    ///     "sender": "@alice:example.org",
+    ///     "origin_server_ts": 1_718_000_000_000_i64,
+    ///     "content": { "body": "Hello", "info": { "size": 3 } }
+    /// }))
+    /// .unwrap();
+    ///
+    /// let event: Event<String> = event.map_content(|value| match value {
+    ///     Value::String(value) => value,
+    ///     value => value.to_string(),
+    /// });
+    /// assert_eq!(event.content["body"], "Hello");
+    /// assert_eq!(event.content["info"], r#"{"size":3}"#);
+    /// assert_eq!(event.id, "$message");
+    /// ```
+    pub fn map_content<U: Clone>(self, f: impl Fn(T) -> U) -> Event<U> {
+        let map = |values: HashMap<String, T>| -> HashMap<String, U> {
+            values
+                .into_iter()
+                .map(|(key, value)| (key, f(value)))
+                .collect()
+        };
+
+        Event {
+            state_key: self.state_key,
+            has_state_key: self.has_state_key,
+            sender: self.sender,
+            etype: self.etype,
+            time_stamp: self.time_stamp,
+            id: self.id,
+            room_id: self.room_id,
+            redacts: self.redacts,
+            unsigned: map(self.unsigned),
+            content: map(self.content),
+            prev_content: map(self.prev_content),
+            is_init: self.is_init,
+        }
+    }
 }
 
+/// Builds an [`Event`] field by field, leaving anything unset empty.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::collections::HashMap;
+/// use matrux::events::EventBuilder;
+///
+/// let mut content = HashMap::new();
+/// content.insert("body".to_string(), "hi".to_string());
+///
+/// let event = EventBuilder::new()
+///     .etype("m.room.message".to_string())
+///     .sender("@alice:example.org".to_string())
+///     .content(content)
+///     .build();
+///
+/// assert_eq!(event.body_str(), Some("hi".to_string()));
+/// assert!(!event.is_init);
+/// ```
 #[derive(Debug, Clone)]
-pub struct ThumbnailInfo {
-  pub height: u64,
-  pub width: u64,
-  pub mime_type: String,
-  pub size: u64
+pub struct EventBuilder<T: Clone> {
+    event: Event<T>,
 }
-#[derive(Debug, Clone)]
-pub struct ImageInfo {
-  pub height: u64,
-  pub width: u64,
-  pub mime_type: String,
-  pub size: u64,
-  pub thumbnail_info: ThumbnailInfo,
-  pub thumbnail_url: String
+
+impl<T: Clone> EventBuilder<T> {
+    pub fn new() -> Self {
+        Self {
+            event: Event {
+                state_key: String::new(),
+                has_state_key: false,
+                sender: String::new(),
+                etype: String::new(),
+                time_stamp: 0,
+                id: String::new(),
+                room_id: String::new(),
+                redacts: String::new(),
+                unsigned: HashMap::new(),
+                content: HashMap::new(),
+                prev_content: HashMap::new(),
+                is_init: false,
+            },
+        }
+    }
+
+    pub fn state_key(mut self, state_key: String) -> Self {
+        self.event.state_key = state_key;
+        self.event.has_state_key = true;
+        self
+    }
+
+    pub fn sender(mut self, sender: String) -> Self {
+        self.event.sender = sender;
+        self
+    }
+
+    pub fn etype(mut self, etype: String) -> Self {
+        self.event.etype = etype;
+        self
+    }
+
+    pub fn time_stamp(mut self, time_stamp: i64) -> Self {
+        self.event.time_stamp = time_stamp;
+        self
+    }
+
+    pub fn id(mut self, id: String) -> Self {
+        self.event.id = id;
+        self
+    }
+
+    pub fn room_id(mut self, room_id: String) -> Self {
+        self.event.room_id = room_id;
+        self
+    }
+
+    pub fn redacts(mut self, redacts: String) -> Self {
+        self.event.redacts = redacts;
+        self
+    }
+
+    pub fn unsigned(mut self, unsigned: HashMap<String, T>) -> Self {
+        self.event.unsigned = unsigned;
+        self
+    }
+
+    pub fn content(mut self, content: HashMap<String, T>) -> Self {
+        self.event.content = content;
+        self
+    }
+
+    pub fn prev_content(mut self, prev_content: HashMap<String, T>) -> Self {
+        self.event.prev_content = prev_content;
+        self
+    }
+
+    pub fn build(self) -> Event<T> {
+        self.event
+    }
 }
 
-#[derive(Debug, Clone)]
-pub struct VideoInfo {
-  pub height: u64,
-  pub width: u64,
-  pub mime_type: String,
-  pub size: u64,
-  pub thumbnail_info: ThumbnailInfo,
-  pub thumbnail_url: String,
-  pub duration: Duration,
+impl<T: Clone> Default for EventBuilder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-#[derive(Debug, Clone)]
-pub struct VideoMessage {
-  pub message_type: String,
-  pub body: String,
-  pub url: String,
-  pub info: VideoInfo
+/// An error raised when a Matrix JSON payload or string doesn't have the expected
+/// shape.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The payload is not a JSON object.
+    NotAnObject,
+    /// A required field is absent.
+    MissingField(&'static str),
+    /// A field is present but has the wrong type.
+    InvalidField(&'static str),
+    /// A URI, such as an `mxc://` URI, is malformed or has the wrong scheme.
+    InvalidUri(String),
+    /// A Matrix identifier such as a user or room id is malformed.
+    InvalidId(String),
+    /// A server name isn't a valid hostname or IP literal with an optional port.
+    InvalidServerName(String),
 }
 
-#[derive(Debug, Clone)]
-pub struct ImageMessage {
-  pub message_type: String,
-  pub body: String,
-  pub url: String,
-  pub info: ImageInfo
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::NotAnObject => write!(f, "expected a JSON object"),
+            ParseError::MissingField(field) => write!(f, "missing required field `{}`", field),
+            ParseError::InvalidField(field) => write!(f, "field `{}` has an invalid type", field),
+            ParseError::InvalidUri(uri) => write!(f, "invalid URI `{}`", uri),
+            ParseError::InvalidId(id) => write!(f, "invalid identifier `{}`", id),
+            ParseError::InvalidServerName(name) => write!(f, "invalid server name `{}`", name),
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
-pub struct HTMLMessage {
-  pub message_type: String,
-  pub body: String,
-  pub formatted_body: String,
-  pub format: String
+impl Error for ParseError {}
+
+/// The largest event the spec allows, in bytes of JSON.
+pub const MAX_EVENT_SIZE: usize = 65_536;
+
+/// The longest `type` or `state_key` the spec allows, in bytes.
+const MAX_KEY_LENGTH: usize = 255;
+
+/// An error raised when an event breaks the spec's limits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The event is larger than [`MAX_EVENT_SIZE`]; holds its size in bytes.
+    TooLarge(usize),
+    /// A field is longer than the spec allows.
+    FieldTooLong(&'static str),
+    /// A field that must hold a Matrix id doesn't.
+    InvalidId(&'static str, ParseError),
 }
 
-#[derive(Debug, Clone)]
-pub struct FileInfo {
-  mime_type: String,
-  size: u64,
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::TooLarge(size) => write!(
+                f,
+                "event is {} bytes, over the {} byte limit",
+                size, MAX_EVENT_SIZE
+            ),
+            ValidationError::FieldTooLong(field) => write!(f, "field `{}` is too long", field),
+            ValidationError::InvalidId(field, err) => write!(f, "field `{}`: {}", field, err),
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
-pub struct FileMessage {
-  message_type: String,
-  body: String,
-  url: String,
-  file_name: String,
-  info: FileInfo,
-  thumbnail_info: ThumbnailInfo,
-  thumbnail_url: String,
+impl Error for ValidationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ValidationError::InvalidId(_, err) => Some(err),
+            _ => None,
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
-pub struct LocationMessage {
-  message_type: String,
-  body: String,
-  url: String,
-  file_name: String,
-  geo_uri: String,
-  thumbnail_info: ImageInfo,
-  thumbnail_url: String,
+impl Event<Value> {
+    /// Parses an event in the client-server API format used by `/sync`.
+    ///
+    /// `type`, `event_id`, `sender`, `origin_server_ts` and `content` are required.
+    /// Timeline messages have no `state_key`, which is left empty with
+    /// `has_state_key` unset.
+    pub fn from_sync_json(value: &Value) -> Result<Self, ParseError> {
+        let event = value.as_object().ok_or(ParseError::NotAnObject)?;
+
+        let required_str = |field: &'static str| -> Result<String, ParseError> {
+            match event.get(field) {
+                Some(Value::String(value)) => Ok(value.clone()),
+                Some(_) => Err(ParseError::InvalidField(field)),
+                None => Err(ParseError::MissingField(field)),
+            }
+        };
+        let optional_str = |field: &'static str| -> Result<String, ParseError> {
+            match event.get(field) {
+                Some(Value::String(value)) => Ok(value.clone()),
+                Some(_) => Err(ParseError::InvalidField(field)),
+                None => Ok(String::new()),
+            }
+        };
+
+        let time_stamp = event
+            .get("origin_server_ts")
+            .ok_or(ParseError::MissingField("origin_server_ts"))?
+            .as_i64()
+            .ok_or(ParseError::InvalidField("origin_server_ts"))?;
+
+        if !event.contains_key("content") {
+            return Err(ParseError::MissingField("content"));
+        }
+
+        let unsigned = object_field(event.get("unsigned"), "unsigned")?;
+        // Older servers send `prev_content` at the top level rather than in `unsigned`.
+        let prev_content = match event.get("prev_content") {
+            Some(prev_content) => object_field(Some(prev_content), "prev_content")?,
+            None => object_field(unsigned.get("prev_content"), "prev_content")?,
+        };
+
+        Ok(Event {
+            state_key: optional_str("state_key")?,
+            has_state_key: event.contains_key("state_key"),
+            sender: required_str("sender")?,
+            etype: required_str("type")?,
+            time_stamp,
+            id: required_str("event_id")?,
+            room_id: optional_str("room_id")?,
+            redacts: optional_str("redacts")?,
+            content: object_field(event.get("content"), "content")?,
+            unsigned,
+            prev_content,
+            is_init: true,
+        })
+    }
+
+    /// Looks up a nested content value by a dotted path, such as
+    /// `m.relates_to.m.in_reply_to.event_id`.
+    ///
+    /// Spec keys contain dots themselves, so each step matches whichever key the
+    /// next segments spell out. Returns `None` when a step is missing or reaches a
+    /// value that isn't an object.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use matrux::events::Event;
+    /// use serde_json::json;
+    ///
+    /// let event = Event::from_sync_json(&json!({
+    ///     "type": "m.room.message",
+    ///     "event_id": "$reply",
+    ///     "sender": "@alice:example.org",
+    ///     "origin_server_ts": 1_718_000_000_000_i64,
+    ///     "content": {
+    ///         "msgtype": "m.text",
+    ///         "body": "Agreed",
+    ///         "m.relates_to": { "m.in_reply_to": { "event_id": "$original" } }
+    ///     }
+    /// }))
+    /// .unwrap();
+    ///
+    /// assert_eq!(
+    ///     event.get_path("m.relates_to.m.in_reply_to.event_id"),
+    ///     Some(&json!("$original"))
+    /// );
+    /// assert_eq!(event.get_path("body.length"), None);
+    /// ```
+    pub fn get_path(&self, dotted: &str) -> Option<&Value> {
+        let segments: Vec<&str> = dotted.split('.').collect();
+
+        (1..=segments.len()).find_map(|taken| {
+            let value = self.content.get(&segments[..taken].join("."))?;
+            walk_path(value, &segments[taken..])
+        })
+    }
 }
 
+/// Follows the rest of a [`Event::get_path`] path from `value`.
+pub(crate) fn walk_path<'a>(value: &'a Value, segments: &[&str]) -> Option<&'a Value> {
+    if segments.is_empty() {
+        return Some(value);
+    }
+
+    let object = value.as_object()?;
 
-#[derive(Debug, Clone)]
-pub struct AudioInfo {
-    mime_type: String,
-    size: u64,
-    duration: Duration, // Duration in milliseconds
+    (1..=segments.len()).find_map(|taken| {
+        let value = object.get(&segments[..taken].join("."))?;
+        walk_path(value, &segments[taken..])
+    })
 }
 
+/// Holds nested values as their JSON encoding, following the `Event<String>`
+/// convention. `null` values are dropped.
+impl From<Event<Value>> for Event<String> {
+    fn from(event: Event<Value>) -> Self {
+        let stringify = |map: HashMap<String, Value>| -> HashMap<String, String> {
+            map.into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| (key, encode_content_value(value)))
+                .collect()
+        };
 
-#[derive(Debug, Clone)]
-pub struct AudioMessage {
-    message_type: String, // Must be `m.audio`
-    body: String,
-    url: String,
-    info: AudioInfo,
+        Event {
+            state_key: event.state_key,
+            has_state_key: event.has_state_key,
+            sender: event.sender,
+            etype: event.etype,
+            time_stamp: event.time_stamp,
+            id: event.id,
+            room_id: event.room_id,
+            redacts: event.redacts,
+            unsigned: stringify(event.unsigned),
+            content: stringify(event.content),
+            prev_content: stringify(event.prev_content),
+            is_init: event.is_init,
+        }
+    }
+}
+
+impl Event<String> {
+    /// Parses the event content into a typed message, dispatching on `msgtype`.
+    /// `m.sticker` events carry no `msgtype` and are parsed as stickers. HTML
+    /// messages come back with their `formatted_body` sanitized.
+    ///
+    /// Content without a `msgtype`, with an unrecognised one, or missing a field
+    /// required by its type is returned as [`MessageContent::Unknown`].
+    pub fn parsed_content(&self) -> MessageContent {
+        let content = self.content_value();
+
+        if self.etype == EVENT_STICKER {
+            return StickerMessage::parse(&content)
+                .map(MessageContent::Sticker)
+                .unwrap_or(MessageContent::Unknown);
+        }
+
+        MessageContent::parse(&content)
+    }
+
+    /// Returns the id of the event this one replies to, if it is a reply.
+    pub fn reply_target(&self) -> Option<String> {
+        let content = self.content_value();
+        str_field(&content[KEY_RELATES_TO][KEY_IN_REPLY_TO], "event_id")
+    }
+
+    /// Returns the id of the event this one edits, if it is an `m.replace` edit.
+    pub fn edit_target(&self) -> Option<String> {
+        let relation = &self.content_value()[KEY_RELATES_TO];
+
+        if str_field(relation, "rel_type").as_deref() != Some(REL_REPLACE) {
+            return None;
+        }
+
+        str_field(relation, "event_id")
+    }
+
+    /// Returns the id of the thread root, if this event is part of an `m.thread`.
+    pub fn thread_root(&self) -> Option<String> {
+        let relation = &self.content_value()[KEY_RELATES_TO];
+
+        if str_field(relation, "rel_type").as_deref() != Some(REL_THREAD) {
+            return None;
+        }
+
+        str_field(relation, "event_id")
+    }
+
+    /// Returns the users intentionally mentioned in `m.mentions`, in the order
+    /// listed. Empty if the event has no `m.mentions`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::collections::HashMap;
+    /// use matrux::events::EventBuilder;
+    ///
+    /// let mut content = HashMap::new();
+    /// content.insert("body".to_string(), "Alice, Bob: lunch?".to_string());
+    /// content.insert(
+    ///     "m.mentions".to_string(),
+    ///     r#"{"user_ids":["@alice:example.org","@bob:example.org"]}"#.to_string(),
+    /// );
+    ///
+    /// let event = EventBuilder::new()
+    ///     .etype("m.room.message".to_string())
+    ///     .content(content)
+    ///     .build();
+    ///
+    /// assert_eq!(event.mentioned_users(), ["@alice:example.org", "@bob:example.org"]);
+    /// assert!(!event.mentions_room());
+    /// ```
+    pub fn mentioned_users(&self) -> Vec<String> {
+        self.content_value()[KEY_MENTIONS]["user_ids"]
+            .as_array()
+            .map(|user_ids| {
+                user_ids
+                    .iter()
+                    .filter_map(|user_id| user_id.as_str().map(str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Whether `m.mentions` mentions the whole room, as `@room` does.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::collections::HashMap;
+    /// use matrux::events::EventBuilder;
+    ///
+    /// let mut content = HashMap::new();
+    /// content.insert("body".to_string(), "@room the server restarts at 5".to_string());
+    /// content.insert("m.mentions".to_string(), r#"{"room":true}"#.to_string());
+    ///
+    /// let event = EventBuilder::new()
+    ///     .etype("m.room.message".to_string())
+    ///     .content(content)
+    ///     .build();
+    ///
+    /// assert!(event.mentions_room());
+    /// assert!(event.mentioned_users().is_empty());
+    /// ```
+    pub fn mentions_room(&self) -> bool {
+        self.content_value()[KEY_MENTIONS]["room"].as_bool() == Some(true)
+    }
+
+    /// Returns a copy of this event with `edit`'s `m.new_content` applied.
+    ///
+    /// Edits that don't target this event, come from a different sender, carry no
+    /// new content or have been redacted leave the copy unchanged. The original's
+    /// own relation is kept.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use matrux::events::Event;
+    /// use serde_json::json;
+    ///
+    /// let event = |value| Event::<String>::from(Event::from_sync_json(&value).unwrap());
+    ///
+    /// let original = event(json!({
+    ///     "type": "m.room.message",
+    ///     "event_id": "$original",
+    ///     "sender": "@alice:example.org",
+    ///     "origin_server_ts": 1_718_000_000_000_i64,
+    ///     "content": { "msgtype": "m.text", "body": "Helo" }
+    /// }));
+    /// let mut edit = event(json!({
+    ///     "type": "m.room.message",
+    ///     "event_id": "$edit",
+    ///     "sender": "@alice:example.org",
+    ///     "origin_server_ts": 1_718_000_000_001_i64,
+    ///     "content": {
+    ///         "msgtype": "m.text",
+    ///         "body": "* Hello",
+    ///         "m.new_content": { "msgtype": "m.text", "body": "Hello" },
+    ///         "m.relates_to": { "rel_type": "m.replace", "event_id": "$original" }
+    ///     }
+    /// }));
+    ///
+    /// assert_eq!(original.apply_edit(&edit).body_str().as_deref(), Some("Hello"));
+    ///
+    /// edit.unsigned.insert("redacted_because".to_string(), "$redaction".to_string());
+    /// assert_eq!(original.apply_edit(&edit).body_str().as_deref(), Some("Helo"));
+    /// ```
+    pub fn apply_edit(&self, edit: &Event<String>) -> Event<String> {
+        let mut edited = self.clone();
+
+        if edit.edit_target().as_deref() != Some(self.id.as_str())
+            || edit.sender != self.sender
+            || edit.is_redacted()
+        {
+            return edited;
+        }
+
+        let Some(Value::Object(new_content)) = edit.content_value().get(KEY_NEW_CONTENT).cloned()
+        else {
+            return edited;
+        };
+
+        let relation = edited.content.remove(KEY_RELATES_TO);
+        edited.content = new_content
+            .into_iter()
+            .map(|(key, value)| (key, encode_content_value(value)))
+            .collect();
+
+        if let Some(relation) = relation {
+            edited.content.insert(KEY_RELATES_TO.to_string(), relation);
+        }
+
+        edited
+    }
+}
+
+/// The content of an `m.room.power_levels` event.
+///
+/// Fields missing from the event take the defaults given by the spec.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PowerLevels {
+    pub users: HashMap<String, i64>,
+    pub users_default: i64,
+    pub events: HashMap<String, i64>,
+    pub events_default: i64,
+    pub state_default: i64,
+    pub ban: i64,
+    pub kick: i64,
+    pub redact: i64,
+    pub invite: i64,
+    pub notifications: HashMap<String, i64>,
+}
+
+impl Default for PowerLevels {
+    fn default() -> Self {
+        Self {
+            users: HashMap::new(),
+            users_default: 0,
+            events: HashMap::new(),
+            events_default: 0,
+            state_default: 50,
+            ban: 50,
+            kick: 50,
+            redact: 50,
+            invite: 0,
+            notifications: HashMap::from([("room".to_string(), 50)]),
+        }
+    }
+}
+
+impl PowerLevels {
+    /// Reads the power levels from an `m.room.power_levels` event's content.
+    pub fn from_event<T: Clone + 'static>(event: &Event<T>) -> PowerLevels
+    where
+        String: for<'a> From<&'a T>,
+    {
+        let content = event.content_value();
+        let defaults = PowerLevels::default();
+        let mut notifications = defaults.notifications;
+        notifications.extend(level_map(&content, "notifications"));
+
+        PowerLevels {
+            users: level_map(&content, "users"),
+            users_default: i64_field(&content, "users_default").unwrap_or(defaults.users_default),
+            events: level_map(&content, "events"),
+            events_default: i64_field(&content, "events_default")
+                .unwrap_or(defaults.events_default),
+            state_default: i64_field(&content, "state_default").unwrap_or(defaults.state_default),
+            ban: i64_field(&content, "ban").unwrap_or(defaults.ban),
+            kick: i64_field(&content, "kick").unwrap_or(defaults.kick),
+            redact: i64_field(&content, "redact").unwrap_or(defaults.redact),
+            invite: i64_field(&content, "invite").unwrap_or(defaults.invite),
+            notifications,
+        }
+    }
+
+    /// The power level of `user_id`, falling back to `users_default`.
+    pub fn user_level(&self, user_id: &str) -> i64 {
+        self.users
+            .get(user_id)
+            .copied()
+            .unwrap_or(self.users_default)
+    }
+
+    /// The level required to send `event_type`, falling back to `state_default` for
+    /// state events and `events_default` for everything else.
+    pub fn event_level(&self, event_type: &str, is_state: bool) -> i64 {
+        self.events.get(event_type).copied().unwrap_or(if is_state {
+            self.state_default
+        } else {
+            self.events_default
+        })
+    }
+}
+
+/// The content of an `m.room.member` event.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MemberEventContent {
+    pub membership: String,
+    pub displayname: Option<String>,
+    pub avatar_url: Option<String>,
+    pub is_direct: Option<bool>,
+    pub reason: Option<String>,
+}
+
+impl MemberEventContent {
+    fn parse(content: &Value) -> Option<Self> {
+        let is_direct = match content.get("is_direct") {
+            Some(Value::Bool(is_direct)) => Some(*is_direct),
+            Some(Value::String(is_direct)) => is_direct.parse().ok(),
+            _ => None,
+        };
+
+        Some(Self {
+            membership: str_field(content, KEY_MEMBERSHIP)?,
+            displayname: str_field(content, KEY_DISPLAYNAME).filter(|name| !name.is_empty()),
+            avatar_url: str_field(content, "avatar_url").filter(|url| !url.is_empty()),
+            is_direct,
+            reason: str_field(content, "reason"),
+        })
+    }
+}
+
+/// The content of an `m.room.encryption` event.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct EncryptionSettings {
+    /// The algorithm used to encrypt messages, e.g. `m.megolm.v1.aes-sha2`.
+    pub algorithm: String,
+    /// How long a session should be used before rotating it.
+    pub rotation_period_ms: Option<u64>,
+    /// How many messages should be sent before rotating the session.
+    pub rotation_period_msgs: Option<u64>,
+}
+
+impl EncryptionSettings {
+    /// Reads the settings from an `m.room.encryption` event. Returns `None` when the
+    /// event has no `algorithm`.
+    pub fn from_event<T: Clone + 'static>(event: &Event<T>) -> Option<EncryptionSettings>
+    where
+        String: for<'a> From<&'a T>,
+    {
+        let content = event.content_value();
+        let period = |key| u64::try_from(i64_field(&content, key)?).ok();
+
+        Some(EncryptionSettings {
+            algorithm: str_field(&content, "algorithm")
+                .filter(|algorithm| !algorithm.is_empty())?,
+            rotation_period_ms: period("rotation_period_ms"),
+            rotation_period_msgs: period("rotation_period_msgs"),
+        })
+    }
+}
+
+/// Whether a user is around, as reported by `m.presence`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PresenceState {
+    Online,
+    #[default]
+    Offline,
+    /// Idle or away from the device.
+    Unavailable,
+}
+
+impl PresenceState {
+    /// Maps a `presence` value to its state; anything unrecognised counts as offline.
+    pub fn from_presence(presence: &str) -> PresenceState {
+        match presence {
+            "online" => PresenceState::Online,
+            "unavailable" => PresenceState::Unavailable,
+            _ => PresenceState::Offline,
+        }
+    }
+}
+
+/// A user's presence, parsed from an `m.presence` event.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Presence {
+    pub user_id: String,
+    pub presence: PresenceState,
+    pub status_msg: Option<String>,
+    /// How long ago the user was last active, in milliseconds.
+    pub last_active_ago: Option<u64>,
+}
+
+impl Presence {
+    /// Reads the presence of the event's sender. Returns `None` for events other
+    /// than `m.presence`.
+    pub fn from_event<T: Clone + 'static>(event: &Event<T>) -> Option<Presence>
+    where
+        String: for<'a> From<&'a T>,
+    {
+        if event.etype != EVENT_PRESENCE {
+            return None;
+        }
+
+        let content = event.content_value();
+
+        Some(Presence {
+            user_id: event.sender.clone(),
+            presence: PresenceState::from_presence(
+                &str_field(&content, "presence").unwrap_or_default(),
+            ),
+            status_msg: str_field(&content, "status_msg").filter(|status| !status.is_empty()),
+            last_active_ago: i64_field(&content, "last_active_ago")
+                .and_then(|ago| u64::try_from(ago).ok()),
+        })
+    }
+}
+
+/// The tags a user has put on a room, parsed from its `m.tag` room account data.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::collections::HashMap;
+/// use matrux::events::Tags;
+///
+/// let mut content = HashMap::new();
+/// content.insert(
+///     "tags".to_string(),
+///     r#"{"m.favourite":{"order":0.25},"u.work":{}}"#.to_string(),
+/// );
+///
+/// let tags = Tags::from_content(&content);
+/// assert!(tags.is_favourite());
+/// assert!(!tags.is_low_priority());
+/// assert_eq!(tags.order("m.favourite"), Some(0.25));
+/// assert_eq!(tags.order("u.work"), None);
+/// assert!(tags.contains("u.work"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Tags {
+    /// Each tag with its `order`, used to sort rooms that share a tag.
+    pub tags: HashMap<String, Option<f64>>,
+}
+
+impl Tags {
+    /// Reads the content of an `m.tag` event. Content without `tags` has none.
+    pub fn from_content<T>(content: &HashMap<String, T>) -> Tags
+    where
+        String: for<'a> From<&'a T>,
+    {
+        let tags = content
+            .get("tags")
+            .map(|raw| decode_content_value("tags", &String::from(raw)));
+
+        Tags {
+            tags: tags
+                .as_ref()
+                .and_then(Value::as_object)
+                .map(|tags| {
+                    tags.iter()
+                        .map(|(tag, info)| (tag.clone(), order(&info["order"])))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Loads the tags `user_id` has put on `room_id` from the `m.tag` account data
+    /// held by `store`.
+    pub fn load<T: Clone, S: Storer<T>>(
+        store: &S,
+        user_id: &str,
+        room_id: &str,
+    ) -> Result<Tags, StoreError>
+    where
+        String: for<'a> From<&'a T>,
+    {
+        let content = store.load_room_account_data(
+            user_id.to_string(),
+            room_id.to_string(),
+            ACCOUNT_DATA_TAG.to_string(),
+        )?;
+
+        Ok(content
+            .map(|content| Tags::from_content(&content))
+            .unwrap_or_default())
+    }
+
+    pub fn contains(&self, tag: &str) -> bool {
+        self.tags.contains_key(tag)
+    }
+
+    pub fn is_favourite(&self) -> bool {
+        self.contains(TAG_FAVOURITE)
+    }
+
+    pub fn is_low_priority(&self) -> bool {
+        self.contains(TAG_LOW_PRIORITY)
+    }
+
+    /// The `order` of `tag`, between 0 and 1. `None` if the room doesn't have the
+    /// tag or it has no order.
+    pub fn order(&self, tag: &str) -> Option<f64> {
+        self.tags.get(tag).copied().flatten()
+    }
+}
+
+/// The rooms a user treats as direct messages, parsed from their global `m.direct`
+/// account data, which maps each partner's user id to the rooms shared with them.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::collections::HashMap;
+/// use matrux::events::DirectRooms;
+///
+/// let mut content = HashMap::new();
+/// content.insert(
+///     "@bob:example.org".to_string(),
+///     r#"["!a:example.org","!b:example.org"]"#.to_string(),
+/// );
+/// content.insert("@carol:example.org".to_string(), r#"["!c:example.org"]"#.to_string());
+///
+/// let direct = DirectRooms::from_content(&content);
+/// assert!(direct.is_direct("!b:example.org"));
+/// assert!(!direct.is_direct("!group:example.org"));
+/// assert_eq!(direct.dm_partner("!c:example.org").as_deref(), Some("@carol:example.org"));
+/// assert_eq!(direct.dm_partner("!group:example.org"), None);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DirectRooms {
+    /// Room ids keyed by the user id of the DM partner.
+    pub rooms: HashMap<String, Vec<String>>,
 }
 
-fn get_html_message(message_type: String, html_text: String) -> HTMLMessage {
+impl DirectRooms {
+    /// Reads the content of an `m.direct` event. Entries that aren't lists of room
+    /// ids are skipped.
+    pub fn from_content<T>(content: &HashMap<String, T>) -> DirectRooms
+    where
+        String: for<'a> From<&'a T>,
+    {
+        let rooms = content
+            .iter()
+            .filter_map(|(user_id, raw)| {
+                let room_ids: Value = serde_json::from_str(&String::from(raw)).ok()?;
+                let room_ids: Vec<String> = room_ids
+                    .as_array()?
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(str::to_string)
+                    .collect();
+                Some((user_id.clone(), room_ids))
+            })
+            .collect();
+
+        DirectRooms { rooms }
+    }
+
+    /// Loads the `m.direct` account data of `user_id` held by `store`.
+    pub fn load<T: Clone, S: Storer<T>>(store: &S, user_id: &str) -> Result<DirectRooms, StoreError>
+    where
+        String: for<'a> From<&'a T>,
+    {
+        let content =
+            store.load_account_data(user_id.to_string(), ACCOUNT_DATA_DIRECT.to_string())?;
 
-  HTMLMessage {
-    body: decode_html_entities(&HTML_REGEX.replace_all(&html_text, "")).to_string(),
-    message_type,
-    format: String::from("org.matrix.custom.html"),
-    formatted_body: html_text
-  }
-}
\ No newline at end of file
+        Ok(content
+            .map(|content| DirectRooms::from_content(&content))
+            .unwrap_or_default())
+    }
+
+    pub fn is_direct(&self, room_id: &str) -> bool {
+        self.dm_partner(room_id).is_some()
+    }
+
+    /// The user `room_id` is a direct message with. If several users list the room,
+    /// the first by user id is returned.
+    pub fn dm_partner(&self, room_id: &str) -> Option<String> {
+        self.rooms
+            .iter()
+            .filter(|(_, room_ids)| room_ids.iter().any(|id| id == room_id))
+            .map(|(user_id, _)| user_id)
+            .min()
+            .cloned()
+    }
+}
+
+/// The users whose events a user has chosen to hide, parsed from their global
+/// `m.ignored_user_list` account data.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::collections::HashMap;
+/// use matrux::events::IgnoredUsers;
+///
+/// let mut content = HashMap::new();
+/// content.insert("ignored_users".to_string(), r#"{"@spam:example.org":{}}"#.to_string());
+///
+/// let ignored = IgnoredUsers::from_content(&content);
+/// assert!(ignored.is_ignored("@spam:example.org"));
+/// assert!(!ignored.is_ignored("@alice:example.org"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct IgnoredUsers {
+    pub users: HashSet<String>,
+}
+
+impl IgnoredUsers {
+    /// Reads the content of an `m.ignored_user_list` event, whose `ignored_users`
+    /// object is keyed by user id.
+    pub fn from_content<T>(content: &HashMap<String, T>) -> IgnoredUsers
+    where
+        String: for<'a> From<&'a T>,
+    {
+        let users = content
+            .get("ignored_users")
+            .map(|raw| decode_content_value("ignored_users", &String::from(raw)))
+            .and_then(|users| {
+                users
+                    .as_object()
+                    .map(|users| users.keys().cloned().collect())
+            })
+            .unwrap_or_default();
+
+        IgnoredUsers { users }
+    }
+
+    /// Loads the `m.ignored_user_list` account data of `user_id` held by `store`.
+    pub fn load<T: Clone, S: Storer<T>>(
+        store: &S,
+        user_id: &str,
+    ) -> Result<IgnoredUsers, StoreError>
+    where
+        String: for<'a> From<&'a T>,
+    {
+        let content = store.load_account_data(
+            user_id.to_string(),
+            ACCOUNT_DATA_IGNORED_USER_LIST.to_string(),
+        )?;
+
+        Ok(content
+            .map(|content| IgnoredUsers::from_content(&content))
+            .unwrap_or_default())
+    }
+
+    pub fn is_ignored(&self, user_id: &str) -> bool {
+        self.users.contains(user_id)
+    }
+}
+
+/// Tag orders are numbers, or numeric strings from some older clients.
+fn order(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(number) => number.parse().ok(),
+        _ => None,
+    }
+    .filter(|order| order.is_finite())
+}
+
+/// How a text-like message should be rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextKind {
+    #[default]
+    Text,
+    /// An automated message, usually shown de-emphasised.
+    Notice,
+    /// An action, shown as "* sender does something".
+    Emote,
+}
+
+impl TextKind {
+    /// Maps a `msgtype` to its kind; anything unrecognised renders as plain text.
+    pub fn from_msgtype(message_type: &str) -> TextKind {
+        match message_type {
+            MSGTYPE_NOTICE => TextKind::Notice,
+            MSGTYPE_EMOTE => TextKind::Emote,
+            _ => TextKind::Text,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "Value")
+)]
+pub struct TextMessage {
+    #[cfg_attr(feature = "serde", serde(rename = "msgtype"))]
+    pub message_type: String,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub kind: TextKind,
+    pub body: String,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "String::is_empty"))]
+    pub formatted_body: String,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "String::is_empty"))]
+    pub format: String,
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "m.relates_to", skip_serializing_if = "Option::is_none")
+    )]
+    pub relates_to: Option<Value>,
+}
+
+impl TextMessage {
+    /// Turns this message into a reply to `event_id`, adding the `m.in_reply_to`
+    /// relation and quoting the original message as the body fallback.
+    pub fn reply_to(mut self, event_id: &str, original_sender: &str, original_body: &str) -> Self {
+        let mut quoted = String::new();
+
+        for (i, line) in original_body.lines().enumerate() {
+            if i == 0 {
+                quoted.push_str(&format!("> <{}> {}\n", original_sender, line));
+            } else {
+                quoted.push_str(&format!("> {}\n", line));
+            }
+        }
+
+        self.body = format!("{}\n{}", quoted, self.body);
+        self.relates_to = Some(json!({ KEY_IN_REPLY_TO: { "event_id": event_id } }));
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(from = "Value")
+)]
+pub struct ThumbnailInfo {
+    #[cfg_attr(feature = "serde", serde(rename = "h"))]
+    pub height: u64,
+    #[cfg_attr(feature = "serde", serde(rename = "w"))]
+    pub width: u64,
+    #[cfg_attr(feature = "serde", serde(rename = "mimetype"))]
+    pub mime_type: String,
+    pub size: u64,
+}
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(from = "Value")
+)]
+pub struct ImageInfo {
+    #[cfg_attr(feature = "serde", serde(rename = "h"))]
+    pub height: u64,
+    #[cfg_attr(feature = "serde", serde(rename = "w"))]
+    pub width: u64,
+    #[cfg_attr(feature = "serde", serde(rename = "mimetype"))]
+    pub mime_type: String,
+    pub size: u64,
+    pub thumbnail_info: ThumbnailInfo,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "String::is_empty"))]
+    pub thumbnail_url: String,
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(from = "Value")
+)]
+pub struct VideoInfo {
+    #[cfg_attr(feature = "serde", serde(rename = "h"))]
+    pub height: u64,
+    #[cfg_attr(feature = "serde", serde(rename = "w"))]
+    pub width: u64,
+    #[cfg_attr(feature = "serde", serde(rename = "mimetype"))]
+    pub mime_type: String,
+    pub size: u64,
+    pub thumbnail_info: ThumbnailInfo,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "String::is_empty"))]
+    pub thumbnail_url: String,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_millis"))]
+    pub duration: Duration,
+}
+
+/// The `key` of an [`EncryptedFile`], as a JSON Web Key.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "Value")
+)]
+pub struct JsonWebKey {
+    pub kty: String,
+    pub key_ops: Vec<String>,
+    pub alg: String,
+    pub k: String,
+    pub ext: bool,
+}
+
+/// Media in encrypted rooms is uploaded encrypted and described by a `file` block
+/// instead of a plain `url`.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "Value")
+)]
+pub struct EncryptedFile {
+    pub url: String,
+    pub key: JsonWebKey,
+    pub iv: String,
+    pub hashes: HashMap<String, String>,
+    pub v: String,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "Value")
+)]
+pub struct VideoMessage {
+    #[cfg_attr(feature = "serde", serde(rename = "msgtype"))]
+    pub message_type: String,
+    pub body: String,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub url: Option<String>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub file: Option<EncryptedFile>,
+    pub info: VideoInfo,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "Value")
+)]
+pub struct ImageMessage {
+    #[cfg_attr(feature = "serde", serde(rename = "msgtype"))]
+    pub message_type: String,
+    pub body: String,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub url: Option<String>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub file: Option<EncryptedFile>,
+    pub info: ImageInfo,
+}
+
+/// The content of an `m.sticker` event. Stickers are identified by their event type,
+/// so unlike [`ImageMessage`] there is no `msgtype`.
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "Value")
+)]
+pub struct StickerMessage {
+    pub body: String,
+    pub url: String,
+    pub info: ImageInfo,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "Value")
+)]
+pub struct HTMLMessage {
+    #[cfg_attr(feature = "serde", serde(rename = "msgtype"))]
+    pub message_type: String,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub kind: TextKind,
+    pub body: String,
+    pub formatted_body: String,
+    pub format: String,
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(from = "Value")
+)]
+pub struct FileInfo {
+    #[cfg_attr(feature = "serde", serde(rename = "mimetype"))]
+    pub mime_type: String,
+    pub size: u64,
+}
+
+/// An `m.file` message: an uploaded file with its original name.
+///
+/// # Examples
+///
+/// ```rust
+/// use matrux::events::{FileInfo, FileMessage, ThumbnailInfo};
+///
+/// let message = FileMessage {
+///     message_type: "m.file".to_string(),
+///     body: "Quarterly report".to_string(),
+///     url: Some("mxc://example.org/abc123".to_string()),
+///     file: None,
+///     file_name: "report.pdf".to_string(),
+///     info: FileInfo { mime_type: "application/pdf".to_string(), size: 48213 },
+///     thumbnail_info: ThumbnailInfo::default(),
+///     thumbnail_url: String::new(),
+/// };
+///
+/// assert_eq!(message.file_name, "report.pdf");
+/// assert_eq!(message.info.size, 48213);
+/// ```
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(into = "Value", try_from = "Value")
+)]
+pub struct FileMessage {
+    pub message_type: String,
+    pub body: String,
+    pub url: Option<String>,
+    pub file: Option<EncryptedFile>,
+    pub file_name: String,
+    pub info: FileInfo,
+    pub thumbnail_info: ThumbnailInfo,
+    pub thumbnail_url: String,
+}
+
+/// Serialized by hand, since the thumbnail fields live inside `info` on the wire.
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(into = "Value", try_from = "Value")
+)]
+pub struct LocationMessage {
+    pub message_type: String,
+    pub body: String,
+    pub url: String,
+    pub file_name: String,
+    pub geo_uri: String,
+    pub thumbnail_info: ImageInfo,
+    pub thumbnail_url: String,
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(from = "Value")
+)]
+pub struct AudioInfo {
+    #[cfg_attr(feature = "serde", serde(rename = "mimetype"))]
+    pub mime_type: String,
+    pub size: u64,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_millis"))]
+    pub duration: Duration, // Duration in milliseconds
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "Value")
+)]
+pub struct AudioMessage {
+    #[cfg_attr(feature = "serde", serde(rename = "msgtype"))]
+    pub message_type: String, // Must be `m.audio`
+    pub body: String,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub url: Option<String>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub file: Option<EncryptedFile>,
+    pub info: AudioInfo,
+}
+
+/// Builds a message from HTML received or written by hand. `formatted_body` is
+/// sanitized down to the tags Matrix clients are expected to render, and the plain
+/// `body` is derived from it.
+pub fn get_html_message(message_type: String, html_text: String) -> HTMLMessage {
+    let formatted_body = sanitize_html(&html_text);
+    let mut body = String::with_capacity(formatted_body.len());
+    strip_html_into(&formatted_body, &mut body);
+
+    HTMLMessage {
+        body,
+        kind: TextKind::from_msgtype(&message_type),
+        message_type,
+        format: String::from(FORMAT_HTML),
+        formatted_body,
+    }
+}
+
+/// Renders Markdown into a message with the raw Markdown as its plain `body`.
+///
+/// `formatted_body` and `format` are only filled in when the Markdown actually
+/// produces formatting; plain text is sent as-is.
+pub fn markdown_message(message_type: String, markdown: &str) -> HTMLMessage {
+    let mut rendered = String::new();
+    let parser = Parser::new_ext(
+        markdown,
+        Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TABLES,
+    );
+    html::push_html(&mut rendered, parser);
+
+    let rendered = rendered.trim_end();
+    let unwrapped = rendered
+        .strip_prefix("<p>")
+        .and_then(|inner| inner.strip_suffix("</p>"))
+        .filter(|inner| !inner.contains("<p>"))
+        .unwrap_or(rendered);
+
+    let (formatted_body, format) = if decode_html_entities(unwrapped) == markdown.trim() {
+        (String::new(), String::new())
+    } else {
+        (sanitize_html(unwrapped), String::from(FORMAT_HTML))
+    };
+
+    HTMLMessage {
+        kind: TextKind::from_msgtype(&message_type),
+        message_type,
+        body: markdown.to_string(),
+        formatted_body,
+        format,
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(untagged, from = "Value")
+)]
+/// The typed content of a message event; see [`Event::parsed_content`].
+///
+/// Deserializing content on its own can't produce [`MessageContent::Sticker`], since
+/// only the event type marks a sticker.
+pub enum MessageContent {
+    Text(TextMessage),
+    Image(ImageMessage),
+    Video(VideoMessage),
+    Audio(AudioMessage),
+    File(FileMessage),
+    Location(LocationMessage),
+    Sticker(StickerMessage),
+    Html(HTMLMessage),
+    Unknown,
+}
+
+impl MessageContent {
+    fn parse(content: &Value) -> MessageContent {
+        let parsed = match content.get(KEY_MSGTYPE).and_then(Value::as_str) {
+            Some(MSGTYPE_TEXT | MSGTYPE_NOTICE | MSGTYPE_EMOTE) => {
+                // `format` without a `formatted_body` leaves only the plain body.
+                let html = (str_field(content, KEY_FORMAT).as_deref() == Some(FORMAT_HTML))
+                    .then(|| HTMLMessage::parse(content))
+                    .flatten();
+
+                html.map(MessageContent::Html)
+                    .or_else(|| TextMessage::parse(content).map(MessageContent::Text))
+            }
+            Some(MSGTYPE_IMAGE) => ImageMessage::parse(content).map(MessageContent::Image),
+            Some(MSGTYPE_VIDEO) => VideoMessage::parse(content).map(MessageContent::Video),
+            Some(MSGTYPE_AUDIO) => AudioMessage::parse(content).map(MessageContent::Audio),
+            Some(MSGTYPE_FILE) => FileMessage::parse(content).map(MessageContent::File),
+            Some(MSGTYPE_LOCATION) => LocationMessage::parse(content).map(MessageContent::Location),
+            // Stickers have no `msgtype`; only the event type tells them apart.
+            _ => None,
+        };
+
+        parsed.unwrap_or(MessageContent::Unknown)
+    }
+}
+
+impl TextMessage {
+    fn parse(content: &Value) -> Option<Self> {
+        let message_type = str_field(content, KEY_MSGTYPE)?;
+
+        Some(Self {
+            kind: TextKind::from_msgtype(&message_type),
+            message_type,
+            body: str_field(content, KEY_BODY)?,
+            formatted_body: str_field(content, KEY_FORMATTED_BODY).unwrap_or_default(),
+            format: str_field(content, KEY_FORMAT).unwrap_or_default(),
+            relates_to: content
+                .get(KEY_RELATES_TO)
+                .filter(|relation| relation.is_object())
+                .cloned(),
+        })
+    }
+}
+
+impl HTMLMessage {
+    /// Parses received HTML content. `formatted_body` is sanitized like
+    /// [`get_html_message`] does, and the plain text derived from it stands in for a
+    /// missing `body`.
+    fn parse(content: &Value) -> Option<Self> {
+        let message_type = str_field(content, KEY_MSGTYPE)?;
+        let mut message = get_html_message(message_type, str_field(content, KEY_FORMATTED_BODY)?);
+
+        if let Some(body) = str_field(content, KEY_BODY) {
+            message.body = body;
+        }
+
+        Some(message)
+    }
+
+    /// An `m.text` message holding `text`, escaped so it shows as written. Mostly
+    /// useful as the start of a message built with [`HTMLMessage::append`].
+    pub fn text(text: &str) -> HTMLMessage {
+        HTMLMessage::fragment(text.to_string(), encode_text(text).into_owned())
+    }
+
+    /// A spoiler, hidden until the reader reveals it. The plain-text `body` keeps
+    /// the text, marked as a spoiler along with the reason if there is one.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use matrux::events::HTMLMessage;
+    ///
+    /// let message = HTMLMessage::spoiler(Some("ending"), "It was the butler");
+    /// assert_eq!(
+    ///     message.formatted_body,
+    ///     r#"<span data-mx-spoiler="ending">It was the butler</span>"#
+    /// );
+    /// assert_eq!(message.body, "[Spoiler: ending] It was the butler");
+    /// ```
+    pub fn spoiler(reason: Option<&str>, text: &str) -> HTMLMessage {
+        let (body, formatted_body) = match reason {
+            Some(reason) => (
+                format!("[Spoiler: {}] {}", reason, text),
+                format!(
+                    "<span data-mx-spoiler=\"{}\">{}</span>",
+                    encode_double_quoted_attribute(reason),
+                    encode_text(text)
+                ),
+            ),
+            None => (
+                format!("[Spoiler] {}", text),
+                format!("<span data-mx-spoiler>{}</span>", encode_text(text)),
+            ),
+        };
+
+        HTMLMessage::fragment(body, formatted_body)
+    }
+
+    /// A mention "pill" linking to `user_id`, shown as `display_name`, which is
+    /// also the plain-text `body`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use matrux::events::HTMLMessage;
+    ///
+    /// let message = HTMLMessage::text("Thanks ")
+    ///     .append(HTMLMessage::mention("@alice:example.org", "Alice"))
+    ///     .append(HTMLMessage::text("!"));
+    ///
+    /// assert_eq!(message.body, "Thanks Alice!");
+    /// assert_eq!(
+    ///     message.formatted_body,
+    ///     r#"Thanks <a href="https://matrix.to/#/@alice:example.org">Alice</a>!"#
+    /// );
+    /// ```
+    pub fn mention(user_id: &str, display_name: &str) -> HTMLMessage {
+        HTMLMessage::fragment(
+            display_name.to_string(),
+            format!(
+                "<a href=\"https://matrix.to/#/{}\">{}</a>",
+                encode_double_quoted_attribute(user_id),
+                encode_text(display_name)
+            ),
+        )
+    }
+
+    /// Adds `other` to the end of this message, both its `body` and its
+    /// `formatted_body`.
+    pub fn append(mut self, other: HTMLMessage) -> HTMLMessage {
+        self.body.push_str(&other.body);
+        self.formatted_body.push_str(&other.formatted_body);
+        self
+    }
+
+    fn fragment(body: String, formatted_body: String) -> HTMLMessage {
+        HTMLMessage {
+            message_type: MSGTYPE_TEXT.to_string(),
+            kind: TextKind::Text,
+            body,
+            formatted_body,
+            format: FORMAT_HTML.to_string(),
+        }
+    }
+}
+
+impl ThumbnailInfo {
+    fn parse(info: &Value) -> Self {
+        Self {
+            height: u64_field(info, "h"),
+            width: u64_field(info, "w"),
+            mime_type: str_field(info, "mimetype").unwrap_or_default(),
+            size: u64_field(info, "size"),
+        }
+    }
+}
+
+impl ImageInfo {
+    fn parse(info: &Value) -> Self {
+        Self {
+            height: u64_field(info, "h"),
+            width: u64_field(info, "w"),
+            mime_type: str_field(info, "mimetype").unwrap_or_default(),
+            size: u64_field(info, "size"),
+            thumbnail_info: ThumbnailInfo::parse(&info["thumbnail_info"]),
+            thumbnail_url: str_field(info, "thumbnail_url").unwrap_or_default(),
+        }
+    }
+
+    /// The thumbnail to show for a `target_w` by `target_h` preview: the
+    /// `thumbnail_url` sent with the image, together with its info.
+    ///
+    /// The thumbnail is returned whatever its size, even when it is smaller than
+    /// the target; compare its width and height with the target to decide whether
+    /// to crop or scale it. Returns `None` only when the image has no thumbnail,
+    /// in which case the client can ask the server for one with
+    /// [`MxcUri::thumbnail_url`](crate::media::MxcUri::thumbnail_url).
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use matrux::events::{ImageInfo, ThumbnailInfo};
+    ///
+    /// let mut info = ImageInfo {
+    ///     width: 1600,
+    ///     height: 1200,
+    ///     ..Default::default()
+    /// };
+    /// assert!(info.best_thumbnail(320, 240).is_none());
+    ///
+    /// info.thumbnail_url = "mxc://example.org/thumb".to_string();
+    /// info.thumbnail_info = ThumbnailInfo { width: 320, height: 240, ..Default::default() };
+    ///
+    /// let (url, thumbnail) = info.best_thumbnail(1024, 768).unwrap();
+    /// assert_eq!(url, "mxc://example.org/thumb");
+    /// // Smaller than the target, so the client scales it up.
+    /// assert!(thumbnail.width < 1024 && thumbnail.height < 768);
+    /// ```
+    pub fn best_thumbnail(
+        &self,
+        _target_w: u64,
+        _target_h: u64,
+    ) -> Option<(String, ThumbnailInfo)> {
+        embedded_thumbnail(&self.thumbnail_url, &self.thumbnail_info)
+    }
+}
+
+impl VideoInfo {
+    /// Reads a video's `info` block. Missing fields are zero or empty, including
+    /// `duration`, which is sent in milliseconds.
+    pub fn parse(info: &Value) -> Self {
+        Self {
+            height: u64_field(info, "h"),
+            width: u64_field(info, "w"),
+            mime_type: str_field(info, "mimetype").unwrap_or_default(),
+            size: u64_field(info, "size"),
+            thumbnail_info: ThumbnailInfo::parse(&info["thumbnail_info"]),
+            thumbnail_url: str_field(info, "thumbnail_url").unwrap_or_default(),
+            duration: duration_field(info, "duration"),
+        }
+    }
+
+    /// The thumbnail to show for a `target_w` by `target_h` preview, like
+    /// [`ImageInfo::best_thumbnail`].
+    pub fn best_thumbnail(
+        &self,
+        _target_w: u64,
+        _target_h: u64,
+    ) -> Option<(String, ThumbnailInfo)> {
+        embedded_thumbnail(&self.thumbnail_url, &self.thumbnail_info)
+    }
+}
+
+/// The thumbnail sent with some media, if there is one.
+fn embedded_thumbnail(url: &str, info: &ThumbnailInfo) -> Option<(String, ThumbnailInfo)> {
+    (!url.is_empty()).then(|| (url.to_string(), info.clone()))
+}
+
+impl FileInfo {
+    fn parse(info: &Value) -> Self {
+        Self {
+            mime_type: str_field(info, "mimetype").unwrap_or_default(),
+            size: u64_field(info, "size"),
+        }
+    }
+}
+
+impl AudioInfo {
+    /// Reads an audio clip's `info` block. Missing fields are zero or empty,
+    /// including `duration`, which is sent in milliseconds.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use matrux::events::AudioInfo;
+    /// use serde_json::json;
+    ///
+    /// let info = AudioInfo::parse(&json!({
+    ///     "mimetype": "audio/ogg",
+    ///     "size": 13_000,
+    ///     "duration": 4200,
+    /// }));
+    /// assert_eq!(info.duration, Duration::from_millis(4200));
+    /// assert_eq!(info.mime_type, "audio/ogg");
+    ///
+    /// assert_eq!(AudioInfo::parse(&json!({})).duration, Duration::ZERO);
+    /// ```
+    pub fn parse(info: &Value) -> Self {
+        Self {
+            mime_type: str_field(info, "mimetype").unwrap_or_default(),
+            size: u64_field(info, "size"),
+            duration: duration_field(info, "duration"),
+        }
+    }
+}
+
+impl JsonWebKey {
+    fn parse(key: &Value) -> Option<Self> {
+        Some(Self {
+            kty: str_field(key, "kty")?,
+            key_ops: key
+                .get("key_ops")
+                .and_then(Value::as_array)
+                .map(|ops| {
+                    ops.iter()
+                        .filter_map(Value::as_str)
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            alg: str_field(key, "alg")?,
+            k: str_field(key, "k")?,
+            ext: key.get("ext").and_then(Value::as_bool).unwrap_or(true),
+        })
+    }
+}
+
+impl EncryptedFile {
+    fn parse(file: &Value) -> Option<Self> {
+        let hashes = file
+            .get("hashes")
+            .and_then(Value::as_object)?
+            .iter()
+            .filter_map(|(algorithm, hash)| Some((algorithm.clone(), hash.as_str()?.to_string())))
+            .collect();
+
+        Some(Self {
+            url: str_field(file, "url")?,
+            key: JsonWebKey::parse(file.get("key")?)?,
+            iv: str_field(file, "iv")?,
+            hashes,
+            v: str_field(file, "v")?,
+        })
+    }
+}
+
+/// Media is either a plain `url` or, in encrypted rooms, a `file` block. At least one
+/// of them has to be present.
+fn media_source(content: &Value) -> Option<(Option<String>, Option<EncryptedFile>)> {
+    let url = str_field(content, "url");
+    let file = content.get("file").and_then(EncryptedFile::parse);
+
+    if url.is_none() && file.is_none() {
+        return None;
+    }
+
+    Some((url, file))
+}
+
+fn content_object(content: &HashMap<String, Value>) -> Value {
+    Value::Object(
+        content
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect(),
+    )
+}
+
+impl ImageMessage {
+    /// Reads an `m.image` message from an event's content, including its `info`
+    /// and `thumbnail_info`. Returns `None` unless `msgtype` is `m.image`, or without
+    /// a `body` or media in either `url` or an encrypted `file`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::collections::HashMap;
+    /// use matrux::events::ImageMessage;
+    /// use serde_json::{json, Value};
+    ///
+    /// let content: HashMap<String, Value> = serde_json::from_value(json!({
+    ///     "msgtype": "m.image",
+    ///     "body": "cat.png",
+    ///     "url": "mxc://example.org/cat",
+    ///     "info": {
+    ///         "w": 800,
+    ///         "h": 600,
+    ///         "mimetype": "image/png",
+    ///         "size": 52_000,
+    ///         "thumbnail_url": "mxc://example.org/cat-thumb",
+    ///         "thumbnail_info": { "w": 80, "h": 60, "mimetype": "image/png", "size": 2_000 }
+    ///     }
+    /// }))
+    /// .unwrap();
+    ///
+    /// let image = ImageMessage::from_content(&content).unwrap();
+    /// assert_eq!(image.url.as_deref(), Some("mxc://example.org/cat"));
+    /// assert_eq!((image.info.width, image.info.height), (800, 600));
+    /// assert_eq!(image.info.thumbnail_info.width, 80);
+    ///
+    /// let mut without_url = content.clone();
+    /// without_url.remove("url");
+    /// assert!(ImageMessage::from_content(&without_url).is_none());
+    /// ```
+    pub fn from_content(content: &HashMap<String, Value>) -> Option<Self> {
+        Self::parse(&content_object(content)).filter(|image| image.message_type == MSGTYPE_IMAGE)
+    }
+
+    fn parse(content: &Value) -> Option<Self> {
+        let (url, file) = media_source(content)?;
+
+        Some(Self {
+            message_type: str_field(content, KEY_MSGTYPE)?,
+            body: str_field(content, KEY_BODY)?,
+            url,
+            file,
+            info: ImageInfo::parse(&content["info"]),
+        })
+    }
+}
+
+impl StickerMessage {
+    fn parse(content: &Value) -> Option<Self> {
+        Some(Self {
+            body: str_field(content, KEY_BODY)?,
+            url: str_field(content, "url")?,
+            info: ImageInfo::parse(&content["info"]),
+        })
+    }
+}
+
+impl VideoMessage {
+    /// Reads an `m.video` message from an event's content, like
+    /// [`ImageMessage::from_content`]. Returns `None` unless `msgtype` is `m.video`.
+    pub fn from_content(content: &HashMap<String, Value>) -> Option<Self> {
+        Self::parse(&content_object(content)).filter(|video| video.message_type == MSGTYPE_VIDEO)
+    }
+
+    fn parse(content: &Value) -> Option<Self> {
+        let (url, file) = media_source(content)?;
+
+        Some(Self {
+            message_type: str_field(content, KEY_MSGTYPE)?,
+            body: str_field(content, KEY_BODY)?,
+            url,
+            file,
+            info: VideoInfo::parse(&content["info"]),
+        })
+    }
+}
+
+impl AudioMessage {
+    fn parse(content: &Value) -> Option<Self> {
+        let (url, file) = media_source(content)?;
+
+        Some(Self {
+            message_type: str_field(content, KEY_MSGTYPE)?,
+            body: str_field(content, KEY_BODY)?,
+            url,
+            file,
+            info: AudioInfo::parse(&content["info"]),
+        })
+    }
+}
+
+impl FileMessage {
+    fn parse(content: &Value) -> Option<Self> {
+        let info = &content["info"];
+        let (url, file) = media_source(content)?;
+
+        Some(Self {
+            message_type: str_field(content, KEY_MSGTYPE)?,
+            body: str_field(content, KEY_BODY)?,
+            url,
+            file,
+            file_name: str_field(content, "filename").unwrap_or_default(),
+            info: FileInfo::parse(info),
+            thumbnail_info: ThumbnailInfo::parse(&info["thumbnail_info"]),
+            thumbnail_url: str_field(info, "thumbnail_url").unwrap_or_default(),
+        })
+    }
+}
+
+impl LocationMessage {
+    fn parse(content: &Value) -> Option<Self> {
+        let info = &content["info"];
+
+        Some(Self {
+            message_type: str_field(content, KEY_MSGTYPE)?,
+            body: str_field(content, KEY_BODY)?,
+            url: str_field(content, "url").unwrap_or_default(),
+            file_name: str_field(content, "filename").unwrap_or_default(),
+            geo_uri: str_field(content, "geo_uri")?,
+            thumbnail_info: ImageInfo::parse(&info["thumbnail_info"]),
+            thumbnail_url: str_field(info, "thumbnail_url").unwrap_or_default(),
+        })
+    }
+
+    /// The latitude and longitude from `geo_uri`, in degrees. Any altitude is
+    /// ignored.
+    ///
+    /// Returns `None` unless `geo_uri` is a `geo:` URI with coordinates in range.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use matrux::events::{ImageInfo, LocationMessage};
+    ///
+    /// let mut location = LocationMessage {
+    ///     message_type: "m.location".to_string(),
+    ///     body: "Big Ben".to_string(),
+    ///     url: String::new(),
+    ///     file_name: String::new(),
+    ///     geo_uri: "geo:51.5008,-0.1247;u=35".to_string(),
+    ///     thumbnail_info: ImageInfo::default(),
+    ///     thumbnail_url: String::new(),
+    /// };
+    /// assert_eq!(location.coordinates(), Some((51.5008, -0.1247)));
+    /// assert_eq!(location.uncertainty(), Some(35.0));
+    ///
+    /// location.geo_uri = "geo:51.5008,-0.1247".to_string();
+    /// assert_eq!(location.coordinates(), Some((51.5008, -0.1247)));
+    /// assert_eq!(location.uncertainty(), None);
+    ///
+    /// location.geo_uri = "https://maps.example.org/51.5008,-0.1247".to_string();
+    /// assert_eq!(location.coordinates(), None);
+    /// ```
+    pub fn coordinates(&self) -> Option<(f64, f64)> {
+        let (coordinates, _) = self.geo_uri_parts()?;
+        let mut coordinates = coordinates
+            .split(',')
+            .map(|coordinate| coordinate.trim().parse::<f64>());
+
+        let latitude = coordinates.next()?.ok()?;
+        let longitude = coordinates.next()?.ok()?;
+
+        // An altitude may follow, but nothing after it.
+        if let Some(altitude) = coordinates.next() {
+            altitude.ok()?;
+        }
+        if coordinates.next().is_some() {
+            return None;
+        }
+
+        if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+            return None;
+        }
+
+        Some((latitude, longitude))
+    }
+
+    /// How far the actual location may be from [`coordinates`](Self::coordinates),
+    /// in metres, from the `u=` parameter of `geo_uri`.
+    pub fn uncertainty(&self) -> Option<f64> {
+        self.coordinates()?;
+        let (_, parameters) = self.geo_uri_parts()?;
+
+        parameters
+            .split(';')
+            .filter_map(|parameter| parameter.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("u"))
+            .and_then(|(_, uncertainty)| uncertainty.trim().parse::<f64>().ok())
+            .filter(|uncertainty| uncertainty.is_finite() && *uncertainty >= 0.0)
+    }
+
+    /// Splits a `geo:` URI into its coordinates and its `;`-separated parameters.
+    fn geo_uri_parts(&self) -> Option<(&str, &str)> {
+        let (scheme, path) = self.geo_uri.split_once(':')?;
+
+        if !scheme.eq_ignore_ascii_case("geo") {
+            return None;
+        }
+
+        Some(path.split_once(';').unwrap_or((path, "")))
+    }
+}
+
+#[cfg(feature = "serde")]
+mod wire {
+    //! Glue between the message structs and their wire format. Deserialization goes
+    //! through the same parsers as [`Event::parsed_content`](super::Event::parsed_content).
+
+    use std::time::Duration;
+
+    use serde::Serializer;
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    const MISSING_FIELDS: &str = "message content is missing a required field";
+
+    pub(super) fn is_false(value: &bool) -> bool {
+        !value
+    }
+
+    pub(super) fn serialize_millis<S: Serializer>(
+        duration: &Duration,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_millis() as u64)
+    }
+
+    macro_rules! parse_from_value {
+        ($($message:ty),*) => {
+            $(
+                impl TryFrom<Value> for $message {
+                    type Error = &'static str;
+
+                    fn try_from(content: Value) -> Result<Self, Self::Error> {
+                        <$message>::parse(&content).ok_or(MISSING_FIELDS)
+                    }
+                }
+            )*
+        };
+    }
+
+    parse_from_value!(
+        TextMessage,
+        HTMLMessage,
+        ImageMessage,
+        VideoMessage,
+        AudioMessage,
+        FileMessage,
+        LocationMessage,
+        StickerMessage,
+        JsonWebKey,
+        EncryptedFile
+    );
+
+    macro_rules! info_from_value {
+        ($($info:ty),*) => {
+            $(
+                impl From<Value> for $info {
+                    fn from(info: Value) -> Self {
+                        <$info>::parse(&info)
+                    }
+                }
+            )*
+        };
+    }
+
+    info_from_value!(ThumbnailInfo, ImageInfo, VideoInfo, FileInfo, AudioInfo);
+
+    impl From<Value> for MessageContent {
+        fn from(content: Value) -> Self {
+            MessageContent::parse(&content)
+        }
+    }
+
+    impl From<FileMessage> for Value {
+        fn from(message: FileMessage) -> Self {
+            let mut value = json!({
+                KEY_MSGTYPE: message.message_type,
+                KEY_BODY: message.body,
+                "filename": message.file_name,
+                "info": {
+                    "mimetype": message.info.mime_type,
+                    "size": message.info.size,
+                    "thumbnail_info": message.thumbnail_info,
+                    "thumbnail_url": message.thumbnail_url,
+                },
+            });
+
+            if let Some(url) = message.url {
+                value["url"] = Value::String(url);
+            }
+            if let Some(file) = message.file {
+                value["file"] = json!(file);
+            }
+            value
+        }
+    }
+
+    impl From<LocationMessage> for Value {
+        fn from(message: LocationMessage) -> Self {
+            json!({
+                KEY_MSGTYPE: message.message_type,
+                KEY_BODY: message.body,
+                "geo_uri": message.geo_uri,
+                "info": {
+                    "thumbnail_info": message.thumbnail_info,
+                    "thumbnail_url": message.thumbnail_url,
+                },
+            })
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+use wire::serialize_millis;
+
+fn object_field(
+    value: Option<&Value>,
+    field: &'static str,
+) -> Result<HashMap<String, Value>, ParseError> {
+    match value {
+        Some(Value::Object(map)) => Ok(map.clone().into_iter().collect()),
+        Some(_) => Err(ParseError::InvalidField(field)),
+        None => Ok(HashMap::new()),
+    }
+}
+
+/// Content and unsigned keys whose values the spec defines as objects or arrays.
+/// Under `Event<String>` only these hold a JSON encoding; every other key is plain
+/// text, however much it looks like JSON.
+const NESTED_CONTENT_KEYS: &[&str] = &[
+    KEY_MENTIONS,
+    KEY_NEW_CONTENT,
+    KEY_RELATES_TO,
+    "aliases",
+    "allow",
+    "alt_aliases",
+    "ciphertext",
+    "deny",
+    "events",
+    "file",
+    "ignored_users",
+    "info",
+    "m.relations",
+    "notifications",
+    "pinned",
+    "predecessor",
+    "prev_content",
+    "public_keys",
+    "redacted_because",
+    "tags",
+    "third_party_invite",
+    "user_ids",
+    "users",
+    "via",
+];
+
+/// Decodes the value held under `key`: nested keys carry their JSON encoding,
+/// everything else is a plain string. Nested keys that don't hold valid JSON,
+/// like a Megolm `ciphertext`, are kept as strings too.
+fn decode_content_value(key: &str, raw: &str) -> Value {
+    if !NESTED_CONTENT_KEYS.contains(&key) {
+        return Value::String(raw.to_string());
+    }
+
+    serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string()))
+}
+
+fn encode_content_value(value: Value) -> String {
+    match value {
+        Value::String(string) => string,
+        other => other.to_string(),
+    }
+}
+
+fn str_field(value: &Value, key: &str) -> Option<String> {
+    value.get(key).and_then(Value::as_str).map(String::from)
+}
+
+/// Integers may be numbers or, in events from older servers, numeric strings.
+fn level(value: &Value) -> Option<i64> {
+    match value {
+        Value::Number(number) => number.as_i64(),
+        Value::String(number) => number.parse().ok(),
+        _ => None,
+    }
+}
+
+fn i64_field(value: &Value, key: &str) -> Option<i64> {
+    value.get(key).and_then(level)
+}
+
+fn level_map(value: &Value, key: &str) -> HashMap<String, i64> {
+    let Some(Value::Object(levels)) = value.get(key) else {
+        return HashMap::new();
+    };
+
+    levels
+        .iter()
+        .filter_map(|(name, value)| Some((name.clone(), level(value)?)))
+        .collect()
+}
+
+/// A duration sent as integer milliseconds; missing or malformed is zero.
+fn duration_field(value: &Value, key: &str) -> Duration {
+    Duration::from_millis(u64_field(value, key))
+}
+
+fn u64_field(value: &Value, key: &str) -> u64 {
+    match value.get(key) {
+        Some(Value::Number(number)) => number.as_u64().unwrap_or(0),
+        Some(Value::String(number)) => number.parse().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::{EVENT_MESSAGE, STATE_NAME};
+
+    fn message(content: Value) -> Event<String> {
+        let content = content
+            .as_object()
+            .unwrap()
+            .iter()
+            .map(|(key, value)| {
+                let value = value
+                    .as_str()
+                    .map_or_else(|| value.to_string(), str::to_string);
+                (key.clone(), value)
+            })
+            .collect();
+
+        EventBuilder::new()
+            .etype(EVENT_MESSAGE.to_string())
+            .content(content)
+            .build()
+    }
+
+    fn at(time_stamp: i64, id: &str) -> Event<String> {
+        EventBuilder::new()
+            .time_stamp(time_stamp)
+            .id(id.to_string())
+            .build()
+    }
+
+    #[test]
+    fn events_sort_by_timestamp_then_id() {
+        let mut events = [
+            at(20, "$b"),
+            at(10, "$z"),
+            at(20, "$a"),
+            at(5, "$c"),
+            at(10, "$y"),
+        ];
+        events.sort_by(Event::timeline_order);
+
+        let order: Vec<(i64, &str)> = events
+            .iter()
+            .map(|event| (event.time_stamp, event.id.as_str()))
+            .collect();
+        assert_eq!(
+            order,
+            [(5, "$c"), (10, "$y"), (10, "$z"), (20, "$a"), (20, "$b")]
+        );
+    }
+
+    #[test]
+    fn equality_compares_every_field() {
+        let original = message(json!({ "msgtype": "m.text", "body": "hello" }));
+        let mut edited = original.clone();
+        edited
+            .content
+            .insert(KEY_BODY.to_string(), "goodbye".to_string());
+
+        assert_eq!(original, original.clone());
+        assert_ne!(original, edited);
+        assert_eq!(original.timeline_order(&edited), Ordering::Equal);
+        assert!(!original.content_eq(&edited));
+    }
+
+    #[test]
+    fn only_sticker_events_parse_as_stickers() {
+        let content = json!({
+            "body": "Landing",
+            "url": "mxc://example.org/sticker",
+            "info": { "mimetype": "image/png", "size": 100, "w": 64, "h": 64 },
+        });
+
+        let mut event = message(content);
+        assert!(matches!(event.parsed_content(), MessageContent::Unknown));
+
+        event.etype = EVENT_STICKER.to_string();
+        match event.parsed_content() {
+            MessageContent::Sticker(sticker) => assert_eq!(sticker.body, "Landing"),
+            other => panic!("expected a sticker, got {:?}", other),
+        }
+    }
+
+    fn new_event(etype: &str, state_key: Option<&str>) -> Event<String> {
+        Event::new(
+            state_key.map(str::to_string),
+            "@alice:example.org".to_string(),
+            etype.to_string(),
+            0,
+            "$event".to_string(),
+            "!room:example.org".to_string(),
+            String::new(),
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+            true,
+        )
+    }
+
+    #[test]
+    fn new_events_with_an_empty_state_key_are_state() {
+        assert!(new_event(STATE_NAME, Some("")).is_state_event());
+        assert!(new_event(STATE_MEMBER, Some("@alice:example.org")).is_state_event());
+        assert!(!new_event(EVENT_MESSAGE, None).is_state_event());
+    }
+
+    #[test]
+    fn from_content_checks_the_msgtype() {
+        let media = |msgtype: &str| -> HashMap<String, Value> {
+            serde_json::from_value(json!({
+                "msgtype": msgtype,
+                "body": "clip",
+                "url": "mxc://example.org/clip",
+            }))
+            .unwrap()
+        };
+
+        assert!(ImageMessage::from_content(&media(MSGTYPE_IMAGE)).is_some());
+        assert!(ImageMessage::from_content(&media(MSGTYPE_VIDEO)).is_none());
+        assert!(VideoMessage::from_content(&media(MSGTYPE_VIDEO)).is_some());
+        assert!(VideoMessage::from_content(&media(MSGTYPE_IMAGE)).is_none());
+        assert!(VideoMessage::from_content(&media(MSGTYPE_FILE)).is_none());
+    }
+
+    #[test]
+    fn parsed_html_message_is_sanitized() {
+        let event = message(json!({
+            "msgtype": "m.text",
+            "body": "hello",
+            "format": "org.matrix.custom.html",
+            "formatted_body": "<b onclick=\"steal()\">hello</b><script>alert(1)</script>",
+        }));
+
+        match event.parsed_content() {
+            MessageContent::Html(html) => {
+                assert_eq!(html.formatted_body, "<b>hello</b>");
+                assert_eq!(html.body, "hello");
+            }
+            other => panic!("expected an HTML message, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parsed_html_message_without_body_falls_back_to_plain_text() {
+        let event = message(json!({
+            "msgtype": "m.text",
+            "format": "org.matrix.custom.html",
+            "formatted_body": "<ul><li>one</li><li>two</li></ul>",
+        }));
+
+        match event.parsed_content() {
+            MessageContent::Html(html) => assert_eq!(html.body, "- one\n- two"),
+            other => panic!("expected an HTML message, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parsed_content_dispatches_on_msgtype() {
+        let text = |msgtype: &str| message(json!({ "msgtype": msgtype, "body": "hi" }));
+        for (msgtype, kind) in [
+            (MSGTYPE_TEXT, TextKind::Text),
+            (MSGTYPE_NOTICE, TextKind::Notice),
+            (MSGTYPE_EMOTE, TextKind::Emote),
+        ] {
+            match text(msgtype).parsed_content() {
+                MessageContent::Text(text) => assert_eq!(text.kind, kind),
+                other => panic!("expected a text message, got {:?}", other),
+            }
+        }
+
+        let html = message(json!({
+            "msgtype": "m.text",
+            "body": "hi",
+            "format": "org.matrix.custom.html",
+            "formatted_body": "<b>hi</b>",
+        }));
+        assert!(matches!(html.parsed_content(), MessageContent::Html(_)));
+
+        let media = |msgtype: &str| {
+            message(json!({
+                "msgtype": msgtype,
+                "body": "clip",
+                "url": "mxc://example.org/clip",
+            }))
+        };
+        assert!(matches!(
+            media(MSGTYPE_IMAGE).parsed_content(),
+            MessageContent::Image(_)
+        ));
+        assert!(matches!(
+            media(MSGTYPE_VIDEO).parsed_content(),
+            MessageContent::Video(_)
+        ));
+        assert!(matches!(
+            media(MSGTYPE_AUDIO).parsed_content(),
+            MessageContent::Audio(_)
+        ));
+        assert!(matches!(
+            media(MSGTYPE_FILE).parsed_content(),
+            MessageContent::File(_)
+        ));
+
+        let location = message(json!({
+            "msgtype": "m.location",
+            "body": "Big Ben",
+            "geo_uri": "geo:51.5008,0.1247",
+        }));
+        match location.parsed_content() {
+            MessageContent::Location(location) => {
+                assert_eq!(location.geo_uri, "geo:51.5008,0.1247")
+            }
+            other => panic!("expected a location, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unrecognised_content_is_unknown() {
+        for content in [
+            json!({ "msgtype": "org.example.custom", "body": "hi" }),
+            json!({ "body": "no msgtype" }),
+            json!({ "msgtype": "m.text" }),
+            json!({ "msgtype": "m.image", "body": "no url" }),
+        ] {
+            assert!(matches!(
+                message(content).parsed_content(),
+                MessageContent::Unknown
+            ));
+        }
+    }
+
+    #[test]
+    fn text_that_looks_like_json_stays_text() {
+        let event: Event<String> = Event::from_sync_json(&json!({
+            "type": "m.room.message",
+            "event_id": "$json",
+            "sender": "@alice:example.org",
+            "origin_server_ts": 1,
+            "content": {
+                "msgtype": "m.text",
+                "body": "[1, 2]",
+                "m.mentions": { "user_ids": ["@bob:example.org"] },
+            },
+        }))
+        .unwrap()
+        .into();
+
+        assert_eq!(event.content[KEY_BODY], "[1, 2]");
+        assert_eq!(event.content_value()[KEY_BODY], "[1, 2]");
+        assert_eq!(event.mentioned_users(), ["@bob:example.org"]);
+        match event.parsed_content() {
+            MessageContent::Text(text) => assert_eq!(text.body, "[1, 2]"),
+            other => panic!("expected a text message, got {:?}", other),
+        }
+
+        let object = message(json!({ "msgtype": "m.text", "body": "{\"a\": 1}" }));
+        assert_eq!(object.content_value()[KEY_BODY], "{\"a\": 1}");
+    }
+
+    #[test]
+    fn html_format_without_formatted_body_is_text() {
+        for msgtype in [MSGTYPE_TEXT, MSGTYPE_NOTICE, MSGTYPE_EMOTE] {
+            let event = message(json!({
+                "msgtype": msgtype,
+                "body": "plain",
+                "format": "org.matrix.custom.html",
+            }));
+
+            match event.parsed_content() {
+                MessageContent::Text(text) => {
+                    assert_eq!(text.message_type, msgtype);
+                    assert_eq!(text.body, "plain");
+                    assert!(text.formatted_body.is_empty());
+                }
+                other => panic!("expected a text message, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn text_messages_keep_their_fields() {
+        let event = message(json!({
+            "msgtype": "m.emote",
+            "body": "waves",
+            "m.relates_to": { "m.in_reply_to": { "event_id": "$parent" } },
+        }));
+
+        match event.parsed_content() {
+            MessageContent::Text(text) => {
+                assert_eq!(text.kind, TextKind::Emote);
+                assert_eq!(text.message_type, MSGTYPE_EMOTE);
+                assert_eq!(text.body, "waves");
+                assert_eq!(
+                    text.relates_to,
+                    Some(json!({ "m.in_reply_to": { "event_id": "$parent" } }))
+                );
+            }
+            other => panic!("expected a text message, got {:?}", other),
+        }
+
+        let notice = message(json!({ "msgtype": "m.notice", "body": "build passed" }));
+        match notice.parsed_content() {
+            MessageContent::Text(text) => {
+                assert_eq!(text.kind, TextKind::Notice);
+                assert_eq!(text.body, "build passed");
+                assert_eq!(text.relates_to, None);
+            }
+            other => panic!("expected a text message, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn best_thumbnail_prefers_the_embedded_one() {
+        let image = message(json!({
+            "msgtype": "m.image",
+            "body": "cat.png",
+            "url": "mxc://example.org/cat",
+            "info": {
+                "w": 1600,
+                "h": 1200,
+                "thumbnail_url": "mxc://example.org/cat-thumb",
+                "thumbnail_info": { "w": 160, "h": 120, "mimetype": "image/png" },
+            },
+        }));
+        let MessageContent::Image(image) = image.parsed_content() else {
+            panic!("expected an image");
+        };
+
+        let (url, thumbnail) = image.info.best_thumbnail(320, 240).unwrap();
+        assert_eq!(url, "mxc://example.org/cat-thumb");
+        assert_eq!((thumbnail.width, thumbnail.height), (160, 120));
+        assert_eq!(thumbnail.mime_type, "image/png");
+    }
+
+    #[test]
+    fn best_thumbnail_is_none_without_one() {
+        let image = message(json!({
+            "msgtype": "m.image",
+            "body": "cat.png",
+            "url": "mxc://example.org/cat",
+            "info": { "w": 1600, "h": 1200 },
+        }));
+        let MessageContent::Image(image) = image.parsed_content() else {
+            panic!("expected an image");
+        };
+
+        assert!(image.info.best_thumbnail(320, 240).is_none());
+        assert!(VideoInfo::default().best_thumbnail(320, 240).is_none());
+    }
+
+    #[test]
+    fn markdown_message_formats_only_when_needed() {
+        let bold = markdown_message(MSGTYPE_TEXT.to_string(), "**hello** there");
+        assert_eq!(bold.body, "**hello** there");
+        assert_eq!(bold.formatted_body, "<strong>hello</strong> there");
+        assert_eq!(bold.format, FORMAT_HTML);
+
+        let code = markdown_message(MSGTYPE_TEXT.to_string(), "run `cargo test`");
+        assert_eq!(code.formatted_body, "run <code>cargo test</code>");
+        assert_eq!(code.format, FORMAT_HTML);
+
+        let bare = markdown_message(MSGTYPE_NOTICE.to_string(), "just words & <stuff>");
+        assert_eq!(bare.body, "just words & <stuff>");
+        assert!(bare.formatted_body.is_empty());
+        assert!(bare.format.is_empty());
+        assert_eq!(bare.kind, TextKind::Notice);
+    }
+
+    #[test]
+    fn replies_are_read_and_built() {
+        let reply = message(json!({
+            "msgtype": "m.text",
+            "body": "> <@bob:example.org> hi\n\nhello",
+            "m.relates_to": { "m.in_reply_to": { "event_id": "$parent" } },
+        }));
+        assert_eq!(reply.reply_target().as_deref(), Some("$parent"));
+
+        let thread = message(json!({
+            "msgtype": "m.text",
+            "body": "in thread",
+            "m.relates_to": { "rel_type": "m.thread", "event_id": "$root" },
+        }));
+        assert_eq!(thread.reply_target(), None);
+        assert_eq!(message(json!({ "body": "plain" })).reply_target(), None);
+
+        let MessageContent::Text(text) =
+            message(json!({ "msgtype": "m.text", "body": "hello" })).parsed_content()
+        else {
+            panic!("expected a text message");
+        };
+        let built = text.reply_to("$parent", "@bob:example.org", "hi\nthere");
+        assert_eq!(built.body, "> <@bob:example.org> hi\n> there\n\nhello");
+        assert_eq!(
+            built.relates_to,
+            Some(json!({ "m.in_reply_to": { "event_id": "$parent" } }))
+        );
+    }
+
+    #[test]
+    fn edits_replace_the_body() {
+        let mut original = message(json!({ "msgtype": "m.text", "body": "helo" }));
+        original.id = "$original".to_string();
+
+        let mut edit = message(json!({
+            "msgtype": "m.text",
+            "body": "* hello",
+            "m.new_content": { "msgtype": "m.text", "body": "hello" },
+            "m.relates_to": { "rel_type": "m.replace", "event_id": "$original" },
+        }));
+        edit.id = "$edit".to_string();
+        assert_eq!(edit.edit_target().as_deref(), Some("$original"));
+
+        let edited = original.apply_edit(&edit);
+        assert_eq!(edited.content[KEY_BODY], "hello");
+        assert_eq!(edited.id, "$original");
+
+        let annotation = message(json!({
+            "m.relates_to": { "rel_type": "m.annotation", "event_id": "$original", "key": "👍" },
+        }));
+        assert_eq!(annotation.edit_target(), None);
+        assert_eq!(original.apply_edit(&annotation).content[KEY_BODY], "helo");
+    }
+
+    fn sync_event() -> Value {
+        json!({
+            "type": "m.room.message",
+            "event_id": "$message",
+            "sender": "@alice:example.org",
+            "origin_server_ts": 1_718_000_000_000_i64,
+            "content": { "msgtype": "m.text", "body": "Hello" },
+            "unsigned": { "age": 250 },
+        })
+    }
+
+    #[test]
+    fn sync_json_parses_state_and_message_events() {
+        let message = Event::from_sync_json(&sync_event()).unwrap();
+        assert_eq!(message.etype, EVENT_MESSAGE);
+        assert_eq!(message.id, "$message");
+        assert_eq!(message.sender, "@alice:example.org");
+        assert_eq!(message.time_stamp, 1_718_000_000_000);
+        assert_eq!(message.content[KEY_BODY], "Hello");
+        assert_eq!(message.unsigned["age"], 250);
+        assert!(!message.is_state_event());
+
+        let name = Event::from_sync_json(&json!({
+            "type": "m.room.name",
+            "state_key": "",
+            "event_id": "$name",
+            "sender": "@alice:example.org",
+            "origin_server_ts": 1,
+            "content": { "name": "Book club" },
+            "unsigned": { "prev_content": { "name": "Books" } },
+        }))
+        .unwrap();
+        assert!(name.is_state_event());
+        assert_eq!(name.state_key, "");
+        assert_eq!(name.content["name"], "Book club");
+        assert_eq!(name.prev_content["name"], "Books");
+    }
+
+    #[test]
+    fn sync_json_needs_the_required_fields() {
+        for field in ["type", "event_id", "sender", "origin_server_ts", "content"] {
+            let mut event = sync_event();
+            event.as_object_mut().unwrap().remove(field);
+
+            assert_eq!(
+                Event::from_sync_json(&event).unwrap_err(),
+                ParseError::MissingField(field)
+            );
+        }
+
+        let mut event = sync_event();
+        event["sender"] = json!(42);
+        assert_eq!(
+            Event::from_sync_json(&event).unwrap_err(),
+            ParseError::InvalidField("sender")
+        );
+        assert_eq!(
+            Event::from_sync_json(&json!([])).unwrap_err(),
+            ParseError::NotAnObject
+        );
+    }
+
+    #[test]
+    fn encrypted_images_carry_the_file_key_and_iv() {
+        let event: Event<String> = Event::from_sync_json(&json!({
+            "type": "m.room.message",
+            "event_id": "$image",
+            "sender": "@alice:example.org",
+            "origin_server_ts": 1,
+            "content": {
+                "msgtype": "m.image",
+                "body": "cat.png",
+                "file": {
+                    "url": "mxc://example.org/encrypted",
+                    "key": {
+                        "kty": "oct",
+                        "key_ops": ["encrypt", "decrypt"],
+                        "alg": "A256CTR",
+                        "k": "qcHVMSgYg-71CauWBezXI5qkaRb0LuIy-Wx5kIaHMIA",
+                        "ext": true,
+                    },
+                    "iv": "X85+XgHN+HEAAAAAAAAAAA",
+                    "hashes": { "sha256": "2CYm4gKDoFwe9OxaeOb47B7S2ZT6iwcjfZNoCSHvOBA" },
+                    "v": "v2",
+                },
+            },
+        }))
+        .unwrap()
+        .into();
+
+        let MessageContent::Image(image) = event.parsed_content() else {
+            panic!("expected an image");
+        };
+        assert_eq!(image.url, None);
+
+        let file = image.file.unwrap();
+        assert_eq!(file.url, "mxc://example.org/encrypted");
+        assert_eq!(file.key.k, "qcHVMSgYg-71CauWBezXI5qkaRb0LuIy-Wx5kIaHMIA");
+        assert_eq!(file.key.alg, "A256CTR");
+        assert_eq!(file.key.key_ops, ["encrypt", "decrypt"]);
+        assert!(file.key.ext);
+        assert_eq!(file.iv, "X85+XgHN+HEAAAAAAAAAAA");
+        assert_eq!(
+            file.hashes["sha256"],
+            "2CYm4gKDoFwe9OxaeOb47B7S2ZT6iwcjfZNoCSHvOBA"
+        );
+        assert_eq!(file.v, "v2");
+    }
+
+    #[test]
+    fn member_content_reads_the_optional_fields() {
+        let member: Event<String> = Event::from_sync_json(&json!({
+            "type": "m.room.member",
+            "state_key": "@alice:example.org",
+            "event_id": "$member",
+            "sender": "@alice:example.org",
+            "origin_server_ts": 1,
+            "content": {
+                "membership": "join",
+                "displayname": "Alice",
+                "avatar_url": "mxc://example.org/alice",
+                "is_direct": true,
+                "reason": "Hi all",
+            },
+        }))
+        .unwrap()
+        .into();
+        assert_eq!(
+            member.member_content(),
+            Some(MemberEventContent {
+                membership: "join".to_string(),
+                displayname: Some("Alice".to_string()),
+                avatar_url: Some("mxc://example.org/alice".to_string()),
+                is_direct: Some(true),
+                reason: Some("Hi all".to_string()),
+            })
+        );
+
+        let mut bare = new_event(STATE_MEMBER, Some("@bob:example.org"));
+        bare.content
+            .insert(KEY_MEMBERSHIP.to_string(), "leave".to_string());
+        assert_eq!(
+            bare.member_content(),
+            Some(MemberEventContent {
+                membership: "leave".to_string(),
+                ..Default::default()
+            })
+        );
+
+        bare.content.clear();
+        assert_eq!(bare.member_content(), None);
+        assert_eq!(new_event(STATE_NAME, Some("")).member_content(), None);
+    }
+
+    fn presence(content: Value) -> Event<String> {
+        Event::from_sync_json(&json!({
+            "type": "m.presence",
+            "event_id": "$presence",
+            "sender": "@alice:example.org",
+            "origin_server_ts": 1,
+            "content": content,
+        }))
+        .unwrap()
+        .into()
+    }
+
+    #[test]
+    fn presence_reads_the_state_and_status() {
+        let online = presence(json!({
+            "presence": "online",
+            "status_msg": "Reading",
+            "last_active_ago": 1000,
+        }));
+        assert_eq!(
+            Presence::from_event(&online),
+            Some(Presence {
+                user_id: "@alice:example.org".to_string(),
+                presence: PresenceState::Online,
+                status_msg: Some("Reading".to_string()),
+                last_active_ago: Some(1000),
+            })
+        );
+
+        let unknown = Presence::from_event(&presence(json!({ "presence": "busy" }))).unwrap();
+        assert_eq!(unknown.presence, PresenceState::Offline);
+        assert_eq!(unknown.status_msg, None);
+        assert_eq!(unknown.last_active_ago, None);
+
+        let message: Event<String> = Event::from_sync_json(&sync_event()).unwrap().into();
+        assert_eq!(Presence::from_event(&message), None);
+    }
+}
@@ -0,0 +1,434 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::room::Room;
+use crate::store::{StoreError, Storer};
+
+const INDEX_FILE: &str = "index.json";
+const ROOMS_DIR: &str = "rooms";
+
+/// Distinguishes temp files written concurrently by the same process.
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Account data content keyed by event type.
+type AccountData<T> = HashMap<String, HashMap<String, T>>;
+
+/// Everything except rooms, which is small enough to rewrite on every change.
+#[derive(Serialize, Deserialize)]
+#[serde(bound(deserialize = "T: DeserializeOwned"), default)]
+struct Index<T> {
+    filters: HashMap<String, String>,
+    next_batch: HashMap<String, String>,
+    prev_batch: HashMap<String, String>,
+    account_data: HashMap<String, AccountData<T>>,
+    room_account_data: HashMap<String, HashMap<String, AccountData<T>>>,
+}
+
+impl<T> Default for Index<T> {
+    fn default() -> Self {
+        Index {
+            filters: HashMap::new(),
+            next_batch: HashMap::new(),
+            prev_batch: HashMap::new(),
+            account_data: HashMap::new(),
+            room_account_data: HashMap::new(),
+        }
+    }
+}
+
+/// A [`Storer`] that keeps each room in its own JSON file under a base directory,
+/// with filters, batch tokens and account data in an `index.json` beside them.
+///
+/// A lighter alternative to [`SqliteStore`](crate::sqlite_store) for tools that
+/// don't want a database. Files are replaced by writing a temp file and renaming
+/// it over the old one, so a crash or a concurrent writer never leaves a file
+/// half-written.
+///
+/// # Examples
+///
+/// ```rust
+/// use matrux::file_store::JsonFileStore;
+/// use matrux::room::Room;
+/// use matrux::store::Storer;
+///
+/// let dir = std::env::temp_dir().join(format!("matrux-doc-{}", std::process::id()));
+///
+/// let mut store: JsonFileStore<String> = JsonFileStore::open(&dir).unwrap();
+/// store.save_room(Room::new("!room:example.org".to_string())).unwrap();
+/// store.save_next_batch("@alice:example.org".to_string(), "s72_1".to_string()).unwrap();
+///
+/// let reopened: JsonFileStore<String> = JsonFileStore::open(&dir).unwrap();
+/// assert!(reopened.load_room("!room:example.org".to_string()).unwrap().is_some());
+/// assert_eq!(
+///     reopened.load_next_batch("@alice:example.org".to_string()).unwrap().as_deref(),
+///     Some("s72_1")
+/// );
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub struct JsonFileStore<T> {
+    base: PathBuf,
+    index: Index<T>,
+}
+
+impl<T: Serialize + DeserializeOwned> JsonFileStore<T> {
+    /// Opens the store in `base`, creating the directory if needed.
+    pub fn open(base: impl AsRef<Path>) -> Result<Self, StoreError> {
+        let base = base.as_ref().to_path_buf();
+        fs::create_dir_all(base.join(ROOMS_DIR))?;
+
+        let index = read_json(&base.join(INDEX_FILE))?.unwrap_or_default();
+
+        Ok(JsonFileStore { base, index })
+    }
+
+    /// The directory the store was opened in.
+    pub fn base(&self) -> &Path {
+        &self.base
+    }
+
+    fn room_path(&self, room_id: &str) -> PathBuf {
+        self.base
+            .join(ROOMS_DIR)
+            .join(format!("{}.json", encode_file_name(room_id)))
+    }
+
+    fn save_index(&self) -> Result<(), StoreError> {
+        write_json(&self.base.join(INDEX_FILE), &self.index)
+    }
+}
+
+impl<T: Clone + Serialize + DeserializeOwned> Storer<T> for JsonFileStore<T> {
+    fn save_filter_id(&mut self, user_id: String, filter_id: String) -> Result<(), StoreError> {
+        self.index.filters.insert(user_id, filter_id);
+        self.save_index()
+    }
+
+    fn load_filter_id(&self, user_id: String) -> Result<Option<String>, StoreError> {
+        Ok(self.index.filters.get(&user_id).cloned())
+    }
+
+    fn save_next_batch(&mut self, user_id: String, next_batch_token: String) -> Result<(), StoreError> {
+        self.index.next_batch.insert(user_id, next_batch_token);
+        self.save_index()
+    }
+
+    fn load_next_batch(&self, user_id: String) -> Result<Option<String>, StoreError> {
+        Ok(self.index.next_batch.get(&user_id).cloned())
+    }
+
+    fn save_prev_batch(
+        &mut self,
+        room_id: String,
+        prev_batch: Option<String>,
+    ) -> Result<(), StoreError> {
+        match prev_batch {
+            Some(prev_batch) => self.index.prev_batch.insert(room_id, prev_batch),
+            None => self.index.prev_batch.remove(&room_id),
+        };
+        self.save_index()
+    }
+
+    fn load_prev_batch(&self, room_id: String) -> Result<Option<String>, StoreError> {
+        Ok(self.index.prev_batch.get(&room_id).cloned())
+    }
+
+    fn save_room(&mut self, room: Room<T>) -> Result<(), StoreError> {
+        write_json(&self.room_path(&room.id), &room)
+    }
+
+    fn load_room(&self, room_id: String) -> Result<Option<Room<T>>, StoreError> {
+        read_json(&self.room_path(&room_id))
+    }
+
+    fn delete_room(&mut self, room_id: &str) -> Result<(), StoreError> {
+        match fs::remove_file(self.room_path(room_id)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+
+        if self.index.prev_batch.remove(room_id).is_some() {
+            self.save_index()?;
+        }
+        Ok(())
+    }
+
+    fn list_room_ids(&self) -> Result<Vec<String>, StoreError> {
+        let mut room_ids = Vec::new();
+
+        for entry in fs::read_dir(self.base.join(ROOMS_DIR))? {
+            let file_name = entry?.file_name();
+            // Skips temp files and anything else that isn't a room.
+            if let Some(room_id) = file_name
+                .to_str()
+                .and_then(|name| name.strip_suffix(".json"))
+                .and_then(decode_file_name)
+            {
+                room_ids.push(room_id);
+            }
+        }
+
+        room_ids.sort();
+        Ok(room_ids)
+    }
+
+    fn clear(&mut self) -> Result<(), StoreError> {
+        for room_id in self.list_room_ids()? {
+            fs::remove_file(self.room_path(&room_id))?;
+        }
+
+        self.index = Index::default();
+        self.save_index()
+    }
+
+    fn save_account_data(
+        &mut self,
+        user_id: String,
+        etype: String,
+        content: HashMap<String, T>,
+    ) -> Result<(), StoreError> {
+        self.index
+            .account_data
+            .entry(user_id)
+            .or_default()
+            .insert(etype, content);
+        self.save_index()
+    }
+
+    fn load_account_data(
+        &self,
+        user_id: String,
+        etype: String,
+    ) -> Result<Option<HashMap<String, T>>, StoreError> {
+        Ok(self
+            .index
+            .account_data
+            .get(&user_id)
+            .and_then(|account_data| account_data.get(&etype))
+            .cloned())
+    }
+
+    fn save_room_account_data(
+        &mut self,
+        user_id: String,
+        room_id: String,
+        etype: String,
+        content: HashMap<String, T>,
+    ) -> Result<(), StoreError> {
+        self.index
+            .room_account_data
+            .entry(user_id)
+            .or_default()
+            .entry(room_id)
+            .or_default()
+            .insert(etype, content);
+        self.save_index()
+    }
+
+    fn load_room_account_data(
+        &self,
+        user_id: String,
+        room_id: String,
+        etype: String,
+    ) -> Result<Option<HashMap<String, T>>, StoreError> {
+        Ok(self
+            .index
+            .room_account_data
+            .get(&user_id)
+            .and_then(|rooms| rooms.get(&room_id))
+            .and_then(|account_data| account_data.get(&etype))
+            .cloned())
+    }
+}
+
+/// Reads a JSON file, returning `None` if it doesn't exist.
+fn read_json<V: DeserializeOwned>(path: &Path) -> Result<Option<V>, StoreError> {
+    let serialized = match fs::read_to_string(path) {
+        Ok(serialized) => serialized,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    serde_json::from_str(&serialized)
+        .map(Some)
+        .map_err(|err| StoreError::Serialization(err.to_string()))
+}
+
+/// Writes `value` to a temp file next to `path` and renames it into place.
+fn write_json<V: Serialize>(path: &Path, value: &V) -> Result<(), StoreError> {
+    let serialized =
+        serde_json::to_string(value).map_err(|err| StoreError::Serialization(err.to_string()))?;
+
+    let temp_path = path.with_extension(format!(
+        "{}.{}.tmp",
+        process::id(),
+        TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed),
+    ));
+
+    fs::write(&temp_path, serialized)?;
+    fs::rename(&temp_path, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temp_path);
+    })?;
+    Ok(())
+}
+
+/// Percent-encodes everything but lowercase ASCII letters, digits, `-` and `_`, so
+/// room ids are valid file names everywhere and ids differing only in case don't
+/// collide on case-insensitive file systems.
+fn encode_file_name(room_id: &str) -> String {
+    room_id
+        .bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn decode_file_name(name: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(name.len());
+    let mut rest = name.as_bytes();
+
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else if byte.is_ascii_lowercase() || byte.is_ascii_digit() || byte == b'-' || byte == b'_' {
+            bytes.push(byte);
+            rest = tail;
+        } else {
+            return None;
+        }
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::STATE_TOPIC;
+    use crate::events::EventBuilder;
+
+    /// A directory of its own for each test, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let path = std::env::temp_dir()
+                .join(format!("matrux-file-store-{}-{}", name, process::id()));
+            let _ = fs::remove_dir_all(&path);
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn room_with_topic(room_id: &str, topic: &str) -> Room<String> {
+        let mut content = HashMap::new();
+        content.insert("topic".to_string(), topic.to_string());
+
+        let mut room = Room::new(room_id.to_string());
+        room.update_state(
+            EventBuilder::new()
+                .etype(STATE_TOPIC.to_string())
+                .state_key(String::new())
+                .content(content)
+                .build(),
+        );
+        room
+    }
+
+    #[test]
+    fn rooms_survive_reopening_the_store() {
+        let dir = TempDir::new("reopen");
+        let room_id = "!Room/With:Odd!Chars:example.org".to_string();
+        let user_id = "@alice:example.org".to_string();
+
+        {
+            let mut store = JsonFileStore::<String>::open(&dir.0).unwrap();
+            store.save_room(room_with_topic(&room_id, "Weekly sync")).unwrap();
+            store.save_prev_batch(room_id.clone(), Some("p1".to_string())).unwrap();
+            store.save_filter_id(user_id.clone(), "f1".to_string()).unwrap();
+            store
+                .save_room_account_data(
+                    user_id.clone(),
+                    room_id.clone(),
+                    "m.tag".to_string(),
+                    HashMap::new(),
+                )
+                .unwrap();
+        }
+
+        let store = JsonFileStore::<String>::open(&dir.0).unwrap();
+        let room = store.load_room(room_id.clone()).unwrap().unwrap();
+
+        assert_eq!(room.id, room_id);
+        assert_eq!(room.topic().as_deref(), Some("Weekly sync"));
+        assert_eq!(store.list_room_ids().unwrap(), vec![room_id.clone()]);
+        assert_eq!(store.load_prev_batch(room_id.clone()).unwrap().as_deref(), Some("p1"));
+        assert_eq!(store.load_filter_id(user_id.clone()).unwrap().as_deref(), Some("f1"));
+        assert_eq!(
+            store.load_room_account_data(user_id, room_id, "m.tag".to_string()).unwrap(),
+            Some(HashMap::new())
+        );
+    }
+
+    #[test]
+    fn deleting_a_room_removes_its_file_and_prev_batch() {
+        let dir = TempDir::new("delete");
+        let room_id = "!room:example.org".to_string();
+
+        let mut store = JsonFileStore::<String>::open(&dir.0).unwrap();
+        store.save_room(Room::new(room_id.clone())).unwrap();
+        store.save_prev_batch(room_id.clone(), Some("p1".to_string())).unwrap();
+        fs::write(dir.0.join(ROOMS_DIR).join("stray.123.tmp"), "{}").unwrap();
+
+        store.delete_room(&room_id).unwrap();
+        store.delete_room(&room_id).unwrap();
+
+        let store = JsonFileStore::<String>::open(&dir.0).unwrap();
+        assert!(store.load_room(room_id.clone()).unwrap().is_none());
+        assert_eq!(store.load_prev_batch(room_id).unwrap(), None);
+        assert!(store.list_room_ids().unwrap().is_empty());
+    }
+
+    #[test]
+    fn file_names_round_trip() {
+        for room_id in [
+            "!room:example.org",
+            "!Room:Example.org",
+            "!a/b:example.org",
+            "!..:example.org",
+            "!caf\u{e9}:example.org",
+        ] {
+            let encoded = encode_file_name(room_id);
+
+            assert!(!encoded.contains(['/', ':', '!', '.']), "{}", encoded);
+            assert_eq!(decode_file_name(&encoded).as_deref(), Some(room_id));
+        }
+
+        assert_ne!(encode_file_name("!room:a.org"), encode_file_name("!Room:a.org"));
+        assert_eq!(encode_file_name("!a/b:c"), "%21a%2Fb%3Ac");
+    }
+
+    #[test]
+    fn other_file_names_are_not_rooms() {
+        assert_eq!(decode_file_name("Room"), None);
+        assert_eq!(decode_file_name("room.123.tmp"), None);
+        assert_eq!(decode_file_name("%2"), None);
+        assert_eq!(decode_file_name("%ZZ"), None);
+        assert_eq!(decode_file_name("%FF"), None);
+    }
+}
@@ -0,0 +1,104 @@
+use serde_json::{json, Map, Value};
+
+/// Builds the filter JSON sent to `/sync` to cut down what the server returns.
+///
+/// Only the options that were set end up in the JSON; the server's defaults apply
+/// to everything else.
+///
+/// # Examples
+///
+/// ```rust
+/// use matrux::filter::Filter;
+/// use serde_json::json;
+///
+/// let filter = Filter::new()
+///     .room_timeline_limit(20)
+///     .lazy_load_members(true)
+///     .include_presence(false);
+///
+/// assert_eq!(
+///     filter.to_json(),
+///     json!({
+///         "room": {
+///             "timeline": { "limit": 20 },
+///             "state": { "lazy_load_members": true }
+///         },
+///         "presence": { "not_types": ["*"] }
+///     })
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filter {
+    timeline_limit: Option<u32>,
+    include_leave: Option<bool>,
+    lazy_load_members: Option<bool>,
+    types: Option<Vec<String>>,
+    include_presence: Option<bool>,
+}
+
+impl Filter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The maximum number of timeline events per room in each sync.
+    pub fn room_timeline_limit(mut self, limit: u32) -> Self {
+        self.timeline_limit = Some(limit);
+        self
+    }
+
+    /// Whether rooms the user has left are included.
+    pub fn include_leave(mut self, include_leave: bool) -> Self {
+        self.include_leave = Some(include_leave);
+        self
+    }
+
+    /// Only send member events for the senders of timeline events.
+    pub fn lazy_load_members(mut self, lazy_load_members: bool) -> Self {
+        self.lazy_load_members = Some(lazy_load_members);
+        self
+    }
+
+    /// Restricts timeline events to these event types.
+    pub fn types(mut self, types: &[&str]) -> Self {
+        self.types = Some(types.iter().map(|etype| etype.to_string()).collect());
+        self
+    }
+
+    /// Whether presence updates are included.
+    pub fn include_presence(mut self, include_presence: bool) -> Self {
+        self.include_presence = Some(include_presence);
+        self
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut timeline = Map::new();
+        if let Some(limit) = self.timeline_limit {
+            timeline.insert("limit".to_string(), json!(limit));
+        }
+        if let Some(types) = &self.types {
+            timeline.insert("types".to_string(), json!(types));
+        }
+
+        let mut room = Map::new();
+        if let Some(include_leave) = self.include_leave {
+            room.insert("include_leave".to_string(), json!(include_leave));
+        }
+        if !timeline.is_empty() {
+            room.insert("timeline".to_string(), Value::Object(timeline));
+        }
+        if let Some(lazy_load_members) = self.lazy_load_members {
+            room.insert("state".to_string(), json!({ "lazy_load_members": lazy_load_members }));
+        }
+
+        let mut filter = Map::new();
+        if !room.is_empty() {
+            filter.insert("room".to_string(), Value::Object(room));
+        }
+        if self.include_presence == Some(false) {
+            filter.insert("presence".to_string(), json!({ "not_types": ["*"] }));
+        }
+
+        Value::Object(filter)
+    }
+}
@@ -0,0 +1,568 @@
+use std::borrow::Cow;
+
+use html_escape::{decode_html_entities, encode_double_quoted_attribute};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref ATTRIBUTE_REGEX: Regex = Regex::new(
+        r#"([a-zA-Z_:][-a-zA-Z0-9_:.]*)(?:\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'=<>`]+)))?"#
+    )
+    .unwrap();
+}
+
+enum Token<'a> {
+    Text(&'a str),
+    Tag {
+        /// Lowercased, which only allocates if the HTML wasn't.
+        name: Cow<'a, str>,
+        closing: bool,
+        /// Everything between the tag name and the closing `>`.
+        attributes: &'a str,
+    },
+}
+
+/// Splits HTML into text and tags.
+///
+/// Quoted attribute values may contain `>`. Comments, doctypes and processing
+/// instructions are skipped, `<![CDATA[...]]>` sections become text, and
+/// `<script>` and `<style>` elements are skipped along with their contents. A `<`
+/// that doesn't start a tag, or a tag that is never closed, is left as text.
+struct Tokens<'a> {
+    html: &'a str,
+    position: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(html: &'a str) -> Self {
+        Tokens { html, position: 0 }
+    }
+
+    /// Moves past the end of `terminator`, or to the end of the input without one.
+    fn skip_past(&mut self, terminator: &str) {
+        self.position = match self.html[self.position..].find(terminator) {
+            Some(offset) => self.position + offset + terminator.len(),
+            None => self.html.len(),
+        };
+    }
+
+    /// The offset of the `>` closing the tag that starts at `start`, ignoring any
+    /// inside quoted attribute values.
+    fn tag_end(&self, start: usize) -> Option<usize> {
+        let mut quote = None;
+        let mut previous = 0;
+
+        for (offset, byte) in self.html.as_bytes()[start..].iter().copied().enumerate() {
+            match quote {
+                Some(open) if byte == open => quote = None,
+                Some(_) => {}
+                None if byte == b'>' => return Some(start + offset),
+                None if (byte == b'"' || byte == b'\'') && previous == b'=' => quote = Some(byte),
+                None => {}
+            }
+
+            if !byte.is_ascii_whitespace() {
+                previous = byte;
+            }
+        }
+
+        None
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        loop {
+            let rest = &self.html[self.position..];
+
+            if rest.is_empty() {
+                return None;
+            }
+
+            if !rest.starts_with('<') {
+                let end = rest.find('<').unwrap_or(rest.len());
+                self.position += end;
+                return Some(Token::Text(&rest[..end]));
+            }
+
+            if rest.starts_with("<!--") {
+                self.skip_past("-->");
+                continue;
+            }
+
+            if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+                let end = cdata.find("]]>").unwrap_or(cdata.len());
+                self.skip_past("]]>");
+                return Some(Token::Text(&cdata[..end]));
+            }
+
+            if rest.starts_with("<!") || rest.starts_with("<?") {
+                self.skip_past(">");
+                continue;
+            }
+
+            let closing = rest.starts_with("</");
+            let name_start = self.position + if closing { 2 } else { 1 };
+            let name_len = self.html[name_start..]
+                .char_indices()
+                .find(|(index, c)| !(c.is_ascii_alphabetic() || (*index > 0 && c.is_ascii_digit())))
+                .map_or(self.html.len() - name_start, |(index, _)| index);
+
+            let end = match self.tag_end(name_start + name_len) {
+                Some(end) if name_len > 0 => end,
+                _ => {
+                    self.position += 1;
+                    return Some(Token::Text("<"));
+                }
+            };
+
+            let name = &self.html[name_start..name_start + name_len];
+            let name = if name.bytes().any(|byte| byte.is_ascii_uppercase()) {
+                Cow::Owned(name.to_ascii_lowercase())
+            } else {
+                Cow::Borrowed(name)
+            };
+            let attributes = &self.html[name_start + name_len..end];
+            self.position = end + 1;
+
+            if !closing && (name == "script" || name == "style") {
+                let close = format!("</{}", name);
+                self.position = match find_ignore_ascii_case(&self.html[self.position..], &close) {
+                    Some(offset) => {
+                        let close_start = self.position + offset;
+                        self.tag_end(close_start)
+                            .map_or(self.html.len(), |end| end + 1)
+                    }
+                    None => self.html.len(),
+                };
+                continue;
+            }
+
+            return Some(Token::Tag {
+                name,
+                closing,
+                attributes,
+            });
+        }
+    }
+}
+
+/// The byte offset of the first match of `needle` in `haystack`, ignoring ASCII case.
+fn find_ignore_ascii_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .as_bytes()
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+/// Tags allowed in `formatted_body`, as recommended by the Matrix client-server spec.
+const ALLOWED_TAGS: &[&str] = &[
+    "font",
+    "del",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "blockquote",
+    "p",
+    "a",
+    "ul",
+    "ol",
+    "sup",
+    "sub",
+    "li",
+    "b",
+    "i",
+    "u",
+    "strong",
+    "em",
+    "strike",
+    "s",
+    "code",
+    "hr",
+    "br",
+    "div",
+    "table",
+    "thead",
+    "tbody",
+    "tr",
+    "th",
+    "td",
+    "caption",
+    "pre",
+    "span",
+    "img",
+    "details",
+    "summary",
+];
+
+/// URL schemes allowed in links.
+const ALLOWED_LINK_SCHEMES: &[&str] = &["http", "https", "ftp", "mailto", "magnet"];
+
+fn is_allowed_attribute(tag: &str, attribute: &str) -> bool {
+    match tag {
+        "font" => matches!(attribute, "data-mx-bg-color" | "data-mx-color" | "color"),
+        "span" => matches!(
+            attribute,
+            "data-mx-bg-color" | "data-mx-color" | "data-mx-spoiler"
+        ),
+        "a" => matches!(attribute, "name" | "target" | "href"),
+        "img" => matches!(attribute, "width" | "height" | "alt" | "title" | "src"),
+        "ol" => attribute == "start",
+        "code" => attribute == "class",
+        _ => false,
+    }
+}
+
+fn is_allowed_value(tag: &str, attribute: &str, value: &str) -> bool {
+    let value = value.trim();
+
+    match (tag, attribute) {
+        ("a", "href") => match value.split_once(':') {
+            Some((scheme, _)) if !scheme.contains('/') => {
+                // Browsers ignore whitespace and control characters inside a scheme.
+                let scheme: String = scheme
+                    .chars()
+                    .filter(|c| !c.is_ascii_whitespace() && !c.is_ascii_control())
+                    .collect();
+                ALLOWED_LINK_SCHEMES.contains(&scheme.to_ascii_lowercase().as_str())
+            }
+            _ => true,
+        },
+        // Images may only point at content in the homeserver's media repository.
+        ("img", "src") => value.starts_with("mxc://"),
+        ("code", "class") => value.starts_with("language-"),
+        _ => true,
+    }
+}
+
+/// Removes everything from `input` that isn't on the Matrix-recommended allowlist of
+/// tags and attributes.
+///
+/// `<script>` and `<style>` elements are dropped along with their contents, other
+/// disallowed tags are unwrapped, and attributes such as event handlers or
+/// `javascript:` links are stripped from the tags that are kept.
+pub fn sanitize_html(input: &str) -> String {
+    let mut sanitized = String::new();
+
+    for token in Tokens::new(input) {
+        let (tag, closing, attributes) = match token {
+            // A stray `<` must not be able to start a tag once the output is parsed again.
+            Token::Text(text) => {
+                sanitized.push_str(&text.replace('<', "&lt;"));
+                continue;
+            }
+            Token::Tag {
+                name,
+                closing,
+                attributes,
+            } => (name, closing, attributes),
+        };
+
+        if !ALLOWED_TAGS.contains(&tag.as_ref()) {
+            continue;
+        }
+
+        if closing {
+            sanitized.push_str(&format!("</{}>", tag));
+            continue;
+        }
+
+        sanitized.push_str(&format!("<{}", tag));
+
+        for attribute in ATTRIBUTE_REGEX.captures_iter(attributes) {
+            let name = attribute[1].to_ascii_lowercase();
+            let value = attribute
+                .get(2)
+                .or_else(|| attribute.get(3))
+                .or_else(|| attribute.get(4))
+                .map_or("", |value| value.as_str());
+            let value = decode_html_entities(value);
+
+            if is_allowed_attribute(&tag, &name) && is_allowed_value(&tag, &name, &value) {
+                sanitized.push_str(&format!(
+                    " {}=\"{}\"",
+                    name,
+                    encode_double_quoted_attribute(&value)
+                ));
+            }
+        }
+
+        if attributes.trim_end().ends_with('/') {
+            sanitized.push_str(" /");
+        }
+
+        sanitized.push('>');
+    }
+
+    sanitized
+}
+
+/// Appends the plain-text rendering of HTML to `output`, tracking where lines start
+/// so quoted lines can be prefixed.
+struct PlainText<'a> {
+    output: &'a mut String,
+    quote_depth: usize,
+    pre_depth: usize,
+    at_line_start: bool,
+    pending_space: bool,
+}
+
+impl<'a> PlainText<'a> {
+    fn new(output: &'a mut String) -> Self {
+        PlainText {
+            output,
+            quote_depth: 0,
+            pre_depth: 0,
+            at_line_start: true,
+            pending_space: false,
+        }
+    }
+
+    fn start_line(&mut self) {
+        if self.at_line_start {
+            for _ in 0..self.quote_depth {
+                self.output.push_str("> ");
+            }
+            self.at_line_start = false;
+            self.pending_space = false;
+        }
+    }
+
+    fn newline(&mut self) {
+        self.output.push('\n');
+        self.at_line_start = true;
+        self.pending_space = false;
+    }
+
+    fn end_line(&mut self) {
+        if !self.at_line_start {
+            self.newline();
+        }
+    }
+
+    fn push_text(&mut self, raw: &str) {
+        for c in decode_html_entities(raw).chars() {
+            if self.pre_depth > 0 {
+                if c == '\n' {
+                    self.newline();
+                } else {
+                    self.start_line();
+                    self.output.push(c);
+                }
+            } else if c.is_whitespace() {
+                // Whitespace in HTML collapses, and is dropped at the start of a line.
+                self.pending_space = !self.at_line_start;
+            } else {
+                if self.pending_space {
+                    self.output.push(' ');
+                }
+                self.start_line();
+                self.pending_space = false;
+                self.output.push(c);
+            }
+        }
+    }
+
+    fn push_tag(&mut self, tag: &str, closing: bool) {
+        match (tag, closing) {
+            ("br", _) => {
+                self.start_line();
+                self.newline();
+            }
+            ("li", false) => {
+                self.end_line();
+                self.start_line();
+                self.output.push_str("- ");
+            }
+            ("blockquote", false) => {
+                self.end_line();
+                self.quote_depth += 1;
+            }
+            ("blockquote", true) => {
+                self.end_line();
+                self.quote_depth = self.quote_depth.saturating_sub(1);
+            }
+            ("pre", _) => {
+                self.end_line();
+                self.pre_depth = if closing {
+                    self.pre_depth.saturating_sub(1)
+                } else {
+                    self.pre_depth + 1
+                };
+            }
+            ("hr", _) => {
+                self.end_line();
+                self.start_line();
+                self.output.push_str("---");
+                self.newline();
+            }
+            ("td" | "th", false) => self.pending_space = !self.at_line_start,
+            (
+                "p" | "div" | "li" | "ul" | "ol" | "table" | "tr" | "h1" | "h2" | "h3" | "h4"
+                | "h5" | "h6" | "details" | "summary",
+                _,
+            ) => self.end_line(),
+            _ => {}
+        }
+    }
+}
+
+/// Renders HTML as the plain text shown by clients that don't support formatting,
+/// for use as a message's `body`.
+///
+/// Line breaks and block elements start new lines, list items become `- ` bullets
+/// and lines inside `<blockquote>` are prefixed with `> `. Other tags are dropped
+/// and entities are decoded.
+pub fn html_to_plain_text(html: &str) -> String {
+    let mut text = String::new();
+    strip_html_into(html, &mut text);
+    text
+}
+
+/// Like [`html_to_plain_text`], but appends the text to `out` instead of returning
+/// it, so a buffer can be reused across messages.
+///
+/// # Examples
+///
+/// ```rust
+/// use matrux::html::strip_html_into;
+///
+/// let mut out = String::new();
+/// for (html, expected) in [
+///     ("<p>Hello <b>world</b></p>", "Hello world"),
+///     ("<blockquote>quoted<br>twice</blockquote>reply", "> quoted\n> twice\nreply"),
+///     ("<UL><LI>one</LI><LI>two &amp; three</LI></UL>", "- one\n- two & three"),
+///     ("<pre>  keep\n  spacing</pre>", "  keep\n  spacing"),
+/// ] {
+///     out.clear();
+///     strip_html_into(html, &mut out);
+///     assert_eq!(out, expected);
+/// }
+/// ```
+pub fn strip_html_into(input: &str, out: &mut String) {
+    let start = out.len();
+    let mut text = PlainText::new(out);
+
+    for token in Tokens::new(input) {
+        match token {
+            Token::Text(raw) => text.push_text(raw),
+            Token::Tag { name, closing, .. } => text.push_tag(&name, closing),
+        }
+    }
+
+    let end = start + out[start..].trim_end().len();
+    out.truncate(end);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script_contents_are_dropped_whatever_the_case() {
+        assert_eq!(
+            sanitize_html("<b>a</b><SCRIPT>alert(1)</ScRiPt><i>b</i>"),
+            "<b>a</b><i>b</i>"
+        );
+        assert_eq!(sanitize_html("a<style>b { color: red }</STYLE >c"), "ac");
+        assert_eq!(sanitize_html("a<script>never closed"), "a");
+    }
+
+    #[test]
+    fn event_handlers_are_stripped() {
+        assert_eq!(
+            sanitize_html(r#"<b onclick="steal()" ONMOUSEOVER='steal()'>bold</b>"#),
+            "<b>bold</b>"
+        );
+        assert_eq!(
+            sanitize_html(r#"<img src="mxc://example.org/cat" onerror=steal() alt="cat">"#),
+            r#"<img src="mxc://example.org/cat" alt="cat">"#
+        );
+    }
+
+    #[test]
+    fn disguised_javascript_links_are_stripped() {
+        for href in [
+            "javascript:alert(1)",
+            "JavaScript:alert(1)",
+            "  javascript:alert(1)",
+            "java\tscript:alert(1)",
+            "java\nscript:alert(1)",
+            "java&#115;cript:alert(1)",
+            "&#x6A;avascript:alert(1)",
+            "javascript&colon;alert(1)",
+            "&#0000106avascript:alert(1)",
+        ] {
+            let html = format!(r#"<a href="{}">link</a>"#, href);
+            assert_eq!(sanitize_html(&html), "<a>link</a>", "{:?}", href);
+        }
+
+        assert_eq!(
+            sanitize_html(r#"<a href="https://example.org/?a=1&amp;b=2">link</a>"#),
+            r#"<a href="https://example.org/?a=1&amp;b=2">link</a>"#
+        );
+    }
+
+    #[test]
+    fn styles_are_removed() {
+        assert_eq!(
+            sanitize_html(r#"<span style="position: fixed" data-mx-color="red">hi</span>"#),
+            r#"<span data-mx-color="red">hi</span>"#
+        );
+        assert_eq!(
+            sanitize_html("<p>a</p><style>p { display: none }</style><p>b</p>"),
+            "<p>a</p><p>b</p>"
+        );
+    }
+
+    #[test]
+    fn greater_than_in_comments_and_quoted_values_does_not_end_the_tag() {
+        assert_eq!(sanitize_html("a<!-- x > y -->b"), "ab");
+        assert_eq!(html_to_plain_text("a<!-- <b> > </b> -->b"), "ab");
+        assert_eq!(
+            sanitize_html(r#"<a href="https://example.org/?q=a>b" title='x>y'>link</a>"#),
+            r#"<a href="https://example.org/?q=a&gt;b">link</a>"#
+        );
+        assert_eq!(html_to_plain_text(r#"<img alt="1 > 0">text"#), "text");
+    }
+
+    #[test]
+    fn list_items_become_bullets() {
+        assert_eq!(
+            html_to_plain_text("<p>Shopping:</p><ul><li>eggs</li><li><b>milk</b></li></ul>"),
+            "Shopping:\n- eggs\n- milk"
+        );
+        assert_eq!(
+            html_to_plain_text("<ol>\n  <li>first</li>\n  <li>second</li>\n</ol>"),
+            "- first\n- second"
+        );
+    }
+
+    #[test]
+    fn paragraphs_and_line_breaks_start_new_lines() {
+        assert_eq!(
+            html_to_plain_text("<p>one</p><p>two</p>three<br/>four"),
+            "one\ntwo\nthree\nfour"
+        );
+        assert_eq!(html_to_plain_text("a<br><br>b"), "a\n\nb");
+    }
+
+    #[test]
+    fn quoted_lines_are_prefixed() {
+        assert_eq!(
+            html_to_plain_text(
+                "<blockquote><p>first</p><p>second</p></blockquote><p>reply &lt;3</p>"
+            ),
+            "> first\n> second\nreply <3"
+        );
+        assert_eq!(
+            html_to_plain_text("<blockquote>outer<blockquote>inner</blockquote></blockquote>"),
+            "> outer\n> > inner"
+        );
+    }
+}
@@ -0,0 +1,311 @@
+//! Validated Matrix identifiers.
+//!
+//! Each identifier is a sigil, a localpart and, after a `:`, the name of the server
+//! it was created on: `@user:server`, `!room:server`, `#alias:server`.
+
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::events::ParseError;
+
+/// Identifiers may be at most 255 bytes long, sigil and server name included.
+const MAX_ID_LENGTH: usize = 255;
+
+/// The name of a homeserver: a DNS name, IPv4 address or bracketed IPv6 address,
+/// optionally followed by `:port`.
+///
+/// # Examples
+///
+/// ```rust
+/// use matrux::ids::ServerName;
+///
+/// let name = ServerName::parse("[::1]:8448").unwrap();
+///
+/// assert_eq!(name.host(), "[::1]");
+/// assert_eq!(name.port(), Some(8448));
+/// assert!(ServerName::parse("matrix .org").is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ServerName {
+    name: String,
+    port: Option<u16>,
+    /// Byte length of the host, which is the whole name unless there's a port.
+    host_len: usize,
+}
+
+impl ServerName {
+    pub fn parse(name: &str) -> Result<ServerName, ParseError> {
+        let invalid = || ParseError::InvalidServerName(name.to_string());
+
+        let (host, port) = if name.starts_with('[') {
+            let end = name.find(']').ok_or_else(invalid)?;
+            name[1..end].parse::<Ipv6Addr>().map_err(|_| invalid())?;
+            let (host, rest) = name.split_at(end + 1);
+
+            match rest {
+                "" => (host, None),
+                rest => (host, Some(rest.strip_prefix(':').ok_or_else(invalid)?)),
+            }
+        } else {
+            match name.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (name, None),
+            }
+        };
+
+        let valid_dns_name = || {
+            host.len() <= 255
+                && host
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+        };
+
+        if host.is_empty()
+            || !(host.starts_with('[') || host.parse::<Ipv4Addr>().is_ok() || valid_dns_name())
+        {
+            return Err(invalid());
+        }
+
+        // The grammar allows one to five digits, and no sign.
+        let valid_port =
+            |port: &str| (1..=5).contains(&port.len()) && port.bytes().all(|b| b.is_ascii_digit());
+
+        let port = match port {
+            Some(port) if valid_port(port) => Some(port.parse::<u16>().map_err(|_| invalid())?),
+            Some(_) => return Err(invalid()),
+            None => None,
+        };
+
+        Ok(ServerName {
+            name: name.to_string(),
+            port,
+            host_len: host.len(),
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.name
+    }
+
+    /// The hostname or IP address. IPv6 addresses keep their brackets.
+    pub fn host(&self) -> &str {
+        &self.name[..self.host_len]
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+}
+
+impl fmt::Display for ServerName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+/// Splits `id` into its localpart and server name, checking the sigil and length.
+fn split_id(id: &str, sigil: char) -> Result<(&str, &str), ParseError> {
+    let invalid = || ParseError::InvalidId(id.to_string());
+
+    if id.len() > MAX_ID_LENGTH {
+        return Err(invalid());
+    }
+
+    let (localpart, server_name) = id
+        .strip_prefix(sigil)
+        .and_then(|rest| rest.split_once(':'))
+        .ok_or_else(invalid)?;
+
+    if localpart.is_empty() || ServerName::parse(server_name).is_err() {
+        return Err(invalid());
+    }
+
+    Ok((localpart, server_name))
+}
+
+macro_rules! matrix_id {
+    ($(#[$meta:meta])* $name:ident, $sigil:literal) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub struct $name {
+            id: String,
+            /// Byte offset of the `:` separating the server name.
+            colon: usize,
+        }
+
+        impl $name {
+            pub fn parse(id: &str) -> Result<$name, ParseError> {
+                let (localpart, _) = split_id(id, $sigil)?;
+
+                Ok($name {
+                    id: id.to_string(),
+                    colon: $sigil.len_utf8() + localpart.len(),
+                })
+            }
+
+            pub fn as_str(&self) -> &str {
+                &self.id
+            }
+
+            /// The part between the sigil and the server name.
+            pub fn localpart(&self) -> &str {
+                &self.id[$sigil.len_utf8()..self.colon]
+            }
+
+            /// The server the identifier was created on.
+            pub fn server_name(&self) -> &str {
+                &self.id[self.colon + 1..]
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.id)
+            }
+        }
+    };
+}
+
+matrix_id!(
+    /// A user id, `@localpart:server`.
+    UserId,
+    '@'
+);
+
+matrix_id!(
+    /// A room id, `!opaque:server`.
+    RoomId,
+    '!'
+);
+
+matrix_id!(
+    /// A room alias, `#alias:server`.
+    RoomAliasId,
+    '#'
+);
+
+/// An event id. Room versions 1 and 2 use `$opaque:server`; later versions use a
+/// hash of the event, `$hash`, with no server name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct EventId {
+    id: String,
+}
+
+impl EventId {
+    pub fn parse(id: &str) -> Result<EventId, ParseError> {
+        let Some(rest) = id.strip_prefix('$') else {
+            return Err(ParseError::InvalidId(id.to_string()));
+        };
+
+        if rest.contains(':') {
+            split_id(id, '$')?;
+        } else if rest.is_empty()
+            || id.len() > MAX_ID_LENGTH
+            || rest.chars().any(char::is_whitespace)
+        {
+            return Err(ParseError::InvalidId(id.to_string()));
+        }
+
+        Ok(EventId { id: id.to_string() })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.id
+    }
+
+    /// The server the event was created on, for ids in the old `$opaque:server` form.
+    pub fn server_name(&self) -> Option<&str> {
+        self.id.split_once(':').map(|(_, server_name)| server_name)
+    }
+}
+
+impl fmt::Display for EventId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_ids_are_split_into_localpart_and_server_name() {
+        let user_id = UserId::parse("@alice:example.org").unwrap();
+        assert_eq!(user_id.as_str(), "@alice:example.org");
+        assert_eq!(user_id.localpart(), "alice");
+        assert_eq!(user_id.server_name(), "example.org");
+
+        let room_id = RoomId::parse("!opaque:example.org:8448").unwrap();
+        assert_eq!(room_id.localpart(), "opaque");
+        assert_eq!(room_id.server_name(), "example.org:8448");
+
+        let alias = RoomAliasId::parse("#books:[::1]").unwrap();
+        assert_eq!(alias.localpart(), "books");
+        assert_eq!(alias.server_name(), "[::1]");
+        assert_eq!(alias.to_string(), "#books:[::1]");
+    }
+
+    #[test]
+    fn invalid_ids_are_rejected() {
+        for id in [
+            "",
+            "alice:example.org",
+            "!alice:example.org",
+            "@alice",
+            "@:example.org",
+            "@alice:",
+            "@alice:exa mple.org",
+            "@alice:example.org:port",
+        ] {
+            assert_eq!(
+                UserId::parse(id),
+                Err(ParseError::InvalidId(id.to_string())),
+                "{id}"
+            );
+        }
+
+        let too_long = format!("@{}:example.org", "a".repeat(MAX_ID_LENGTH));
+        assert!(UserId::parse(&too_long).is_err());
+        assert!(RoomId::parse("#room:example.org").is_err());
+        assert!(RoomAliasId::parse("!books:example.org").is_err());
+    }
+
+    #[test]
+    fn event_ids_have_a_server_name_only_in_the_old_form() {
+        let old = EventId::parse("$opaque:example.org").unwrap();
+        assert_eq!(old.server_name(), Some("example.org"));
+
+        let hashed = EventId::parse("$Rqnc-F-dvnEYJTyHq_iKxU2bZ1CI92-kuZq3a5lr5Zg").unwrap();
+        assert_eq!(hashed.server_name(), None);
+        assert_eq!(
+            hashed.as_str(),
+            "$Rqnc-F-dvnEYJTyHq_iKxU2bZ1CI92-kuZq3a5lr5Zg"
+        );
+
+        for id in ["", "$", "opaque", "$has space", "$:example.org", "$opaque:"] {
+            assert!(EventId::parse(id).is_err(), "{id}");
+        }
+    }
+
+    #[test]
+    fn server_names_with_ports() {
+        let name = ServerName::parse("example.org:8448").unwrap();
+        assert_eq!(name.host(), "example.org");
+        assert_eq!(name.port(), Some(8448));
+
+        let name = ServerName::parse("1.2.3.4").unwrap();
+        assert_eq!(name.host(), "1.2.3.4");
+        assert_eq!(name.port(), None);
+
+        for name in [
+            "",
+            "example.org:",
+            "example.org:+80",
+            "example.org:65536",
+            "[::1",
+        ] {
+            assert!(ServerName::parse(name).is_err(), "{name}");
+        }
+    }
+}
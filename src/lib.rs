@@ -0,0 +1,20 @@
+// #![warn(missing_docs)]
+#[cfg(feature = "async")]
+pub mod async_store;
+#[cfg(feature = "async")]
+pub mod client;
+pub mod consts;
+pub mod events;
+#[cfg(feature = "serde")]
+pub mod file_store;
+pub mod filter;
+pub mod html;
+pub mod ids;
+pub mod media;
+pub mod push;
+pub mod response;
+pub mod room;
+#[cfg(feature = "sqlite")]
+pub mod sqlite_store;
+pub mod store;
+pub mod sync;
@@ -1,7 +1,3 @@
-// #![warn(missing_docs)]
-mod events;
-mod store;
-mod room;
 fn main() {
     println!("Hello, world!");
 }
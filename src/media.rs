@@ -0,0 +1,109 @@
+use std::fmt;
+
+use crate::events::ParseError;
+
+/// How the server should shrink an image when making a thumbnail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ThumbnailMethod {
+    /// Fill the requested size exactly, cropping whatever doesn't fit.
+    Crop,
+    /// Fit the image within the requested size, keeping its aspect ratio.
+    #[default]
+    Scale,
+}
+
+impl ThumbnailMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ThumbnailMethod::Crop => "crop",
+            ThumbnailMethod::Scale => "scale",
+        }
+    }
+}
+
+/// A reference to content in a homeserver's media repository, `mxc://server/media_id`.
+///
+/// # Examples
+///
+/// ```rust
+/// use matrux::media::MxcUri;
+///
+/// let uri = MxcUri::parse("mxc://example.org/SEsfnsuifSDFSSEF").unwrap();
+///
+/// assert_eq!(uri.server(), "example.org");
+/// assert_eq!(
+///     uri.download_url("https://matrix.example.org"),
+///     "https://matrix.example.org/_matrix/media/v3/download/example.org/SEsfnsuifSDFSSEF"
+/// );
+/// assert!(MxcUri::parse("https://example.org/image.png").is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MxcUri {
+    server: String,
+    media_id: String,
+}
+
+impl MxcUri {
+    /// Parses an `mxc://` URI, rejecting other schemes and media ids with characters
+    /// outside `A-Z`, `a-z`, `0-9`, `_` and `-`.
+    pub fn parse(uri: &str) -> Result<MxcUri, ParseError> {
+        let invalid = || ParseError::InvalidUri(uri.to_string());
+
+        let (server, media_id) = uri
+            .strip_prefix("mxc://")
+            .and_then(|rest| rest.split_once('/'))
+            .ok_or_else(invalid)?;
+
+        let valid_media_id = !media_id.is_empty()
+            && media_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+        if server.is_empty() || !valid_media_id {
+            return Err(invalid());
+        }
+
+        Ok(MxcUri {
+            server: server.to_string(),
+            media_id: media_id.to_string(),
+        })
+    }
+
+    /// The homeserver the content was uploaded to.
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
+    pub fn media_id(&self) -> &str {
+        &self.media_id
+    }
+
+    /// The URL to download the content from through the homeserver at `base`.
+    pub fn download_url(&self, base: &str) -> String {
+        format!(
+            "{}/_matrix/media/v3/download/{}/{}",
+            base.trim_end_matches('/'),
+            self.server,
+            self.media_id
+        )
+    }
+
+    /// The URL for a thumbnail of the content of about `width` by `height` pixels.
+    pub fn thumbnail_url(&self, base: &str, width: u32, height: u32, method: ThumbnailMethod) -> String {
+        format!(
+            "{}/_matrix/media/v3/thumbnail/{}/{}?width={}&height={}&method={}",
+            base.trim_end_matches('/'),
+            self.server,
+            self.media_id,
+            width,
+            height,
+            method.as_str()
+        )
+    }
+}
+
+impl fmt::Display for MxcUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "mxc://{}/{}", self.server, self.media_id)
+    }
+}
@@ -0,0 +1,380 @@
+use regex::{Regex, RegexBuilder};
+
+use crate::consts::{EVENT_MESSAGE, KEY_MENTIONS, MSGTYPE_NOTICE, STATE_MEMBER};
+use crate::events::{walk_path, Event};
+use crate::room::Room;
+
+/// What a client should do about an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushAction {
+    DontNotify,
+    Notify,
+    /// Notify and highlight, e.g. because the user was mentioned.
+    Highlight,
+}
+
+/// Where an event is evaluated from: the user receiving it and their room.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PushContext {
+    pub user_id: String,
+    /// The user's display name in the room, if they have one.
+    pub display_name: Option<String>,
+    pub member_count: usize,
+}
+
+impl PushContext {
+    /// The context of `user_id` in `room`, from its member state.
+    pub fn for_room(room: &Room<String>, user_id: &str) -> PushContext {
+        PushContext {
+            user_id: user_id.to_string(),
+            display_name: room.resolve_member(user_id).and_then(|member| member.displayname),
+            member_count: room.joined_members().len(),
+        }
+    }
+}
+
+/// A condition of a [`PushRule`]; the rule applies when all of its conditions hold.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PushCondition {
+    /// A field of the event, such as `type` or `content.body`, matches a glob
+    /// pattern. `content.body` matches if any whole word sequence does.
+    EventMatch { key: String, pattern: String },
+    /// The body contains the user's display name as a whole word.
+    ContainsDisplayName,
+    /// The joined member count compares as given, e.g. `"2"`, `"==2"` or `">=10"`.
+    RoomMemberCount { is: String },
+    /// `m.mentions` lists the user.
+    IsUserMention,
+    /// `m.mentions` mentions the room.
+    IsRoomMention,
+    /// The event has no `m.mentions`, so the legacy body-matching rules apply.
+    NoMentions,
+}
+
+/// A push rule: an action taken for events matching every condition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PushRule {
+    pub rule_id: String,
+    pub conditions: Vec<PushCondition>,
+    pub action: PushAction,
+    pub enabled: bool,
+}
+
+impl PushRule {
+    pub fn new(rule_id: &str, conditions: Vec<PushCondition>, action: PushAction) -> PushRule {
+        PushRule {
+            rule_id: rule_id.to_string(),
+            conditions,
+            action,
+            enabled: true,
+        }
+    }
+
+    fn matches(&self, event: &Event<String>, context: &PushContext) -> bool {
+        self.enabled
+            && self
+                .conditions
+                .iter()
+                .all(|condition| condition_matches(condition, event, context))
+    }
+}
+
+/// An ordered list of push rules, evaluated first match wins.
+///
+/// [`PushRuleSet::default`] holds the spec's default rules that can be evaluated
+/// locally: notices and member events don't notify, mentions of the user or the
+/// room highlight, and other messages notify. Enabling `.m.rule.master` silences
+/// everything.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::collections::HashMap;
+/// use matrux::events::EventBuilder;
+/// use matrux::push::{PushAction, PushContext, PushRuleSet};
+///
+/// let context = PushContext {
+///     user_id: "@alice:example.org".to_string(),
+///     display_name: Some("Alice".to_string()),
+///     member_count: 5,
+/// };
+///
+/// let message = |body: &str| {
+///     let mut content = HashMap::new();
+///     content.insert("msgtype".to_string(), "m.text".to_string());
+///     content.insert("body".to_string(), body.to_string());
+///
+///     EventBuilder::new()
+///         .etype("m.room.message".to_string())
+///         .sender("@bob:example.org".to_string())
+///         .content(content)
+///         .build()
+/// };
+///
+/// let rules = PushRuleSet::default();
+/// assert_eq!(rules.evaluate(&message("Thanks, alice!"), &context), PushAction::Highlight);
+/// assert_eq!(rules.evaluate(&message("Lunch is here"), &context), PushAction::Notify);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct PushRuleSet {
+    pub rules: Vec<PushRule>,
+}
+
+impl PushRuleSet {
+    /// Returns the action of the first enabled rule matching `event`. Events with no
+    /// matching rule, and the user's own events, don't notify.
+    pub fn evaluate(&self, event: &Event<String>, context: &PushContext) -> PushAction {
+        if event.sender == context.user_id {
+            return PushAction::DontNotify;
+        }
+
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(event, context))
+            .map_or(PushAction::DontNotify, |rule| rule.action)
+    }
+
+    /// Looks up a rule by id, e.g. to disable it.
+    pub fn rule_mut(&mut self, rule_id: &str) -> Option<&mut PushRule> {
+        self.rules.iter_mut().find(|rule| rule.rule_id == rule_id)
+    }
+}
+
+impl Default for PushRuleSet {
+    fn default() -> Self {
+        let event_match = |key: &str, pattern: &str| PushCondition::EventMatch {
+            key: key.to_string(),
+            pattern: pattern.to_string(),
+        };
+
+        PushRuleSet {
+            rules: vec![
+                PushRule {
+                    enabled: false,
+                    ..PushRule::new(".m.rule.master", Vec::new(), PushAction::DontNotify)
+                },
+                PushRule::new(
+                    ".m.rule.suppress_notices",
+                    vec![event_match("content.msgtype", MSGTYPE_NOTICE)],
+                    PushAction::DontNotify,
+                ),
+                PushRule::new(
+                    ".m.rule.member_event",
+                    vec![event_match("type", STATE_MEMBER)],
+                    PushAction::DontNotify,
+                ),
+                PushRule::new(
+                    ".m.rule.is_user_mention",
+                    vec![PushCondition::IsUserMention],
+                    PushAction::Highlight,
+                ),
+                PushRule::new(
+                    ".m.rule.contains_display_name",
+                    vec![PushCondition::NoMentions, PushCondition::ContainsDisplayName],
+                    PushAction::Highlight,
+                ),
+                PushRule::new(
+                    ".m.rule.is_room_mention",
+                    vec![PushCondition::IsRoomMention],
+                    PushAction::Highlight,
+                ),
+                PushRule::new(
+                    ".m.rule.roomnotif",
+                    vec![PushCondition::NoMentions, event_match("content.body", "@room")],
+                    PushAction::Highlight,
+                ),
+                PushRule::new(
+                    ".m.rule.room_one_to_one",
+                    vec![
+                        PushCondition::RoomMemberCount { is: "2".to_string() },
+                        event_match("type", EVENT_MESSAGE),
+                    ],
+                    PushAction::Notify,
+                ),
+                PushRule::new(
+                    ".m.rule.message",
+                    vec![event_match("type", EVENT_MESSAGE)],
+                    PushAction::Notify,
+                ),
+            ],
+        }
+    }
+}
+
+fn condition_matches(
+    condition: &PushCondition,
+    event: &Event<String>,
+    context: &PushContext,
+) -> bool {
+    match condition {
+        PushCondition::EventMatch { key, pattern } => {
+            let value = match event_field(event, key) {
+                Some(value) => value,
+                None => return false,
+            };
+            glob_regex(pattern, key == "content.body").is_some_and(|regex| regex.is_match(&value))
+        }
+        PushCondition::ContainsDisplayName => match (&context.display_name, event.body_str()) {
+            (Some(name), Some(body)) if !name.is_empty() => {
+                word_regex(&regex::escape(name)).is_some_and(|regex| regex.is_match(&body))
+            }
+            _ => false,
+        },
+        PushCondition::RoomMemberCount { is } => member_count_matches(is, context.member_count),
+        PushCondition::IsUserMention => event.mentioned_users().contains(&context.user_id),
+        PushCondition::IsRoomMention => event.mentions_room(),
+        PushCondition::NoMentions => !event.content.contains_key(KEY_MENTIONS),
+    }
+}
+
+/// The string value at a dotted key such as `type` or `content.msgtype`.
+fn event_field(event: &Event<String>, key: &str) -> Option<String> {
+    match key {
+        "type" => Some(event.etype.clone()),
+        "sender" => Some(event.sender.clone()),
+        "room_id" => Some(event.room_id.clone()),
+        "state_key" => Some(event.state_key.clone()),
+        _ => {
+            let path: Vec<&str> = key.strip_prefix("content.")?.split('.').collect();
+            walk_path(&event.content_value(), &path)?.as_str().map(str::to_string)
+        }
+    }
+}
+
+/// Compiles a glob pattern, where `*` matches any run of characters and `?` any one,
+/// into a case-insensitive regex matching the whole value or, for `words`, any run of
+/// whole words in it.
+fn glob_regex(pattern: &str, words: bool) -> Option<Regex> {
+    let mut glob = String::new();
+
+    for c in pattern.chars() {
+        match c {
+            '*' => glob.push_str(".*?"),
+            '?' => glob.push('.'),
+            _ => glob.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+
+    if words {
+        word_regex(&glob)
+    } else {
+        RegexBuilder::new(&format!("^{}$", glob)).case_insensitive(true).build().ok()
+    }
+}
+
+/// Matches `inner` where it isn't preceded or followed by a letter or digit.
+fn word_regex(inner: &str) -> Option<Regex> {
+    RegexBuilder::new(&format!(r"(?:^|\W)(?:{})(?:\W|$)", inner))
+        .case_insensitive(true)
+        .build()
+        .ok()
+}
+
+fn member_count_matches(is: &str, member_count: usize) -> bool {
+    let split = is.find(|c: char| c.is_ascii_digit()).unwrap_or(is.len());
+    let (operator, count) = is.split_at(split);
+
+    let count: usize = match count.parse() {
+        Ok(count) => count,
+        Err(_) => return false,
+    };
+
+    match operator {
+        "" | "==" => member_count == count,
+        "<" => member_count < count,
+        ">" => member_count > count,
+        "<=" => member_count <= count,
+        ">=" => member_count >= count,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventBuilder;
+    use std::collections::HashMap;
+
+    fn context(member_count: usize) -> PushContext {
+        PushContext {
+            user_id: "@alice:example.org".to_string(),
+            display_name: Some("Alice".to_string()),
+            member_count,
+        }
+    }
+
+    fn event(etype: &str, content: &[(&str, &str)]) -> Event<String> {
+        let content: HashMap<String, String> = content
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+
+        EventBuilder::new()
+            .etype(etype.to_string())
+            .sender("@bob:example.org".to_string())
+            .content(content)
+            .build()
+    }
+
+    fn text(body: &str) -> Event<String> {
+        event(EVENT_MESSAGE, &[("msgtype", "m.text"), ("body", body)])
+    }
+
+    #[test]
+    fn default_rules_evaluate_as_specified() {
+        let rules = PushRuleSet::default();
+        let evaluate = |event: &Event<String>| rules.evaluate(event, &context(5));
+
+        let notice = event(EVENT_MESSAGE, &[("msgtype", "m.notice"), ("body", "Alice")]);
+        assert_eq!(evaluate(&notice), PushAction::DontNotify);
+        let member = event(STATE_MEMBER, &[("membership", "join")]);
+        assert_eq!(evaluate(&member), PushAction::DontNotify);
+
+        let mentioned = event(
+            EVENT_MESSAGE,
+            &[
+                ("msgtype", "m.text"),
+                ("body", "hi"),
+                (KEY_MENTIONS, r#"{"user_ids":["@alice:example.org"]}"#),
+            ],
+        );
+        assert_eq!(evaluate(&mentioned), PushAction::Highlight);
+        let room_mention = event(
+            EVENT_MESSAGE,
+            &[("msgtype", "m.text"), ("body", "hi"), (KEY_MENTIONS, r#"{"room":true}"#)],
+        );
+        assert_eq!(evaluate(&room_mention), PushAction::Highlight);
+
+        // .m.rule.contains_display_name and .m.rule.roomnotif only apply without
+        // `m.mentions`.
+        assert_eq!(evaluate(&text("Thanks, alice!")), PushAction::Highlight);
+        assert_eq!(evaluate(&text("Thanks, Alicent")), PushAction::Notify);
+        assert_eq!(evaluate(&text("@room lunch")), PushAction::Highlight);
+        let mentions_nobody = event(
+            EVENT_MESSAGE,
+            &[("msgtype", "m.text"), ("body", "Alice, @room"), (KEY_MENTIONS, "{}")],
+        );
+        assert_eq!(evaluate(&mentions_nobody), PushAction::Notify);
+
+        assert_eq!(evaluate(&text("Lunch is here")), PushAction::Notify);
+        assert_eq!(rules.evaluate(&text("Lunch"), &context(2)), PushAction::Notify);
+        assert_eq!(evaluate(&event("m.reaction", &[])), PushAction::DontNotify);
+
+        let mut own = text("Alice here");
+        own.sender = "@alice:example.org".to_string();
+        assert_eq!(evaluate(&own), PushAction::DontNotify);
+    }
+
+    #[test]
+    fn master_rule_is_disabled_until_enabled() {
+        let mut rules = PushRuleSet::default();
+        let master = rules.rule_mut(".m.rule.master").unwrap();
+        assert!(!master.enabled);
+        assert!(master.conditions.is_empty());
+        assert_eq!(master.action, PushAction::DontNotify);
+
+        master.enabled = true;
+        assert_eq!(rules.evaluate(&text("Thanks, alice!"), &context(5)), PushAction::DontNotify);
+        assert_eq!(rules.evaluate(&text("Lunch"), &context(2)), PushAction::DontNotify);
+    }
+}
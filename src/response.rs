@@ -1,32 +1,277 @@
 use std::boxed::Box;
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
 
+use serde_json::Value;
 
-#[derive(Debug, Clone, Display, Default)]
-struct RespError {
+
+/// An error returned by a homeserver, optionally caused by a lower-level error.
+#[derive(Debug)]
+pub struct RespError {
   kind: ErrorKind,
-  source: Box<dyn Error>,
+  source: Option<Box<dyn Error + Send + Sync>>,
   message: Option<String>,
+  status: Option<u16>,
 }
 
-#[derive(Debug)]
 // struct RespCreate
 
-impl Error for RespError {}
+impl RespError {
+  /// Creates an error from a Matrix `errcode` kind and human-readable message.
+  pub fn matrix(kind: ErrorKind, message: String) -> RespError {
+    RespError {
+      kind,
+      source: None,
+      message: Some(message),
+      status: None,
+    }
+  }
+
+  /// Parses a homeserver error response of the form `{ "errcode": ..., "error": ... }`.
+  ///
+  /// A body that isn't a JSON object becomes an `M_UNKNOWN` error with the parse
+  /// failure as its source.
+  pub fn from_json(status: u16, body: &str) -> RespError {
+    let parsed = match serde_json::from_str::<Value>(body) {
+      Ok(parsed) if parsed.is_object() => parsed,
+      Ok(_) => return RespError::unparseable(status, None),
+      Err(err) => return RespError::unparseable(status, Some(Box::new(err))),
+    };
+
+    let mut kind = ErrorKind::from_errcode(parsed["errcode"].as_str().unwrap_or("M_UNKNOWN"));
+
+    if let ErrorKind::LimitExceeded { retry_after } = &mut kind {
+      *retry_after = parsed["retry_after_ms"].as_u64().map(Duration::from_millis);
+    }
+
+    RespError {
+      kind,
+      source: None,
+      message: parsed["error"].as_str().map(String::from),
+      status: Some(status),
+    }
+  }
+
+  /// Wraps a failure that happened before a homeserver error could be read, such as
+  /// a dropped connection or an unparseable success body.
+  #[cfg(feature = "async")]
+  pub(crate) fn transport(status: Option<u16>, source: Box<dyn Error + Send + Sync>) -> RespError {
+    RespError {
+      kind: ErrorKind::Unknown("M_UNKNOWN".to_string()),
+      source: Some(source),
+      message: None,
+      status,
+    }
+  }
+
+  fn unparseable(status: u16, source: Option<Box<dyn Error + Send + Sync>>) -> RespError {
+    RespError {
+      kind: ErrorKind::Unknown("M_UNKNOWN".to_string()),
+      source,
+      message: None,
+      status: Some(status),
+    }
+  }
+
+  pub fn kind(&self) -> &ErrorKind {
+    &self.kind
+  }
+
+  pub fn message(&self) -> Option<&str> {
+    self.message.as_deref()
+  }
 
-#[derive(Debug, Clone, Default)]
+  /// The HTTP status of the response this error was parsed from.
+  pub fn status(&self) -> Option<u16> {
+    self.status
+  }
+
+  /// How long the server asked us to wait before retrying a rate-limited request.
+  pub fn retry_after(&self) -> Option<Duration> {
+    match self.kind {
+      ErrorKind::LimitExceeded { retry_after } => retry_after,
+      _ => None,
+    }
+  }
+}
+
+impl fmt::Display for RespError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match &self.message {
+      Some(message) => write!(f, "{}: {}", self.kind, message),
+      None => write!(f, "{}", self.kind),
+    }
+  }
+}
+
+impl Error for RespError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    self.source.as_deref().map(|source| source as &(dyn Error + 'static))
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub enum ErrorKind {
   Forbidden,
   UnknownToken,
   BadJSON,
   NotJSON,
+  #[default]
   NotFound,
-  LimitExceeded,
+  LimitExceeded { retry_after: Option<Duration> },
   UserInUse,
   InvalidUsername,
   RoomInUse,
   BadPagination,
   ThreepidInUse,
   ThreepidNotFound,
-  ServerNotTrusted
+  ServerNotTrusted,
+  /// An errcode this crate doesn't know about, kept verbatim.
+  Unknown(String),
+}
+
+impl ErrorKind {
+  /// The Matrix `errcode` string for this kind of error.
+  pub fn as_errcode(&self) -> &str {
+    match self {
+      ErrorKind::Forbidden => "M_FORBIDDEN",
+      ErrorKind::UnknownToken => "M_UNKNOWN_TOKEN",
+      ErrorKind::BadJSON => "M_BAD_JSON",
+      ErrorKind::NotJSON => "M_NOT_JSON",
+      ErrorKind::NotFound => "M_NOT_FOUND",
+      ErrorKind::LimitExceeded { .. } => "M_LIMIT_EXCEEDED",
+      ErrorKind::UserInUse => "M_USER_IN_USE",
+      ErrorKind::InvalidUsername => "M_INVALID_USERNAME",
+      ErrorKind::RoomInUse => "M_ROOM_IN_USE",
+      ErrorKind::BadPagination => "M_BAD_PAGINATION",
+      ErrorKind::ThreepidInUse => "M_THREEPID_IN_USE",
+      ErrorKind::ThreepidNotFound => "M_THREEPID_NOT_FOUND",
+      ErrorKind::ServerNotTrusted => "M_SERVER_NOT_TRUSTED",
+      ErrorKind::Unknown(errcode) => errcode,
+    }
+  }
+
+  /// Parses a Matrix `errcode` string. Unrecognised codes map to [`ErrorKind::Unknown`].
+  pub fn from_errcode(errcode: &str) -> ErrorKind {
+    match errcode {
+      "M_FORBIDDEN" => ErrorKind::Forbidden,
+      "M_UNKNOWN_TOKEN" => ErrorKind::UnknownToken,
+      "M_BAD_JSON" => ErrorKind::BadJSON,
+      "M_NOT_JSON" => ErrorKind::NotJSON,
+      "M_NOT_FOUND" => ErrorKind::NotFound,
+      "M_LIMIT_EXCEEDED" => ErrorKind::LimitExceeded { retry_after: None },
+      "M_USER_IN_USE" => ErrorKind::UserInUse,
+      "M_INVALID_USERNAME" => ErrorKind::InvalidUsername,
+      "M_ROOM_IN_USE" => ErrorKind::RoomInUse,
+      "M_BAD_PAGINATION" => ErrorKind::BadPagination,
+      "M_THREEPID_IN_USE" => ErrorKind::ThreepidInUse,
+      "M_THREEPID_NOT_FOUND" => ErrorKind::ThreepidNotFound,
+      "M_SERVER_NOT_TRUSTED" => ErrorKind::ServerNotTrusted,
+      _ => ErrorKind::Unknown(errcode.to_string()),
+    }
+  }
+}
+
+impl fmt::Display for ErrorKind {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.as_errcode())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const KINDS: &[(ErrorKind, &str)] = &[
+    (ErrorKind::Forbidden, "M_FORBIDDEN"),
+    (ErrorKind::UnknownToken, "M_UNKNOWN_TOKEN"),
+    (ErrorKind::BadJSON, "M_BAD_JSON"),
+    (ErrorKind::NotJSON, "M_NOT_JSON"),
+    (ErrorKind::NotFound, "M_NOT_FOUND"),
+    (ErrorKind::LimitExceeded { retry_after: None }, "M_LIMIT_EXCEEDED"),
+    (ErrorKind::UserInUse, "M_USER_IN_USE"),
+    (ErrorKind::InvalidUsername, "M_INVALID_USERNAME"),
+    (ErrorKind::RoomInUse, "M_ROOM_IN_USE"),
+    (ErrorKind::BadPagination, "M_BAD_PAGINATION"),
+    (ErrorKind::ThreepidInUse, "M_THREEPID_IN_USE"),
+    (ErrorKind::ThreepidNotFound, "M_THREEPID_NOT_FOUND"),
+    (ErrorKind::ServerNotTrusted, "M_SERVER_NOT_TRUSTED"),
+  ];
+
+  #[test]
+  fn errcodes_round_trip() {
+    for (kind, errcode) in KINDS {
+      assert_eq!(kind.as_errcode(), *errcode);
+      assert_eq!(&ErrorKind::from_errcode(errcode), kind);
+      assert_eq!(kind.to_string(), *errcode);
+    }
+  }
+
+  #[test]
+  fn unknown_errcode_is_kept() {
+    let kind = ErrorKind::from_errcode("M_SOMETHING_NEW");
+
+    assert_eq!(kind, ErrorKind::Unknown("M_SOMETHING_NEW".to_string()));
+    assert_eq!(kind.as_errcode(), "M_SOMETHING_NEW");
+  }
+
+  #[test]
+  fn from_json_reads_retry_after() {
+    let err = RespError::from_json(
+      429,
+      r#"{"errcode": "M_LIMIT_EXCEEDED", "error": "Too many requests", "retry_after_ms": 2000}"#,
+    );
+
+    assert_eq!(err.retry_after(), Some(Duration::from_millis(2000)));
+    assert_eq!(err.status(), Some(429));
+    assert_eq!(err.message(), Some("Too many requests"));
+
+    let err = RespError::from_json(429, r#"{"errcode": "M_LIMIT_EXCEEDED"}"#);
+    assert_eq!(err.kind(), &ErrorKind::LimitExceeded { retry_after: None });
+    assert_eq!(err.retry_after(), None);
 
-}
\ No newline at end of file
+    let err = RespError::from_json(403, r#"{"errcode": "M_FORBIDDEN", "retry_after_ms": 2000}"#);
+    assert_eq!(err.retry_after(), None);
+  }
+
+  #[test]
+  fn from_json_without_errcode_is_unknown() {
+    let err = RespError::from_json(500, r#"{"error": "Internal server error"}"#);
+
+    assert_eq!(err.kind(), &ErrorKind::Unknown("M_UNKNOWN".to_string()));
+    assert_eq!(err.message(), Some("Internal server error"));
+  }
+
+  #[test]
+  fn display_includes_message() {
+    let err = RespError::matrix(ErrorKind::Forbidden, "You are not invited".to_string());
+    assert_eq!(err.to_string(), "M_FORBIDDEN: You are not invited");
+
+    let err = RespError::from_json(404, r#"{"errcode": "M_NOT_FOUND"}"#);
+    assert_eq!(err.to_string(), "M_NOT_FOUND");
+  }
+
+  #[test]
+  fn unparseable_body_keeps_the_parse_error_as_source() {
+    let err = RespError::from_json(502, "<html>Bad Gateway</html>");
+
+    assert_eq!(err.kind(), &ErrorKind::Unknown("M_UNKNOWN".to_string()));
+    assert_eq!(err.status(), Some(502));
+    assert!(err.source().is_some_and(|source| source.is::<serde_json::Error>()));
+
+    let err = RespError::from_json(502, "[]");
+    assert!(err.source().is_none());
+  }
+
+  #[cfg(feature = "async")]
+  #[test]
+  fn transport_error_keeps_its_source() {
+    let source = std::io::Error::new(std::io::ErrorKind::ConnectionReset, "connection reset");
+    let err = RespError::transport(None, Box::new(source));
+
+    assert_eq!(err.status(), None);
+    assert_eq!(err.to_string(), "M_UNKNOWN");
+    assert_eq!(err.source().map(ToString::to_string).as_deref(), Some("connection reset"));
+  }
+}
@@ -1,23 +1,280 @@
 use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::OnceLock;
+use std::time::SystemTime;
 
-use crate::events::Event;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
+use crate::consts::{
+    EVENT_MESSAGE, EVENT_REACTION, EVENT_REDACTION, KEY_MEMBERSHIP, KEY_RELATES_TO, MEMBERSHIP_BAN,
+    MEMBERSHIP_INVITE, MEMBERSHIP_JOIN, MEMBERSHIP_KNOCK, MEMBERSHIP_LEAVE, REL_ANNOTATION,
+    STATE_AVATAR, STATE_CANONICAL_ALIAS, STATE_CREATE, STATE_ENCRYPTION, STATE_GUEST_ACCESS,
+    STATE_JOIN_RULES, STATE_MEMBER, STATE_NAME, STATE_POWER_LEVELS, STATE_TOMBSTONE, STATE_TOPIC,
+};
+use crate::events::{
+    EncryptionSettings, Event, EventBuilder, IgnoredUsers, MemberEventContent, PowerLevels,
+};
+
+/// A room and the current state events it holds, keyed by event type and state key.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::collections::HashMap;
+/// use matrux::events::Event;
+/// use matrux::room::Room;
+///
+/// let mut room: Room<String> = Room::new("!room:example.org".to_string());
+///
+/// let mut content = HashMap::new();
+/// content.insert("membership".to_string(), "join".to_string());
+/// room.update_state(Event {
+///     etype: "m.room.member".to_string(),
+///     state_key: "@alice:example.org".to_string(),
+///     content,
+///     ..Default::default()
+/// });
+///
+/// let event = room
+///     .get_state_event("m.room.member".to_string(), "@alice:example.org".to_string())
+///     .unwrap();
+/// assert_eq!(event.content["membership"], "join");
+/// assert_eq!(room.get_membership_state("@alice:example.org".to_string()), "join");
+/// assert_eq!(room.state_types(), vec!["m.room.member"]);
+/// ```
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Room<T: Clone> {
     pub id: String,
     state: HashMap<String, HashMap<String, Event<T>>>,
+    timeline: Vec<Event<T>>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    full_member_list: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    notification_count: u64,
+    #[cfg_attr(feature = "serde", serde(default))]
+    highlight_count: u64,
+    #[cfg_attr(feature = "serde", serde(default))]
+    read_marker: Option<String>,
+    /// Each user's latest read receipt and the event it was sent for, keyed by user.
+    #[cfg_attr(feature = "serde", serde(default))]
+    receipts: HashMap<String, (String, Receipt)>,
+    /// `None` until an `m.typing` event has been seen. Typing is transient, so it
+    /// isn't stored.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    typing: Option<Vec<String>>,
+    /// Where `/messages` continues backwards from the oldest event in the timeline.
+    #[cfg_attr(feature = "serde", serde(default))]
+    prev_batch: Option<String>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    gaps: Vec<TimelineGap>,
+    /// The user the room is seen by, left out when naming the room after its
+    /// members.
+    #[cfg_attr(feature = "serde", serde(default))]
+    own_user_id: Option<String>,
+    /// The events relating to each event, built from the timeline on first use and
+    /// kept up to date as the timeline changes.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    relations: OnceLock<RelationIndex>,
+}
+
+/// Where in the timeline the events relating to each event are, by the id of the
+/// event they relate to. Events are numbered rather than looked up by id, so local
+/// echoes without an id yet are included.
+#[derive(Clone, Default)]
+struct RelationIndex {
+    /// The number of the oldest timeline event. Prepending lowers it, so the
+    /// numbers of events already indexed stay put.
+    first: isize,
+    /// The numbers of the related events, in ascending order.
+    related: HashMap<String, Vec<isize>>,
+}
+
+impl RelationIndex {
+    fn insert(&mut self, target: String, number: isize) {
+        let numbers = self.related.entry(target).or_default();
+        let at = numbers.partition_point(|&indexed| indexed < number);
+        numbers.insert(at, number);
+    }
+
+    fn remove(&mut self, target: &str, number: isize) {
+        if let Some(numbers) = self.related.get_mut(target) {
+            numbers.retain(|&indexed| indexed != number);
+            if numbers.is_empty() {
+                self.related.remove(target);
+            }
+        }
+    }
+
+    fn position(&self, number: isize) -> usize {
+        (number - self.first) as usize
+    }
+
+    fn number(&self, position: usize) -> isize {
+        self.first + position as isize
+    }
+}
+
+/// Rooms are the same room when their ids match, whatever state each snapshot
+/// holds.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::collections::HashSet;
+/// use matrux::room::Room;
+///
+/// let mut joined = Room::<String>::new("!room:example.org".to_string());
+/// joined.mark_joined("@alice:example.org");
+///
+/// let rooms: HashSet<_> = [joined, Room::new("!room:example.org".to_string())].into();
+/// assert_eq!(rooms.len(), 1);
+/// ```
+impl<T: Clone> PartialEq for Room<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T: Clone> Eq for Room<T> {}
+
+impl<T: Clone> Hash for Room<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+/// How many characters of a room's name are shown; the spec caps names at 255.
+const MAX_NAME_LENGTH: usize = 255;
+
+/// The state event types included in an invite's stripped state, besides the
+/// member events of the inviter and invitee.
+const STRIPPED_STATE_TYPES: &[&str] = &[
+    STATE_CREATE,
+    STATE_NAME,
+    STATE_AVATAR,
+    STATE_CANONICAL_ALIAS,
+    STATE_JOIN_RULES,
+    STATE_ENCRYPTION,
+];
+
+/// The memberships the spec defines.
+const MEMBERSHIPS: &[&str] = &[
+    MEMBERSHIP_JOIN,
+    MEMBERSHIP_INVITE,
+    MEMBERSHIP_LEAVE,
+    MEMBERSHIP_BAN,
+    MEMBERSHIP_KNOCK,
+];
+
+/// A user's read receipt: they have read up to the event it was sent for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Receipt {
+    pub user_id: String,
+    /// When the receipt was sent, in milliseconds since the Unix epoch.
+    pub ts: i64,
+}
+
+/// Marks missing history in the middle of the timeline, left when a sync returns a
+/// `limited` timeline that doesn't join up with the events already held.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TimelineGap {
+    /// The first event after the gap.
+    pub event_id: String,
+    /// The token to pass as `from` to `/messages` to load the missing events.
+    pub prev_batch: Option<String>,
 }
 
-struct PublicRoom {
-    canonical_alias: String,
-    name: String,
-    world_readable: bool,
-    topic: String,
-    num_joined_members: u32,
-    avatar_url: String,
-    room_id: String,
-    guest_can_join: bool,
-    aliases: Vec<String>,
+/// What a room list shows for a room, gathered by [`Room::summary`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RoomSummary {
+    pub display_name: String,
+    pub avatar_url: Option<String>,
+    pub topic: Option<String>,
+    /// The number of joined members.
+    pub member_count: usize,
+    /// The body of the latest message in the timeline.
+    pub last_message: Option<String>,
+    pub unread_count: u64,
+}
+
+/// How a room's state differs between two snapshots, as `(type, state_key)` pairs
+/// sorted in that order. Returned by [`Room::state_diff`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StateDiff {
+    /// Entries only the newer snapshot has.
+    pub added: Vec<(String, String)>,
+    /// Entries only the older snapshot has.
+    pub removed: Vec<(String, String)>,
+    /// Entries both have, but held by different events.
+    pub changed: Vec<(String, String)>,
+}
+
+impl StateDiff {
+    /// Whether the snapshots hold the same state.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PublicRoom {
+    pub canonical_alias: String,
+    pub name: String,
+    pub world_readable: bool,
+    pub topic: String,
+    pub num_joined_members: u32,
+    pub avatar_url: String,
+    pub room_id: String,
+    pub guest_can_join: bool,
+    pub aliases: Vec<String>,
+}
+
+impl PublicRoom {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        canonical_alias: String,
+        name: String,
+        world_readable: bool,
+        topic: String,
+        num_joined_members: u32,
+        avatar_url: String,
+        room_id: String,
+        guest_can_join: bool,
+        aliases: Vec<String>,
+    ) -> Self {
+        Self {
+            canonical_alias,
+            name,
+            world_readable,
+            topic,
+            num_joined_members,
+            avatar_url,
+            room_id,
+            guest_can_join,
+            aliases,
+        }
+    }
+
+    /// Builds a directory entry from the name, canonical alias, topic and avatar state
+    /// held by `room`. Fields without a corresponding state event are left empty.
+    pub fn from_room<T: 'static + Clone>(room: &Room<T>) -> PublicRoom
+    where
+        String: for<'a> From<&'a T>,
+    {
+        PublicRoom {
+            canonical_alias: room.canonical_alias().unwrap_or_default(),
+            name: room.raw_name().unwrap_or_default(),
+            topic: room.topic().unwrap_or_default(),
+            avatar_url: room.avatar_url().unwrap_or_default(),
+            num_joined_members: room.joined_members().len() as u32,
+            room_id: room.id.clone(),
+            ..Default::default()
+        }
+    }
 }
 
 impl<T: 'static + Clone> Room<T>
@@ -26,50 +283,2100 @@ where
 {
     pub fn new(id: String) -> Self {
         let state = HashMap::new();
-        Self { id, state }
+        let timeline = Vec::new();
+        Self {
+            id,
+            state,
+            timeline,
+            full_member_list: false,
+            notification_count: 0,
+            highlight_count: 0,
+            read_marker: None,
+            receipts: HashMap::new(),
+            typing: None,
+            prev_batch: None,
+            gaps: Vec::new(),
+            own_user_id: None,
+            relations: OnceLock::new(),
+        }
     }
 
-    fn update_state(&mut self, mut event: Event<T>) {
-        let exists = self.state.contains_key(&event.etype);
+    /// Creates a room holding `events` as its state. When several events share a
+    /// type and state key, the last one wins.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::collections::HashMap;
+    /// use matrux::events::Event;
+    /// use matrux::room::Room;
+    ///
+    /// let state_event = |etype: &str, state_key: &str, key: &str, value: &str| {
+    ///     let mut content = HashMap::new();
+    ///     content.insert(key.to_string(), value.to_string());
+    ///     Event {
+    ///         etype: etype.to_string(),
+    ///         state_key: state_key.to_string(),
+    ///         content,
+    ///         ..Default::default()
+    ///     }
+    /// };
+    ///
+    /// let room = Room::with_state(
+    ///     "!room:example.org".to_string(),
+    ///     vec![
+    ///         state_event("m.room.name", "", "name", "Draft"),
+    ///         state_event("m.room.member", "@alice:example.org", "membership", "join"),
+    ///         state_event("m.room.name", "", "name", "Book club"),
+    ///     ],
+    /// );
+    ///
+    /// assert_eq!(room.state_event_count(), 2);
+    /// assert_eq!(room.display_name(), "Book club");
+    /// assert_eq!(room.joined_members(), ["@alice:example.org"]);
+    /// ```
+    pub fn with_state(id: String, events: impl IntoIterator<Item = Event<T>>) -> Self {
+        let mut state: HashMap<String, HashMap<String, Event<T>>> = HashMap::new();
 
-        if !exists {
-            self.state.insert(event.etype.to_string(), HashMap::new());
+        for event in events {
+            state
+                .entry(event.etype.clone())
+                .or_default()
+                .insert(event.state_key.clone(), event);
         }
 
-        let inner_map = self.state.entry(event.etype.clone()).and_modify(|value| {
-            let _event = event.clone();
-            value.entry(event.state_key.clone()).and_modify(|inner| {
-                let mut inner_val = inner;
-                inner_val = &mut event;
+        Room { state, ..Room::new(id) }
+    }
+
+    /// Builds the room an invite shows, from the stripped `invite_state` events
+    /// the server sends with it.
+    pub fn from_invite_state(room_id: String, events: Vec<Event<T>>) -> Self {
+        Room::with_state(room_id, events)
+    }
+
+    /// Stores `event` as the room's current state for its type and state key,
+    /// marking it as a state event.
+    pub fn update_state(&mut self, mut event: Event<T>) {
+        event.has_state_key = true;
+        self.state
+            .entry(event.etype.clone())
+            .or_default()
+            .insert(event.state_key.clone(), event);
+    }
+
+    /// Appends an event to the end of the timeline. State events also update the
+    /// room's current state, and redactions are applied with
+    /// [`Room::apply_redaction`].
+    ///
+    /// Sync can deliver the same event more than once, so an event whose id is
+    /// already in the timeline replaces that entry in place instead, keeping any
+    /// `unsigned` fields the new copy lacks.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use matrux::events::Event;
+    /// use matrux::room::Room;
+    /// use serde_json::json;
+    ///
+    /// let event = |value| Event::<String>::from(Event::from_sync_json(&value).unwrap());
+    ///
+    /// let mut room = Room::new("!room:example.org".to_string());
+    /// room.append_timeline(event(json!({
+    ///     "type": "m.room.name",
+    ///     "state_key": "",
+    ///     "event_id": "$name",
+    ///     "sender": "@alice:example.org",
+    ///     "origin_server_ts": 1_718_000_000_000_i64,
+    ///     "content": { "name": "Book club" }
+    /// })));
+    /// room.append_timeline(event(json!({
+    ///     "type": "m.room.message",
+    ///     "event_id": "$message",
+    ///     "sender": "@alice:example.org",
+    ///     "origin_server_ts": 1_718_000_000_001_i64,
+    ///     "content": { "msgtype": "m.text", "body": "Hello" }
+    /// })));
+    ///
+    /// assert_eq!(room.display_name(), "Book club");
+    /// assert_eq!(room.state_types(), ["m.room.name"]);
+    /// ```
+    pub fn append_timeline(&mut self, event: Event<T>)
+    where
+        T: From<String>,
+    {
+        if event.is_state_event() {
+            self.update_state(event.clone());
+        }
+        if event.etype == EVENT_REDACTION {
+            self.apply_redaction(event.clone());
+        }
+
+        let existing = self
+            .timeline
+            .iter()
+            .position(|existing| !event.id.is_empty() && existing.id == event.id);
+
+        match existing {
+            Some(position) => self.replace_event(position, event),
+            None => self.push_event(event),
+        }
+    }
+
+    /// Shows an event the client is sending before the server has confirmed it. The
+    /// echo is tagged with `txn_id` so [`Room::reconcile_echo`] can replace it.
+    pub fn add_local_echo(&mut self, txn_id: &str, mut event: Event<T>)
+    where
+        T: From<String>,
+    {
+        event
+            .unsigned
+            .insert("transaction_id".to_string(), T::from(txn_id.to_string()));
+        self.push_event(event);
+    }
+
+    /// Appends an event from sync, replacing the local echo that has the same
+    /// `unsigned.transaction_id` instead of showing the message twice. Unsigned
+    /// fields that only the echo has are kept.
+    pub fn reconcile_echo(&mut self, event: Event<T>)
+    where
+        T: From<String>,
+    {
+        let echo = event
+            .unsigned
+            .get("transaction_id")
+            .map(String::from)
+            .and_then(|txn_id| {
+                self.timeline.iter().position(|echo| {
+                    echo.id != event.id
+                        && echo.unsigned.get("transaction_id").map(String::from).as_ref()
+                            == Some(&txn_id)
+                })
             });
+
+        match echo {
+            Some(position) => {
+                if event.is_state_event() {
+                    self.update_state(event.clone());
+                }
+
+                self.replace_event(position, event);
+            }
+            None => self.append_timeline(event),
+        }
+    }
+
+    /// Inserts older history, in chronological order, before the start of the
+    /// timeline. Events already in the timeline are skipped, and state is left
+    /// alone since these events predate it. Redactions among them are applied, to
+    /// state as well.
+    pub fn prepend_timeline(&mut self, events: Vec<Event<T>>)
+    where
+        T: From<String>,
+    {
+        let older: Vec<Event<T>> = events
+            .into_iter()
+            .filter(|event| !self.contains_event(&event.id))
+            .collect();
+
+        if let Some(relations) = self.relations.get_mut() {
+            relations.first -= older.len() as isize;
+            for (position, event) in older.iter().enumerate() {
+                if let Some(target) = relation_target(event) {
+                    relations.insert(target, relations.number(position));
+                }
+            }
+        }
+
+        let redactions: Vec<Event<T>> = older
+            .iter()
+            .filter(|event| event.etype == EVENT_REDACTION)
+            .cloned()
+            .collect();
+        self.timeline.splice(0..0, older);
+
+        for redaction in redactions {
+            self.apply_redaction(redaction);
+        }
+    }
+
+    /// Redacts the event targeted by `redaction` wherever it appears in the
+    /// timeline or the current state. Redactions added to the timeline are applied
+    /// already; this is for ones that arrive some other way.
+    pub fn apply_redaction(&mut self, redaction: Event<T>)
+    where
+        T: From<String>,
+    {
+        let target = if redaction.redacts.is_empty() {
+            redaction.content.get("redacts").map(String::from).unwrap_or_default()
+        } else {
+            redaction.redacts.clone()
+        };
+
+        if target.is_empty() {
+            return;
+        }
+
+        for position in 0..self.timeline.len() {
+            if self.timeline[position].id == target {
+                // Redaction strips `m.relates_to`, so the event stops relating to
+                // anything.
+                let previous = relation_target(&self.timeline[position]);
+                self.timeline[position].redact(&redaction.id);
+                self.reindex(position, previous);
+            }
+        }
+
+        let state_events = self.state.values_mut().flat_map(|events| events.values_mut());
+
+        for event in state_events {
+            if event.id == target {
+                event.redact(&redaction.id);
+            }
+        }
+    }
+
+    /// The timeline events relating to `event_id` through `m.relates_to`, such as
+    /// reactions, edits and thread replies, in timeline order.
+    ///
+    /// Relations are looked up in an index built on first use and updated as events
+    /// are added, replaced or redacted, so lookups don't each scan the timeline.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::collections::HashMap;
+    /// use matrux::events::Event;
+    /// use matrux::room::Room;
+    ///
+    /// let mut room = Room::new("!room:example.org".to_string());
+    /// let reaction = |id: &str| {
+    ///     let mut content = HashMap::new();
+    ///     content.insert(
+    ///         "m.relates_to".to_string(),
+    ///         r#"{"rel_type":"m.annotation","event_id":"$message","key":"👍"}"#.to_string(),
+    ///     );
+    ///     Event {
+    ///         etype: "m.reaction".to_string(),
+    ///         id: id.to_string(),
+    ///         content,
+    ///         ..Default::default()
+    ///     }
+    /// };
+    ///
+    /// room.append_timeline(reaction("$r1"));
+    /// assert_eq!(room.relations_for("$message").len(), 1);
+    ///
+    /// room.append_timeline(reaction("$r2"));
+    /// room.apply_redaction(Event {
+    ///     etype: "m.room.redaction".to_string(),
+    ///     id: "$redaction".to_string(),
+    ///     redacts: "$r1".to_string(),
+    ///     ..Default::default()
+    /// });
+    ///
+    /// let ids: Vec<&str> =
+    ///     room.relations_for("$message").iter().map(|event| event.id.as_str()).collect();
+    /// assert_eq!(ids, ["$r2"]);
+    /// ```
+    pub fn relations_for(&self, event_id: &str) -> Vec<&Event<T>> {
+        let relations = self.relations();
+
+        relations
+            .related
+            .get(event_id)
+            .map(|numbers| {
+                numbers
+                    .iter()
+                    .map(|&number| &self.timeline[relations.position(number)])
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn relations(&self) -> &RelationIndex {
+        self.relations.get_or_init(|| {
+            let mut relations = RelationIndex::default();
+            for (position, event) in self.timeline.iter().enumerate() {
+                if let Some(target) = relation_target(event) {
+                    relations.insert(target, relations.number(position));
+                }
+            }
+            relations
+        })
+    }
+
+    /// Adds an event to the end of the timeline.
+    fn push_event(&mut self, event: Event<T>) {
+        if let Some(relations) = self.relations.get_mut() {
+            if let Some(target) = relation_target(&event) {
+                relations.insert(target, relations.number(self.timeline.len()));
+            }
+        }
+
+        self.timeline.push(event);
+    }
+
+    /// Replaces the timeline event at `position`, keeping `unsigned` fields only the
+    /// old copy has.
+    fn replace_event(&mut self, position: usize, event: Event<T>) {
+        let previous = relation_target(&self.timeline[position]);
+        replace_keeping_unsigned(&mut self.timeline[position], event);
+        self.reindex(position, previous);
+    }
+
+    /// Moves the timeline event at `position` in the relation index from
+    /// `previous`, what it related to before it changed, to what it relates to now.
+    fn reindex(&mut self, position: usize, previous: Option<String>) {
+        let Some(relations) = self.relations.get_mut() else {
+            return;
+        };
+        let number = relations.number(position);
+
+        if let Some(previous) = previous {
+            relations.remove(&previous, number);
+        }
+        if let Some(target) = relation_target(&self.timeline[position]) {
+            relations.insert(target, number);
+        }
+    }
+
+    /// Whether an event with id `id` is in the timeline.
+    pub fn contains_event(&self, id: &str) -> bool {
+        !id.is_empty() && self.timeline.iter().any(|event| event.id == id)
+    }
+
+    /// Applies a newer snapshot of the room, such as one parsed from `/sync`: its
+    /// state replaces ours and its timeline is appended to ours, replacing any local
+    /// echoes it confirms.
+    #[cfg(feature = "async")]
+    pub(crate) fn merge(&mut self, newer: Room<T>)
+    where
+        T: From<String>,
+    {
+        // Once we hold a timeline, our token leads back from our oldest event and the
+        // newer one only back to events we hold. Without a token we have reached
+        // the start of the room.
+        if self.timeline.is_empty() {
+            self.prev_batch = newer.prev_batch;
+        } else {
+            // A gap at the start of an empty timeline is covered by `prev_batch`, and
+            // one before an event we already hold has been filled.
+            let new_gaps: Vec<TimelineGap> = newer
+                .gaps
+                .into_iter()
+                .filter(|gap| !self.contains_event(&gap.event_id))
+                .collect();
+            self.gaps.extend(new_gaps);
+        }
+
+        for event in newer.state.into_values().flat_map(HashMap::into_values) {
+            self.update_state(event);
+        }
+
+        for event in newer.timeline {
+            self.reconcile_echo(event);
+        }
+        self.full_member_list |= newer.full_member_list;
+        if newer.own_user_id.is_some() {
+            self.own_user_id = newer.own_user_id;
+        }
+        self.notification_count = newer.notification_count;
+        self.highlight_count = newer.highlight_count;
+
+        for (event_id, receipt) in newer.receipts.into_values() {
+            self.set_receipt(&event_id, receipt);
+        }
+
+        if newer.typing.is_some() {
+            self.typing = newer.typing;
+        }
+    }
+
+    /// The number of unread messages that notify, as counted by the server.
+    pub fn notification_count(&self) -> u64 {
+        self.notification_count
+    }
+
+    /// The number of unread messages that highlight, e.g. because they mention the
+    /// user.
+    pub fn highlight_count(&self) -> u64 {
+        self.highlight_count
+    }
+
+    pub fn set_unread_counts(&mut self, notification_count: u64, highlight_count: u64) {
+        self.notification_count = notification_count;
+        self.highlight_count = highlight_count;
+    }
+
+    /// Marks the room as read up to `event_id`, clearing the unread counts locally
+    /// and remembering the event so a read receipt can be sent for it later.
+    pub fn mark_read(&mut self, event_id: &str) {
+        self.set_unread_counts(0, 0);
+        self.read_marker = Some(event_id.to_string());
+    }
+
+    /// The event most recently passed to [`Room::mark_read`].
+    pub fn read_marker(&self) -> Option<&str> {
+        self.read_marker.as_deref()
+    }
+
+    /// The token to pass as `from` to `/messages` to load history older than the
+    /// timeline. `None` before the first sync, or once the start of the room has
+    /// been reached.
+    pub fn prev_batch(&self) -> Option<&str> {
+        self.prev_batch.as_deref()
+    }
+
+    pub fn set_prev_batch(&mut self, prev_batch: Option<String>) {
+        self.prev_batch = prev_batch;
+    }
+
+    /// The user the room is seen by, as set by [`Room::set_own_user_id`].
+    pub fn own_user_id(&self) -> Option<&str> {
+        self.own_user_id.as_deref()
+    }
+
+    /// Sets the user the room is seen by, usually the logged-in user. They are left
+    /// out of the members [`Room::display_name`] names the room after.
+    pub fn set_own_user_id(&mut self, user_id: &str) {
+        self.own_user_id = Some(user_id.to_string());
+    }
+
+    /// Marks history as missing before `event_id`, loadable from `prev_batch`.
+    pub fn insert_gap(&mut self, event_id: &str, prev_batch: Option<String>) {
+        self.gaps.retain(|gap| gap.event_id != event_id);
+        self.gaps.push(TimelineGap {
+            event_id: event_id.to_string(),
+            prev_batch,
         });
     }
 
-    fn get_state_event(&self, event_type: String, state_key: String) -> Option<&Event<T>> {
-        let mut exists = false;
+    /// Forgets the gap before `event_id`, e.g. once its events are loaded.
+    pub fn remove_gap(&mut self, event_id: &str) {
+        self.gaps.retain(|gap| gap.event_id != event_id);
+    }
 
-        let state_event_map = self
-            .state
+    /// The gaps in the timeline, oldest first.
+    pub fn gaps(&self) -> &[TimelineGap] {
+        &self.gaps
+    }
+
+    /// Whether history is missing right before `event_id`, where a UI can offer to
+    /// load more.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use matrux::sync::SyncResponse;
+    /// use serde_json::json;
+    ///
+    /// let response = SyncResponse::from_json(&json!({
+    ///     "next_batch": "s2",
+    ///     "rooms": { "join": { "!room:example.org": { "timeline": {
+    ///         "limited": true,
+    ///         "prev_batch": "t1",
+    ///         "events": [
+    ///             {
+    ///                 "type": "m.room.message",
+    ///                 "event_id": "$first",
+    ///                 "sender": "@alice:example.org",
+    ///                 "origin_server_ts": 1_718_000_000_000_i64,
+    ///                 "content": { "msgtype": "m.text", "body": "Back again" }
+    ///             },
+    ///             {
+    ///                 "type": "m.room.message",
+    ///                 "event_id": "$second",
+    ///                 "sender": "@alice:example.org",
+    ///                 "origin_server_ts": 1_718_000_000_001_i64,
+    ///                 "content": { "msgtype": "m.text", "body": "Anyone here?" }
+    ///             }
+    ///         ]
+    ///     } } } }
+    /// }))
+    /// .unwrap();
+    ///
+    /// let room = &response.joined["!room:example.org"];
+    /// assert!(room.has_gap_before("$first"));
+    /// assert!(!room.has_gap_before("$second"));
+    /// assert_eq!(room.gaps()[0].prev_batch.as_deref(), Some("t1"));
+    /// ```
+    pub fn has_gap_before(&self, event_id: &str) -> bool {
+        self.gaps.iter().any(|gap| gap.event_id == event_id)
+    }
+
+    /// Records that `receipt.user_id` has read up to `event_id`. Receipts older than
+    /// the one already held for the user are ignored.
+    pub fn set_receipt(&mut self, event_id: &str, receipt: Receipt) {
+        if let Some((_, existing)) = self.receipts.get(&receipt.user_id) {
+            if existing.ts > receipt.ts {
+                return;
+            }
+        }
+
+        self.receipts
+            .insert(receipt.user_id.clone(), (event_id.to_string(), receipt));
+    }
+
+    /// Lists the users who have read up to `event_id`: those whose receipt is for
+    /// that event or for a later one in the timeline. Sorted by user id.
+    pub fn read_receipts(&self, event_id: &str) -> Vec<Receipt> {
+        let later_events: Vec<&str> = self
+            .timeline
+            .iter()
+            .map(|event| event.id.as_str())
+            .skip_while(|id| *id != event_id)
+            .collect();
+
+        let mut receipts: Vec<Receipt> = self
+            .receipts
+            .values()
+            .filter(|(read_up_to, _)| {
+                read_up_to == event_id || later_events.contains(&read_up_to.as_str())
+            })
+            .map(|(_, receipt)| receipt.clone())
+            .collect();
+
+        receipts.sort_by(|a, b| a.user_id.cmp(&b.user_id));
+        receipts
+    }
+
+    /// The users currently typing in the room.
+    pub fn typing_users(&self) -> &[String] {
+        self.typing.as_deref().unwrap_or_default()
+    }
+
+    /// Replaces the set of typing users. Each `m.typing` event lists everyone who is
+    /// typing, so the previous set is discarded.
+    pub fn set_typing(&mut self, users: Vec<String>) {
+        self.typing = Some(users);
+    }
+
+    /// Returns the last `n` timeline events, oldest first.
+    pub fn recent_messages(&self, n: usize) -> &[Event<T>] {
+        let start = self.timeline.len().saturating_sub(n);
+        &self.timeline[start..]
+    }
+
+    pub fn state_types(&self) -> Vec<&str> {
+        let mut types: Vec<&str> = self.state.keys().map(String::as_str).collect();
+        types.sort_unstable();
+        types
+    }
+
+    pub fn get_state_event(&self, event_type: String, state_key: String) -> Option<&Event<T>> {
+        self.state
             .get(&event_type)
-            .expect("event type should exist in state");
+            .and_then(|state_event_map| state_event_map.get(&state_key))
+    }
+
+    /// Every current state event, in order of event type and then state key.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use matrux::events::Event;
+    /// use matrux::room::Room;
+    ///
+    /// let mut room = Room::<String>::new("!room:example.org".to_string());
+    /// let state = [("m.room.name", ""), ("m.room.member", "@bob:x"), ("m.room.member", "@al:x")];
+    /// for (etype, state_key) in state {
+    ///     room.update_state(Event {
+    ///         etype: etype.to_string(),
+    ///         state_key: state_key.to_string(),
+    ///         ..Default::default()
+    ///     });
+    /// }
+    ///
+    /// assert_eq!(room.state_event_count(), 3);
+    /// let keys: Vec<(&str, &str)> = room
+    ///     .state_events()
+    ///     .map(|event| (event.etype.as_str(), event.state_key.as_str()))
+    ///     .collect();
+    /// assert_eq!(
+    ///     keys,
+    ///     [("m.room.member", "@al:x"), ("m.room.member", "@bob:x"), ("m.room.name", "")]
+    /// );
+    /// ```
+    pub fn state_events(&self) -> impl Iterator<Item = &Event<T>> {
+        let mut events: Vec<&Event<T>> =
+            self.state.values().flat_map(HashMap::values).collect();
+        events.sort_by(|a, b| (&a.etype, &a.state_key).cmp(&(&b.etype, &b.state_key)));
+        events.into_iter()
+    }
+
+    /// The number of current state events.
+    pub fn state_event_count(&self) -> usize {
+        self.state.values().map(HashMap::len).sum()
+    }
+
+    /// Compares this room's state with `other`, a newer snapshot of the same room.
+    /// An entry counts as changed when it is held by an event with a different id.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use matrux::events::Event;
+    /// use matrux::room::Room;
+    ///
+    /// let state_event = |etype: &str, state_key: &str, id: &str| Event::<String> {
+    ///     etype: etype.to_string(),
+    ///     state_key: state_key.to_string(),
+    ///     id: id.to_string(),
+    ///     ..Default::default()
+    /// };
+    ///
+    /// let before = Room::with_state(
+    ///     "!room:example.org".to_string(),
+    ///     vec![
+    ///         state_event("m.room.member", "@alice:example.org", "$alice"),
+    ///         state_event("m.room.topic", "", "$topic"),
+    ///     ],
+    /// );
+    /// let mut after = before.clone();
+    /// after.update_state(state_event("m.room.member", "@bob:example.org", "$bob"));
+    /// after.update_state(state_event("m.room.topic", "", "$new_topic"));
+    ///
+    /// let diff = before.state_diff(&after);
+    /// let entry = |etype: &str, state_key: &str| (etype.to_string(), state_key.to_string());
+    /// assert_eq!(diff.added, [entry("m.room.member", "@bob:example.org")]);
+    /// assert!(diff.removed.is_empty());
+    /// assert_eq!(diff.changed, [entry("m.room.topic", "")]);
+    /// ```
+    pub fn state_diff(&self, other: &Room<T>) -> StateDiff {
+        let mut diff = StateDiff::default();
+        let key = |event: &Event<T>| (event.etype.clone(), event.state_key.clone());
+
+        for event in self.state_events() {
+            match other.get_state_event(event.etype.clone(), event.state_key.clone()) {
+                None => diff.removed.push(key(event)),
+                Some(newer) if newer.id != event.id => diff.changed.push(key(event)),
+                Some(_) => {}
+            }
+        }
+
+        for event in other.state_events() {
+            if self
+                .get_state_event(event.etype.clone(), event.state_key.clone())
+                .is_none()
+            {
+                diff.added.push(key(event));
+            }
+        }
+
+        diff
+    }
+
+    pub fn members(&self) -> Vec<(String, String)> {
+        let mut members: Vec<(String, String)> = self
+            .state
+            .get(STATE_MEMBER)
+            .map(|member_events| {
+                member_events
+                    .iter()
+                    .filter_map(|(user_id, event)| {
+                        let membership = event.content.get(KEY_MEMBERSHIP)?;
+                        Some((user_id.clone(), String::from(membership)))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        members.sort();
+        members
+    }
+
+    /// Looks up the member event of `user_id`. Returns `None` when it hasn't been
+    /// loaded, which with lazy-loaded members doesn't mean the user isn't in the
+    /// room; see [`Room::has_full_member_list`].
+    pub fn resolve_member(&self, user_id: &str) -> Option<MemberEventContent> {
+        self.get_state_event(STATE_MEMBER.to_string(), user_id.to_string())
+            .and_then(Event::member_content)
+    }
+
+    /// Records locally that `user_id` has joined, ahead of the server's member event.
+    ///
+    /// Like the other `mark_*` methods this only updates the room's `m.room.member`
+    /// state, so the UI can reflect the change immediately; the event from the next
+    /// sync replaces it. Profile fields of an existing member event are kept.
+    pub fn mark_joined(&mut self, user_id: &str)
+    where
+        T: From<String>,
+    {
+        self.set_membership(user_id, MEMBERSHIP_JOIN, None);
+    }
+
+    /// Records locally that `user_id` has left the room.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use matrux::room::Room;
+    ///
+    /// let mut room = Room::<String>::new("!room:example.org".to_string());
+    /// room.mark_joined("@alice:example.org");
+    /// assert_eq!(room.get_membership_state("@alice:example.org".to_string()), "join");
+    ///
+    /// room.mark_left("@alice:example.org");
+    /// assert_eq!(room.get_membership_state("@alice:example.org".to_string()), "leave");
+    /// ```
+    pub fn mark_left(&mut self, user_id: &str)
+    where
+        T: From<String>,
+    {
+        self.set_membership(user_id, MEMBERSHIP_LEAVE, None);
+    }
+
+    /// Records locally that `user_id` has been invited, optionally with the reason
+    /// given for the invite.
+    pub fn mark_invited(&mut self, user_id: &str, reason: Option<String>)
+    where
+        T: From<String>,
+    {
+        self.set_membership(user_id, MEMBERSHIP_INVITE, reason);
+    }
+
+    /// Updates the member event of `user_id`, or synthesizes one, so its
+    /// `membership` is `membership`. A reason from an earlier change is dropped.
+    fn set_membership(&mut self, user_id: &str, membership: &str, reason: Option<String>)
+    where
+        T: From<String>,
+    {
+        let mut event = self
+            .get_state_event(STATE_MEMBER.to_string(), user_id.to_string())
+            .cloned()
+            .unwrap_or_else(|| {
+                EventBuilder::new()
+                    .etype(STATE_MEMBER.to_string())
+                    .state_key(user_id.to_string())
+                    .sender(user_id.to_string())
+                    .room_id(self.id.clone())
+                    .build()
+            });
+
+        // The change hasn't come from the server, so it has no event id yet.
+        event.id = String::new();
+        event.set_timestamp(SystemTime::now());
+        event
+            .content
+            .insert(KEY_MEMBERSHIP.to_string(), T::from(membership.to_string()));
+
+        match reason {
+            Some(reason) => {
+                event.content.insert("reason".to_string(), T::from(reason));
+            }
+            None => {
+                event.content.remove("reason");
+            }
+        }
+
+        self.update_state(event);
+    }
+
+    /// Whether every member event of the room is held, rather than only those for
+    /// senders that lazy-loading delivered.
+    pub fn has_full_member_list(&self) -> bool {
+        self.full_member_list
+    }
+
+    pub fn set_full_member_list(&mut self, full_member_list: bool) {
+        self.full_member_list = full_member_list;
+    }
+
+    pub fn joined_members(&self) -> Vec<String> {
+        self.members()
+            .into_iter()
+            .filter(|(_, membership)| membership == MEMBERSHIP_JOIN)
+            .map(|(user_id, _)| user_id)
+            .collect()
+    }
+
+    /// The room's main alias from `m.room.canonical_alias`. An event with an empty
+    /// or missing `alias` counts as no alias.
+    pub fn canonical_alias(&self) -> Option<String> {
+        self.state_content(STATE_CANONICAL_ALIAS, "alias")
+    }
+
+    /// The other aliases advertised in `m.room.canonical_alias`.
+    pub fn alt_aliases(&self) -> Vec<String> {
+        self.get_state_event(STATE_CANONICAL_ALIAS.to_string(), String::new())
+            .and_then(|event| event.content_json("alt_aliases"))
+            .and_then(|aliases| {
+                aliases.as_array().map(|aliases| {
+                    aliases
+                        .iter()
+                        .filter_map(|alias| alias.as_str())
+                        .map(str::to_string)
+                        .collect()
+                })
+            })
+            .unwrap_or_default()
+    }
+
+    /// The room's topic from `m.room.topic`.
+    pub fn topic(&self) -> Option<String> {
+        self.state_content(STATE_TOPIC, "topic")
+    }
+
+    /// The `mxc://` URL of the room's avatar from `m.room.avatar`.
+    pub fn avatar_url(&self) -> Option<String> {
+        self.state_content(STATE_AVATAR, "url")
+    }
+
+    /// The room version from `m.room.create`, which defaults to `"1"` when the
+    /// event doesn't say.
+    pub fn room_version(&self) -> String {
+        self.state_content(STATE_CREATE, "room_version")
+            .unwrap_or_else(|| "1".to_string())
+    }
+
+    /// The user who created the room. Room version 11 dropped the `creator` field,
+    /// so the sender of `m.room.create` is used when it is missing.
+    pub fn creator(&self) -> Option<String> {
+        self.state_content(STATE_CREATE, "creator").or_else(|| {
+            self.get_state_event(STATE_CREATE.to_string(), String::new())
+                .map(|event| event.sender.clone())
+                .filter(|sender| !sender.is_empty())
+        })
+    }
+
+    /// The room this one replaced when it was upgraded, as its room id and the id
+    /// of the old room's last event, from the `predecessor` of `m.room.create`. The
+    /// event id is empty if the create event doesn't give one.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::collections::HashMap;
+    /// use matrux::events::Event;
+    /// use matrux::room::Room;
+    ///
+    /// let state_event = |etype: &str, key: &str, value: &str| {
+    ///     let mut content = HashMap::new();
+    ///     content.insert(key.to_string(), value.to_string());
+    ///     Event { etype: etype.to_string(), content, ..Default::default() }
+    /// };
+    ///
+    /// let upgraded = Room::with_state(
+    ///     "!new:example.org".to_string(),
+    ///     vec![state_event(
+    ///         "m.room.create",
+    ///         "predecessor",
+    ///         r#"{"room_id":"!old:example.org","event_id":"$last"}"#,
+    ///     )],
+    /// );
+    /// assert_eq!(
+    ///     upgraded.predecessor(),
+    ///     Some(("!old:example.org".to_string(), "$last".to_string()))
+    /// );
+    ///
+    /// let replaced = Room::with_state(
+    ///     "!old:example.org".to_string(),
+    ///     vec![state_event("m.room.tombstone", "replacement_room", "!new:example.org")],
+    /// );
+    /// assert_eq!(replaced.successor().as_deref(), Some("!new:example.org"));
+    /// assert_eq!(replaced.predecessor(), None);
+    /// ```
+    pub fn predecessor(&self) -> Option<(String, String)> {
+        let predecessor = self
+            .get_state_event(STATE_CREATE.to_string(), String::new())?
+            .content_json("predecessor")?;
+        let room_id = predecessor["room_id"].as_str().filter(|room_id| !room_id.is_empty())?;
+        let event_id = predecessor["event_id"].as_str().unwrap_or_default();
+
+        Some((room_id.to_string(), event_id.to_string()))
+    }
+
+    /// The room that replaced this one, from the `replacement_room` of its
+    /// `m.room.tombstone`. A room with a tombstone is no longer used.
+    pub fn successor(&self) -> Option<String> {
+        self.state_content(STATE_TOMBSTONE, "replacement_room")
+    }
+
+    /// Whether the room has an `m.room.encryption` state event. Once enabled,
+    /// encryption can't be turned off again.
+    pub fn is_encrypted(&self) -> bool {
+        self.get_state_event(STATE_ENCRYPTION.to_string(), String::new())
+            .is_some()
+    }
+
+    /// The algorithm messages in this room must be encrypted with.
+    pub fn encryption_algorithm(&self) -> Option<String> {
+        self.encryption().map(|encryption| encryption.algorithm)
+    }
 
-        state_event_map.get(&state_key)
+    /// The room's encryption settings, including session rotation periods.
+    pub fn encryption(&self) -> Option<EncryptionSettings> {
+        self.get_state_event(STATE_ENCRYPTION.to_string(), String::new())
+            .and_then(EncryptionSettings::from_event)
     }
 
-    fn get_membership_state(&self, user_id: String) -> String {
-        let state = "leave".to_string();
-        let event_type = "m.room.member".to_string();
+    /// Who may join the room, from `m.room.join_rules`. Defaults to `"invite"`.
+    pub fn join_rule(&self) -> String {
+        self.state_content(STATE_JOIN_RULES, "join_rule")
+            .unwrap_or_else(|| "invite".to_string())
+    }
+
+    /// The rooms whose members may join a `restricted` (or `knock_restricted`) room
+    /// without an invite. Empty for any other join rule.
+    pub fn join_allow_list(&self) -> Vec<String> {
+        if !matches!(self.join_rule().as_str(), "restricted" | "knock_restricted") {
+            return Vec::new();
+        }
+
+        self.get_state_event(STATE_JOIN_RULES.to_string(), String::new())
+            .and_then(|event| event.content_json("allow"))
+            .and_then(|allow| {
+                allow.as_array().map(|conditions| {
+                    conditions
+                        .iter()
+                        .filter(|condition| condition["type"] == "m.room_membership")
+                        .filter_map(|condition| condition["room_id"].as_str())
+                        .map(str::to_string)
+                        .collect()
+                })
+            })
+            .unwrap_or_default()
+    }
+
+    /// Whether guests may join, from `m.room.guest_access`. Defaults to `"forbidden"`.
+    pub fn guest_access(&self) -> String {
+        self.state_content(STATE_GUEST_ACCESS, "guest_access")
+            .unwrap_or_else(|| "forbidden".to_string())
+    }
+
+    /// The `name` from `m.room.name`, unless it is missing or blank, which the spec
+    /// treats as the room having no name.
+    pub fn raw_name(&self) -> Option<String> {
+        self.state_content(STATE_NAME, "name")
+            .filter(|name| !name.trim().is_empty())
+    }
+
+    /// Computes a human-readable name for the room.
+    ///
+    /// Uses the [`raw_name`](Room::raw_name) if there is one, cut to 255 characters,
+    /// then `m.room.canonical_alias`, and otherwise names the room after up to five
+    /// joined or invited members ("heroes") other than the
+    /// [own user](Room::own_user_id). A room without any such members is an "Empty
+    /// Room", and a room with no member state at all falls back to its id.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::collections::HashMap;
+    /// use matrux::events::Event;
+    /// use matrux::room::Room;
+    ///
+    /// let named = |name: String| {
+    ///     let mut content = HashMap::new();
+    ///     content.insert("name".to_string(), name);
+    ///     Room::with_state(
+    ///         "!room:example.org".to_string(),
+    ///         vec![Event { etype: "m.room.name".to_string(), content, ..Default::default() }],
+    ///     )
+    /// };
+    ///
+    /// assert_eq!(named(" ".to_string()).raw_name(), None);
+    /// assert_eq!(named(" ".to_string()).display_name(), "!room:example.org");
+    /// assert_eq!(named("a".repeat(300)).display_name().chars().count(), 255);
+    /// ```
+    pub fn display_name(&self) -> String {
+        if let Some(name) = self.raw_name() {
+            return name.chars().take(MAX_NAME_LENGTH).collect();
+        }
+
+        if let Some(alias) = self.canonical_alias() {
+            return alias;
+        }
+
+        let members = self.members();
 
-        let event = self.get_state_event(event_type, user_id);
+        if members.is_empty() {
+            return self.id.clone();
+        }
 
-        match event {
-            Some(event) => {
-                let membership_state = event.content.get("membership").unwrap();
+        let heroes: Vec<String> = members
+            .iter()
+            .filter(|(user_id, membership)| {
+                (membership == MEMBERSHIP_JOIN || membership == MEMBERSHIP_INVITE)
+                    && self.own_user_id.as_ref() != Some(user_id)
+            })
+            .map(|(user_id, _)| self.member_display_name(user_id))
+            .collect();
 
-                String::from(membership_state)
+        match heroes.len() {
+            0 => "Empty Room".to_string(),
+            1 => heroes[0].clone(),
+            2..=5 => {
+                let (last, rest) = heroes.split_last().unwrap();
+                format!("{} and {}", rest.join(", "), last)
             }
+            count => format!("{} and {} others", heroes[..5].join(", "), count - 5),
+        }
+    }
+
+    /// Returns the power level of `user_id` according to `m.room.power_levels`,
+    /// falling back to `users_default`, or `0` when the room has no power levels.
+    pub fn power_level(&self, user_id: &str) -> i64 {
+        self.power_levels()
+            .map_or(0, |power_levels| power_levels.user_level(user_id))
+    }
+
+    /// Checks whether `user_id` has a high enough power level to send a message event
+    /// of type `event_type`.
+    pub fn can_send_event(&self, user_id: &str, event_type: &str) -> bool {
+        self.can_send(user_id, event_type, false)
+    }
+
+    /// Checks whether `user_id` has a high enough power level to send a state event of
+    /// type `event_type`, which without an explicit level needs `state_default`.
+    pub fn can_send_state_event(&self, user_id: &str, event_type: &str) -> bool {
+        self.can_send(user_id, event_type, true)
+    }
+
+    fn can_send(&self, user_id: &str, event_type: &str, is_state: bool) -> bool {
+        let required = self
+            .power_levels()
+            .map_or(0, |power_levels| power_levels.event_level(event_type, is_state));
+
+        self.power_level(user_id) >= required
+    }
+
+    /// Gathers what a room list needs to show for the room.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::collections::HashMap;
+    /// use matrux::events::Event;
+    /// use matrux::room::Room;
+    ///
+    /// let mut room = Room::new("!room:example.org".to_string());
+    /// room.mark_joined("@alice:example.org");
+    /// room.set_unread_counts(2, 0);
+    ///
+    /// let mut content = HashMap::new();
+    /// content.insert("msgtype".to_string(), "m.text".to_string());
+    /// content.insert("body".to_string(), "See you at ten".to_string());
+    /// room.append_timeline(Event {
+    ///     etype: "m.room.message".to_string(),
+    ///     id: "$1".to_string(),
+    ///     content,
+    ///     ..Default::default()
+    /// });
+    ///
+    /// let summary = room.summary();
+    /// assert_eq!(summary.display_name, "@alice:example.org");
+    /// assert_eq!(summary.member_count, 1);
+    /// assert_eq!(summary.last_message.as_deref(), Some("See you at ten"));
+    /// assert_eq!(summary.unread_count, 2);
+    /// ```
+    pub fn summary(&self) -> RoomSummary {
+        let last_message = self
+            .timeline
+            .iter()
+            .rev()
+            .filter(|event| event.etype == EVENT_MESSAGE)
+            .find_map(Event::body_str);
+
+        RoomSummary {
+            display_name: self.display_name(),
+            avatar_url: self.avatar_url(),
+            topic: self.topic(),
+            member_count: self.joined_members().len(),
+            last_message,
+            unread_count: self.notification_count(),
+        }
+    }
+
+    /// The state an invite of `invitee` to the room carries, stripped down to `type`,
+    /// `state_key`, `sender` and `content`: the room's create event, name, avatar,
+    /// canonical alias, join rules and encryption, and the member events of `invitee`
+    /// and of whoever sent their invite.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::collections::HashMap;
+    /// use matrux::events::Event;
+    /// use matrux::room::Room;
+    ///
+    /// let mut room = Room::new("!room:example.org".to_string());
+    /// room.mark_joined("@alice:example.org");
+    /// room.mark_joined("@bob:example.org");
+    ///
+    /// let state = [
+    ///     ("m.room.name", "name", "Book club"),
+    ///     ("m.room.topic", "topic", "Chapter 3"),
+    /// ];
+    /// for (etype, key, value) in state {
+    ///     let mut content = HashMap::new();
+    ///     content.insert(key.to_string(), value.to_string());
+    ///     room.update_state(Event {
+    ///         etype: etype.to_string(),
+    ///         id: format!("${}", key),
+    ///         content,
+    ///         ..Default::default()
+    ///     });
+    /// }
+    ///
+    /// let stripped = room.stripped_state("@carol:example.org");
+    /// assert_eq!(stripped.len(), 1);
+    /// assert_eq!(stripped[0].etype, "m.room.name");
+    /// assert!(stripped[0].id.is_empty());
+    ///
+    /// let invite = Room::from_invite_state("!room:example.org".to_string(), stripped);
+    /// assert_eq!(invite.display_name(), "Book club");
+    /// ```
+    pub fn stripped_state(&self, invitee: &str) -> Vec<Event<T>> {
+        let mut events: Vec<&Event<T>> = STRIPPED_STATE_TYPES
+            .iter()
+            .filter_map(|etype| self.get_state_event(etype.to_string(), String::new()))
+            .collect();
+
+        let invite = self.get_state_event(STATE_MEMBER.to_string(), invitee.to_string());
+        if let Some(invite) = invite {
+            let inviter = self.get_state_event(STATE_MEMBER.to_string(), invite.sender.clone());
+            events.extend(inviter.filter(|inviter| inviter.state_key != invitee));
+            events.push(invite);
+        }
+
+        events
+            .into_iter()
+            .map(|event| {
+                EventBuilder::new()
+                    .etype(event.etype.clone())
+                    .state_key(event.state_key.clone())
+                    .sender(event.sender.clone())
+                    .content(event.content.clone())
+                    .build()
+            })
+            .collect()
+    }
+
+    /// The room's `m.room.power_levels`, if it has any.
+    pub fn power_levels(&self) -> Option<PowerLevels> {
+        self.get_state_event(STATE_POWER_LEVELS.to_string(), String::new())
+            .map(PowerLevels::from_event)
+    }
+
+    /// The name to show for `user_id`: their display name, followed by their user
+    /// id in brackets when another joined member has the same display name. Users
+    /// without a display name are shown by their user id.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::collections::HashMap;
+    /// use matrux::events::Event;
+    /// use matrux::room::Room;
+    ///
+    /// let member = |user_id: &str, displayname: Option<&str>| {
+    ///     let mut content = HashMap::new();
+    ///     content.insert("membership".to_string(), "join".to_string());
+    ///     if let Some(displayname) = displayname {
+    ///         content.insert("displayname".to_string(), displayname.to_string());
+    ///     }
+    ///     Event {
+    ///         etype: "m.room.member".to_string(),
+    ///         state_key: user_id.to_string(),
+    ///         content,
+    ///         ..Default::default()
+    ///     }
+    /// };
+    ///
+    /// let room = Room::with_state(
+    ///     "!room:example.org".to_string(),
+    ///     vec![
+    ///         member("@alice:example.org", Some("Alice")),
+    ///         member("@sam:one.org", Some("Sam")),
+    ///         member("@sam:two.org", Some("Sam")),
+    ///         member("@nameless:example.org", None),
+    ///     ],
+    /// );
+    ///
+    /// assert_eq!(room.disambiguated_name("@alice:example.org"), "Alice");
+    /// assert_eq!(room.disambiguated_name("@sam:one.org"), "Sam (@sam:one.org)");
+    /// assert_eq!(room.disambiguated_name("@nameless:example.org"), "@nameless:example.org");
+    /// ```
+    pub fn disambiguated_name(&self, user_id: &str) -> String {
+        let Some(name) = self
+            .resolve_member(user_id)
+            .and_then(|member| member.displayname)
+        else {
+            return user_id.to_string();
+        };
+
+        let collides = self
+            .joined_members()
+            .iter()
+            .filter(|other| other.as_str() != user_id)
+            .any(|other| {
+                self.resolve_member(other)
+                    .and_then(|member| member.displayname)
+                    .as_deref()
+                    == Some(name.as_str())
+            });
+
+        if collides {
+            format!("{} ({})", name, user_id)
+        } else {
+            name
+        }
+    }
+
+    fn member_display_name(&self, user_id: &str) -> String {
+        self.get_state_event(STATE_MEMBER.to_string(), user_id.to_string())
+            .and_then(Event::member_content)
+            .and_then(|member| member.displayname)
+            .unwrap_or_else(|| user_id.to_string())
+    }
+
+    fn state_content(&self, event_type: &str, key: &str) -> Option<String> {
+        self.get_state_event(event_type.to_string(), String::new())
+            .and_then(|event| event.content.get(key))
+            .map(String::from)
+            .filter(|value| !value.is_empty())
+    }
+
+    /// The membership of `user_id`, or `leave` when they have no member event or
+    /// its `membership` is missing or not one the spec defines.
+    pub fn get_membership_state(&self, user_id: String) -> String {
+        self.resolve_member(&user_id)
+            .map(|member| member.membership)
+            .filter(|membership| MEMBERSHIPS.contains(&membership.as_str()))
+            .unwrap_or_else(|| MEMBERSHIP_LEAVE.to_string())
+    }
+}
+
+impl Room<String> {
+    /// Groups the `m.annotation` reactions to `event_id` by key, listing the senders
+    /// who reacted with each. Redacted reactions are not counted.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::collections::HashMap;
+    /// use matrux::events::Event;
+    /// use matrux::room::Room;
+    ///
+    /// let mut room = Room::new("!room:example.org".to_string());
+    ///
+    /// for (id, sender) in [("$r1", "@alice:example.org"), ("$r2", "@bob:example.org")] {
+    ///     let mut content = HashMap::new();
+    ///     content.insert(
+    ///         "m.relates_to".to_string(),
+    ///         r#"{"rel_type":"m.annotation","event_id":"$message","key":"👍"}"#.to_string(),
+    ///     );
+    ///     room.append_timeline(Event {
+    ///         etype: "m.reaction".to_string(),
+    ///         id: id.to_string(),
+    ///         sender: sender.to_string(),
+    ///         content,
+    ///         ..Default::default()
+    ///     });
+    /// }
+    ///
+    /// room.apply_redaction(Event {
+    ///     etype: "m.room.redaction".to_string(),
+    ///     id: "$redaction".to_string(),
+    ///     redacts: "$r2".to_string(),
+    ///     ..Default::default()
+    /// });
+    ///
+    /// assert_eq!(room.reactions_for("$message")["👍"], ["@alice:example.org"]);
+    /// ```
+    pub fn reactions_for(&self, event_id: &str) -> HashMap<String, Vec<String>> {
+        let mut reactions: HashMap<String, Vec<String>> = HashMap::new();
+
+        for event in self.relations_for(event_id) {
+            if event.etype != EVENT_REACTION || event.is_redacted() {
+                continue;
+            }
+
+            let content = event.content_value();
+            let relation = &content[KEY_RELATES_TO];
+
+            if relation["rel_type"] != REL_ANNOTATION || relation["event_id"] != event_id {
+                continue;
+            }
+
+            let Some(key) = relation["key"].as_str() else {
+                continue;
+            };
+
+            let senders = reactions.entry(key.to_string()).or_default();
+
+            if !senders.contains(&event.sender) {
+                senders.push(event.sender.clone());
+            }
+        }
+
+        reactions
+    }
+
+    /// Collects the thread rooted at `root`, starting with the root event itself,
+    /// in timeline order. Plain replies to the root are not part of the thread, and
+    /// neither are redacted messages other than the root.
+    pub fn thread_messages(&self, root: &str) -> Vec<&Event<String>> {
+        let replies = self
+            .relations_for(root)
+            .into_iter()
+            .filter(|event| !event.is_redacted() && event.thread_root().as_deref() == Some(root));
+
+        self.timeline
+            .iter()
+            .filter(|event| event.id == root)
+            .chain(replies)
+            .collect()
+    }
+
+    /// The timeline as shown to a user ignoring `ignored`: their messages are left
+    /// out, but their state events, such as joining or leaving, are kept. Their
+    /// events still update the room's state either way.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::collections::HashMap;
+    /// use matrux::events::{Event, IgnoredUsers};
+    /// use matrux::room::Room;
+    ///
+    /// let mut room = Room::new("!room:example.org".to_string());
+    ///
+    /// let mut membership = HashMap::new();
+    /// membership.insert("membership".to_string(), "join".to_string());
+    /// room.append_timeline(Event {
+    ///     etype: "m.room.member".to_string(),
+    ///     id: "$join".to_string(),
+    ///     sender: "@spam:example.org".to_string(),
+    ///     state_key: "@spam:example.org".to_string(),
+    ///     content: membership,
+    ///     ..Default::default()
+    /// });
+    ///
+    /// for (id, sender) in [("$ad", "@spam:example.org"), ("$hello", "@alice:example.org")] {
+    ///     room.append_timeline(Event {
+    ///         etype: "m.room.message".to_string(),
+    ///         id: id.to_string(),
+    ///         sender: sender.to_string(),
+    ///         ..Default::default()
+    ///     });
+    /// }
+    ///
+    /// let ignored = IgnoredUsers { users: ["@spam:example.org".to_string()].into() };
+    /// let ids: Vec<&str> = room
+    ///     .visible_timeline(&ignored)
+    ///     .into_iter()
+    ///     .map(|event| event.id.as_str())
+    ///     .collect();
+    ///
+    /// assert_eq!(ids, ["$join", "$hello"]);
+    /// assert_eq!(room.get_membership_state("@spam:example.org".to_string()), "join");
+    /// ```
+    pub fn visible_timeline(&self, ignored: &IgnoredUsers) -> Vec<&Event<String>> {
+        self.timeline
+            .iter()
+            .filter(|event| event.is_state_event() || !ignored.is_ignored(&event.sender))
+            .collect()
+    }
+
+    /// Finds the messages in the timeline whose `body` contains `query`, newest
+    /// first. Other event types are skipped, and an empty query matches nothing.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::collections::HashMap;
+    /// use matrux::events::Event;
+    /// use matrux::room::Room;
+    ///
+    /// let mut room = Room::new("!room:example.org".to_string());
+    ///
+    /// for (id, body) in [("$1", "Lunch at noon?"), ("$2", "Sure, lunch works")] {
+    ///     let mut content = HashMap::new();
+    ///     content.insert("msgtype".to_string(), "m.text".to_string());
+    ///     content.insert("body".to_string(), body.to_string());
+    ///     room.append_timeline(Event {
+    ///         etype: "m.room.message".to_string(),
+    ///         id: id.to_string(),
+    ///         content,
+    ///         ..Default::default()
+    ///     });
+    /// }
+    ///
+    /// let ids = |events: Vec<&Event<String>>| -> Vec<String> {
+    ///     events.into_iter().map(|event| event.id.clone()).collect()
+    /// };
+    /// assert_eq!(ids(room.search("LUNCH", false)), ["$2", "$1"]);
+    /// assert_eq!(ids(room.search("Lunch", true)), ["$1"]);
+    /// ```
+    pub fn search(&self, query: &str, case_sensitive: bool) -> Vec<&Event<String>> {
+        if query.is_empty() {
+            return Vec::new();
+        }
+
+        let query = if case_sensitive { query.to_string() } else { query.to_lowercase() };
+
+        self.timeline
+            .iter()
+            .rev()
+            .filter(|event| event.etype == EVENT_MESSAGE)
+            .filter(|event| match event.body() {
+                Some(body) if case_sensitive => body.contains(&query),
+                Some(body) => body.to_lowercase().contains(&query),
+                None => false,
+            })
+            .collect()
+    }
+}
+
+/// The id of the event `event` relates to through `m.relates_to`. Replies are not
+/// relations, since `m.in_reply_to` has no `rel_type`.
+fn relation_target<T: 'static + Clone>(event: &Event<T>) -> Option<String>
+where
+    String: for<'a> From<&'a T>,
+{
+    let relation = event.content_json(KEY_RELATES_TO)?;
+    relation.get("rel_type")?;
+    relation["event_id"].as_str().map(str::to_string)
+}
+
+/// Replaces `existing` with `event`, carrying over the `unsigned` fields that only
+/// `existing` has.
+fn replace_keeping_unsigned<T: 'static + Clone>(existing: &mut Event<T>, event: Event<T>)
+where
+    String: for<'a> From<&'a T>,
+{
+    let previous = std::mem::replace(existing, event);
+    let newer = std::mem::replace(&mut existing.unsigned, previous.unsigned);
+    existing.merge_unsigned(&newer);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(etype: &str, state_key: &str, content: &[(&str, &str)]) -> Event<String> {
+        let content: HashMap<String, String> = content
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+
+        EventBuilder::new()
+            .etype(etype.to_string())
+            .state_key(state_key.to_string())
+            .content(content)
+            .build()
+    }
+
+    fn related(id: &str, sender: &str, etype: &str, relates_to: &str) -> Event<String> {
+        let mut content = HashMap::new();
+        content.insert(KEY_RELATES_TO.to_string(), relates_to.to_string());
+
+        EventBuilder::new()
+            .etype(etype.to_string())
+            .id(id.to_string())
+            .sender(sender.to_string())
+            .content(content)
+            .build()
+    }
+
+    fn reaction(id: &str, sender: &str) -> Event<String> {
+        let relates_to = r#"{"rel_type":"m.annotation","event_id":"$root","key":"👍"}"#;
+        related(id, sender, EVENT_REACTION, relates_to)
+    }
+
+    fn thread_reply(id: &str) -> Event<String> {
+        let relates_to = r#"{"rel_type":"m.thread","event_id":"$root"}"#;
+        related(id, "@alice:example.org", EVENT_MESSAGE, relates_to)
+    }
+
+    fn root() -> Event<String> {
+        EventBuilder::new()
+            .etype(EVENT_MESSAGE.to_string())
+            .id("$root".to_string())
+            .content(HashMap::new())
+            .build()
+    }
+
+    fn ids<'a>(events: impl IntoIterator<Item = &'a Event<String>>) -> Vec<&'a str> {
+        events.into_iter().map(|event| event.id.as_str()).collect()
+    }
+
+    #[test]
+    fn relations_include_backfilled_events() {
+        let mut room = Room::<String>::new("!room:example.org".to_string());
+        room.append_timeline(reaction("$r2", "@alice:example.org"));
+        assert_eq!(room.reactions_for("$root")["👍"], ["@alice:example.org"]);
+
+        room.prepend_timeline(vec![
+            root(),
+            reaction("$r1", "@bob:example.org"),
+            thread_reply("$t1"),
+        ]);
+
+        assert_eq!(room.reactions_for("$root")["👍"], ["@bob:example.org", "@alice:example.org"]);
+        assert_eq!(ids(room.thread_messages("$root")), ["$root", "$t1"]);
+        assert_eq!(ids(room.relations_for("$root")), ["$r1", "$t1", "$r2"]);
+
+        room.append_timeline(thread_reply("$t2"));
+        assert_eq!(ids(room.thread_messages("$root")), ["$root", "$t1", "$t2"]);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn relations_follow_merged_syncs() {
+        let mut room = Room::<String>::new("!room:example.org".to_string());
+        room.append_timeline(root());
+        room.add_local_echo("txn1", reaction("", "@alice:example.org"));
+        assert_eq!(room.reactions_for("$root")["👍"], ["@alice:example.org"]);
+
+        let mut confirmed = reaction("$r1", "@alice:example.org");
+        confirmed.unsigned.insert("transaction_id".to_string(), "txn1".to_string());
+
+        let mut newer = Room::new("!room:example.org".to_string());
+        newer.append_timeline(confirmed);
+        newer.append_timeline(reaction("$r2", "@bob:example.org"));
+        newer.append_timeline(thread_reply("$t1"));
+        room.merge(newer);
+
+        assert_eq!(room.reactions_for("$root")["👍"], ["@alice:example.org", "@bob:example.org"]);
+        assert_eq!(ids(room.relations_for("$root")), ["$r1", "$r2", "$t1"]);
+        assert_eq!(ids(room.thread_messages("$root")), ["$root", "$t1"]);
+    }
 
-            None => state,
+    #[test]
+    fn relations_index_is_updated_in_place() {
+        let mut room = Room::<String>::new("!room:example.org".to_string());
+        room.append_timeline(root());
+        room.append_timeline(reaction("$r2", "@alice:example.org"));
+        assert_eq!(ids(room.relations_for("$root")), ["$r2"]);
+
+        room.append_timeline(thread_reply("$t1"));
+        room.prepend_timeline(vec![reaction("$r1", "@bob:example.org")]);
+        room.apply_redaction(redaction("$redaction", "$r2"));
+
+        let relations = room.relations.get().expect("the index is kept, not dropped");
+        assert_eq!(relations.related["$root"], [-1, 2]);
+        assert_eq!(ids(room.relations_for("$root")), ["$r1", "$t1"]);
+    }
+
+    fn redaction(id: &str, redacts: &str) -> Event<String> {
+        EventBuilder::new()
+            .etype(EVENT_REDACTION.to_string())
+            .id(id.to_string())
+            .redacts(redacts.to_string())
+            .build()
+    }
+
+    #[test]
+    fn appended_redactions_empty_the_redacted_message() {
+        let mut message = root();
+        message.content.insert("body".to_string(), "secret".to_string());
+
+        let mut room = Room::<String>::new("!room:example.org".to_string());
+        room.append_timeline(message);
+        room.append_timeline(redaction("$redaction", "$root"));
+
+        let timeline = room.recent_messages(usize::MAX);
+        assert!(timeline[0].content.is_empty());
+        assert!(timeline[0].is_redacted());
+        assert_eq!(timeline[0].unsigned["redacted_because"], "$redaction");
+        assert_eq!(ids(timeline), ["$root", "$redaction"]);
+    }
+
+    #[test]
+    fn redacting_a_member_keeps_the_membership() {
+        let mut member = state(
+            STATE_MEMBER,
+            "@alice:example.org",
+            &[("membership", "join"), ("displayname", "Alice")],
+        );
+        member.id = "$member".to_string();
+
+        let mut room = Room::<String>::new("!room:example.org".to_string());
+        room.append_timeline(member);
+        room.prepend_timeline(vec![redaction("$redaction", "$member")]);
+
+        let member = room
+            .get_state_event(STATE_MEMBER.to_string(), "@alice:example.org".to_string())
+            .unwrap();
+        assert_eq!(member.content.len(), 1);
+        assert_eq!(member.content[KEY_MEMBERSHIP], "join");
+        assert!(member.is_redacted());
+    }
+
+    fn member(user_id: &str, membership: &str, displayname: &str) -> Event<String> {
+        state(
+            STATE_MEMBER,
+            user_id,
+            &[("membership", membership), ("displayname", displayname)],
+        )
+    }
+
+    #[test]
+    fn display_name_prefers_the_name_then_the_canonical_alias() {
+        let mut room = Room::<String>::new("!room:example.org".to_string());
+        room.update_state(member("@bob:example.org", "join", "Bob"));
+        room.update_state(state(
+            STATE_CANONICAL_ALIAS,
+            "",
+            &[("alias", "#books:example.org")],
+        ));
+        assert_eq!(room.display_name(), "#books:example.org");
+
+        room.update_state(state(STATE_NAME, "", &[("name", "Book club")]));
+        assert_eq!(room.display_name(), "Book club");
+    }
+
+    #[test]
+    fn display_name_names_the_room_after_other_members() {
+        let mut room = Room::<String>::new("!room:example.org".to_string());
+        room.set_own_user_id("@alice:example.org");
+        room.update_state(member("@alice:example.org", "join", "Alice"));
+        room.update_state(member("@bob:example.org", "join", "Bob"));
+        assert_eq!(room.display_name(), "Bob");
+
+        room.update_state(member("@carol:example.org", "invite", "Carol"));
+        room.update_state(member("@dave:example.org", "leave", "Dave"));
+        assert_eq!(room.display_name(), "Bob and Carol");
+
+        for name in ["Erin", "Frank", "Grace", "Heidi"] {
+            let user_id = format!("@{}:example.org", name.to_lowercase());
+            room.update_state(member(&user_id, "join", name));
         }
+        assert_eq!(
+            room.display_name(),
+            "Bob, Carol, Erin, Frank, Grace and 1 others"
+        );
+    }
+
+    #[test]
+    fn display_name_of_a_room_without_other_members() {
+        let mut room = Room::<String>::new("!room:example.org".to_string());
+        assert_eq!(room.display_name(), "!room:example.org");
+
+        room.set_own_user_id("@alice:example.org");
+        room.update_state(member("@alice:example.org", "join", "Alice"));
+        room.update_state(member("@bob:example.org", "leave", "Bob"));
+        assert_eq!(room.display_name(), "Empty Room");
+    }
+
+    #[test]
+    fn state_events_need_state_default() {
+        let mut room = Room::<String>::new("!room:example.org".to_string());
+        room.update_state(state(
+            STATE_POWER_LEVELS,
+            "",
+            &[
+                ("users", r#"{"@mod:example.org": 10, "@admin:example.org": 100}"#),
+                ("events", r#"{"m.room.pinned_events": 10}"#),
+                ("events_default", "0"),
+                ("state_default", "50"),
+            ],
+        ));
+
+        assert!(room.can_send_event("@mod:example.org", EVENT_MESSAGE));
+        assert!(!room.can_send_state_event("@mod:example.org", STATE_TOPIC));
+        assert!(room.can_send_state_event("@mod:example.org", "m.room.pinned_events"));
+        assert!(room.can_send_state_event("@admin:example.org", STATE_TOPIC));
+        assert!(room.can_send_event("@guest:example.org", EVENT_MESSAGE));
+        assert!(!room.can_send_state_event("@guest:example.org", STATE_NAME));
+    }
+
+    #[test]
+    fn stripped_state_holds_only_the_inviter_and_invitee_members() {
+        let mut room = Room::<String>::new("!room:example.org".to_string());
+        room.update_state(state(STATE_CREATE, "", &[("creator", "@alice:example.org")]));
+        room.update_state(state(STATE_NAME, "", &[("name", "Book club")]));
+        room.update_state(state(STATE_TOPIC, "", &[("topic", "Chapter 3")]));
+        room.update_state(state(STATE_JOIN_RULES, "", &[("join_rule", "invite")]));
+        for user_id in ["@alice:example.org", "@bob:example.org"] {
+            room.update_state(state(STATE_MEMBER, user_id, &[("membership", "join")]));
+        }
+
+        let mut invite = state(STATE_MEMBER, "@carol:example.org", &[("membership", "invite")]);
+        invite.sender = "@alice:example.org".to_string();
+        room.update_state(invite);
+
+        let stripped: Vec<(String, String, String)> = room
+            .stripped_state("@carol:example.org")
+            .into_iter()
+            .map(|event| (event.etype, event.state_key, event.sender))
+            .collect();
+        let expected = [
+            (STATE_CREATE, "", ""),
+            (STATE_NAME, "", ""),
+            (STATE_JOIN_RULES, "", ""),
+            (STATE_MEMBER, "@alice:example.org", ""),
+            (STATE_MEMBER, "@carol:example.org", "@alice:example.org"),
+        ];
+        let expected: Vec<(String, String, String)> = expected
+            .iter()
+            .map(|(etype, state_key, sender)| {
+                (etype.to_string(), state_key.to_string(), sender.to_string())
+            })
+            .collect();
+        assert_eq!(stripped, expected);
+
+        // Without an invite there are no members to include.
+        let stripped = room.stripped_state("@dave:example.org");
+        assert!(stripped.iter().all(|event| event.etype != STATE_MEMBER));
+    }
+
+    #[test]
+    fn membership_of_malformed_member_is_leave() {
+        let mut room = Room::<String>::new("!room:example.org".to_string());
+        room.update_state(state(STATE_MEMBER, "@alice:example.org", &[("displayname", "Alice")]));
+        room.update_state(state(STATE_MEMBER, "@bob:example.org", &[("membership", "dancing")]));
+        room.update_state(state(STATE_MEMBER, "@carol:example.org", &[("membership", "join")]));
+
+        assert_eq!(room.get_membership_state("@alice:example.org".to_string()), "leave");
+        assert_eq!(room.get_membership_state("@bob:example.org".to_string()), "leave");
+        assert_eq!(room.get_membership_state("@carol:example.org".to_string()), "join");
+        assert_eq!(room.get_membership_state("@dave:example.org".to_string()), "leave");
+    }
+
+    #[test]
+    fn update_state_replaces_the_member_event() {
+        let mut room = Room::<String>::new("!room:example.org".to_string());
+        room.update_state(member("@alice:example.org", "invite", "Alice"));
+        room.update_state(member("@alice:example.org", "join", "Alice Liddell"));
+
+        let event = room
+            .get_state_event(STATE_MEMBER.to_string(), "@alice:example.org".to_string())
+            .unwrap();
+        assert_eq!(event.content[KEY_MEMBERSHIP], "join");
+        assert_eq!(event.content["displayname"], "Alice Liddell");
+        assert_eq!(room.members().len(), 1);
+    }
+
+    #[test]
+    fn missing_state_is_none() {
+        let mut room = Room::<String>::new("!room:example.org".to_string());
+        assert!(room.get_state_event(STATE_TOPIC.to_string(), String::new()).is_none());
+
+        room.update_state(member("@alice:example.org", "join", "Alice"));
+        let missing = room.get_state_event(STATE_MEMBER.to_string(), "@bob:example.org".to_string());
+        assert!(missing.is_none());
+    }
+
+    #[test]
+    fn members_lists_every_membership_and_joined_members_only_joins() {
+        let mut room = Room::<String>::new("!room:example.org".to_string());
+        room.update_state(member("@carol:example.org", "invite", "Carol"));
+        room.update_state(member("@alice:example.org", "join", "Alice"));
+        room.update_state(member("@dave:example.org", "join", "Dave"));
+        room.update_state(member("@bob:example.org", "leave", "Bob"));
+
+        let members = room.members();
+        let members: Vec<(&str, &str)> = members
+            .iter()
+            .map(|(user_id, membership)| (user_id.as_str(), membership.as_str()))
+            .collect();
+        assert_eq!(
+            members,
+            [
+                ("@alice:example.org", "join"),
+                ("@bob:example.org", "leave"),
+                ("@carol:example.org", "invite"),
+                ("@dave:example.org", "join"),
+            ]
+        );
+        assert_eq!(room.joined_members(), ["@alice:example.org", "@dave:example.org"]);
+    }
+
+    #[test]
+    fn recent_messages_keep_insertion_order() {
+        let mut room = Room::<String>::new("!room:example.org".to_string());
+        for id in ["$first", "$second", "$third"] {
+            room.append_timeline(
+                EventBuilder::new()
+                    .etype(EVENT_MESSAGE.to_string())
+                    .id(id.to_string())
+                    .build(),
+            );
+        }
+
+        assert_eq!(ids(room.recent_messages(usize::MAX)), ["$first", "$second", "$third"]);
+        assert_eq!(ids(room.recent_messages(2)), ["$second", "$third"]);
+        assert!(room.recent_messages(0).is_empty());
+    }
+
+    #[test]
+    fn public_room_is_built_from_room_state() {
+        let mut room = Room::<String>::new("!room:example.org".to_string());
+        room.update_state(state(STATE_NAME, "", &[("name", "Book club")]));
+        room.update_state(state(STATE_TOPIC, "", &[("topic", "Chapter 3")]));
+        room.update_state(member("@alice:example.org", "join", "Alice"));
+        room.update_state(member("@bob:example.org", "join", "Bob"));
+        room.update_state(member("@carol:example.org", "invite", "Carol"));
+
+        let public = PublicRoom::from_room(&room);
+        assert_eq!(public.room_id, "!room:example.org");
+        assert_eq!(public.name, "Book club");
+        assert_eq!(public.topic, "Chapter 3");
+        assert_eq!(public.num_joined_members, 2);
+        assert_eq!(public.canonical_alias, "");
+        assert_eq!(public.avatar_url, "");
+    }
+
+    #[test]
+    fn power_levels_of_admins_default_and_demoted_users() {
+        let mut room = Room::<String>::new("!room:example.org".to_string());
+        assert_eq!(room.power_level("@admin:example.org"), 0);
+        assert!(room.can_send_event("@admin:example.org", EVENT_MESSAGE));
+
+        room.update_state(state(
+            STATE_POWER_LEVELS,
+            "",
+            &[
+                ("users", r#"{"@admin:example.org": 100, "@muted:example.org": -1}"#),
+                ("users_default", "0"),
+                ("events", r#"{"m.room.topic": 50}"#),
+                ("events_default", "0"),
+            ],
+        ));
+
+        assert_eq!(room.power_level("@admin:example.org"), 100);
+        assert_eq!(room.power_level("@user:example.org"), 0);
+        assert_eq!(room.power_level("@muted:example.org"), -1);
+
+        assert!(room.can_send_event("@admin:example.org", STATE_TOPIC));
+        assert!(room.can_send_event("@user:example.org", EVENT_MESSAGE));
+        assert!(!room.can_send_event("@user:example.org", STATE_TOPIC));
+        assert!(!room.can_send_event("@muted:example.org", EVENT_MESSAGE));
+    }
+
+    #[test]
+    fn reactions_are_grouped_by_key() {
+        let heart = r#"{"rel_type":"m.annotation","event_id":"$root","key":"❤️"}"#;
+
+        let mut room = Room::<String>::new("!room:example.org".to_string());
+        room.append_timeline(root());
+        room.append_timeline(reaction("$r1", "@alice:example.org"));
+        room.append_timeline(reaction("$r2", "@bob:example.org"));
+        room.append_timeline(related("$r3", "@carol:example.org", EVENT_REACTION, heart));
+        room.append_timeline(reaction("$r4", "@carol:example.org"));
+        room.append_timeline(redaction("$redaction", "$r4"));
+
+        let reactions = room.reactions_for("$root");
+        assert_eq!(reactions.len(), 2);
+        assert_eq!(reactions["👍"], ["@alice:example.org", "@bob:example.org"]);
+        assert_eq!(reactions["❤️"], ["@carol:example.org"]);
+    }
+
+    #[test]
+    fn threads_hold_the_root_and_thread_replies_only() {
+        let plain_reply = r#"{"m.in_reply_to":{"event_id":"$root"}}"#;
+
+        let mut room = Room::<String>::new("!room:example.org".to_string());
+        room.append_timeline(root());
+        room.append_timeline(thread_reply("$t1"));
+        room.append_timeline(related("$reply", "@bob:example.org", EVENT_MESSAGE, plain_reply));
+        room.append_timeline(reaction("$r1", "@bob:example.org"));
+        room.append_timeline(thread_reply("$t2"));
+
+        assert_eq!(room.recent_messages(1)[0].thread_root().as_deref(), Some("$root"));
+        assert_eq!(root().thread_root(), None);
+        assert_eq!(ids(room.thread_messages("$root")), ["$root", "$t1", "$t2"]);
+        assert!(room.thread_messages("$elsewhere").is_empty());
+    }
+
+    #[test]
+    fn canonical_and_alt_aliases() {
+        let mut room = Room::<String>::new("!room:example.org".to_string());
+        assert_eq!(room.canonical_alias(), None);
+        assert!(room.alt_aliases().is_empty());
+
+        room.update_state(state(
+            STATE_CANONICAL_ALIAS,
+            "",
+            &[
+                ("alias", "#books:example.org"),
+                ("alt_aliases", r##"["#reading:example.org", "#club:example.org"]"##),
+            ],
+        ));
+        assert_eq!(room.canonical_alias().as_deref(), Some("#books:example.org"));
+        assert_eq!(room.alt_aliases(), ["#reading:example.org", "#club:example.org"]);
+
+        room.update_state(state(STATE_CANONICAL_ALIAS, "", &[("alias", "")]));
+        assert_eq!(room.canonical_alias(), None);
+        assert!(room.alt_aliases().is_empty());
+    }
+
+    #[test]
+    fn topic_and_avatar_url() {
+        let mut room = Room::<String>::new("!room:example.org".to_string());
+        assert_eq!(room.topic(), None);
+        assert_eq!(room.avatar_url(), None);
+
+        room.update_state(state(STATE_TOPIC, "", &[("topic", "Chapter 3")]));
+        room.update_state(state(STATE_AVATAR, "", &[("url", "mxc://example.org/avatar")]));
+        assert_eq!(room.topic().as_deref(), Some("Chapter 3"));
+        assert_eq!(room.avatar_url().as_deref(), Some("mxc://example.org/avatar"));
+    }
+
+    #[test]
+    fn room_version_and_creator_come_from_the_create_event() {
+        let mut v9 = Room::<String>::new("!v9:example.org".to_string());
+        v9.update_state(state(
+            STATE_CREATE,
+            "",
+            &[("room_version", "9"), ("creator", "@alice:example.org")],
+        ));
+        assert_eq!(v9.room_version(), "9");
+        assert_eq!(v9.creator().as_deref(), Some("@alice:example.org"));
+
+        let mut legacy = Room::<String>::new("!legacy:example.org".to_string());
+        let mut create = state(STATE_CREATE, "", &[]);
+        create.sender = "@bob:example.org".to_string();
+        legacy.update_state(create);
+        assert_eq!(legacy.room_version(), "1");
+        assert_eq!(legacy.creator().as_deref(), Some("@bob:example.org"));
+
+        let unknown = Room::<String>::new("!unknown:example.org".to_string());
+        assert_eq!(unknown.room_version(), "1");
+        assert_eq!(unknown.creator(), None);
+    }
+
+    #[test]
+    fn encryption_settings_of_encrypted_and_unencrypted_rooms() {
+        let mut room = Room::<String>::new("!room:example.org".to_string());
+        assert!(!room.is_encrypted());
+        assert_eq!(room.encryption_algorithm(), None);
+        assert_eq!(room.encryption(), None);
+
+        room.update_state(state(
+            STATE_ENCRYPTION,
+            "",
+            &[
+                ("algorithm", "m.megolm.v1.aes-sha2"),
+                ("rotation_period_ms", "604800000"),
+                ("rotation_period_msgs", "100"),
+            ],
+        ));
+        assert!(room.is_encrypted());
+        assert_eq!(room.encryption_algorithm().as_deref(), Some("m.megolm.v1.aes-sha2"));
+        assert_eq!(
+            room.encryption(),
+            Some(EncryptionSettings {
+                algorithm: "m.megolm.v1.aes-sha2".to_string(),
+                rotation_period_ms: Some(604_800_000),
+                rotation_period_msgs: Some(100),
+            })
+        );
+    }
+
+    #[test]
+    fn join_rules_and_guest_access() {
+        let mut room = Room::<String>::new("!room:example.org".to_string());
+        assert_eq!(room.join_rule(), "invite");
+        assert_eq!(room.guest_access(), "forbidden");
+
+        room.update_state(state(STATE_JOIN_RULES, "", &[("join_rule", "public")]));
+        room.update_state(state(STATE_GUEST_ACCESS, "", &[("guest_access", "can_join")]));
+        assert_eq!(room.join_rule(), "public");
+        assert!(room.join_allow_list().is_empty());
+        assert_eq!(room.guest_access(), "can_join");
+
+        room.update_state(state(
+            STATE_JOIN_RULES,
+            "",
+            &[
+                ("join_rule", "restricted"),
+                (
+                    "allow",
+                    r#"[{"type": "m.room_membership", "room_id": "!space:example.org"}]"#,
+                ),
+            ],
+        ));
+        assert_eq!(room.join_rule(), "restricted");
+        assert_eq!(room.join_allow_list(), ["!space:example.org"]);
+    }
+
+
+    #[test]
+    fn unloaded_members_resolve_to_none() {
+        let mut room = Room::<String>::new("!room:example.org".to_string());
+        room.update_state(member("@bob:example.org", "join", "Bob"));
+        assert!(!room.has_full_member_list());
+
+        let bob = room.resolve_member("@bob:example.org").unwrap();
+        assert_eq!(bob.membership, "join");
+        assert_eq!(bob.displayname.as_deref(), Some("Bob"));
+        assert!(room.resolve_member("@carol:example.org").is_none());
+
+        room.set_full_member_list(true);
+        assert!(room.has_full_member_list());
+        assert!(room.resolve_member("@carol:example.org").is_none());
+    }
+
+
+    #[test]
+    fn set_typing_replaces_the_typing_users() {
+        let mut room = Room::<String>::new("!room:example.org".to_string());
+        assert!(room.typing_users().is_empty());
+
+        room.set_typing(vec!["@alice:example.org".to_string(), "@bob:example.org".to_string()]);
+        assert_eq!(room.typing_users(), ["@alice:example.org", "@bob:example.org"]);
+
+        room.set_typing(vec!["@carol:example.org".to_string()]);
+        assert_eq!(room.typing_users(), ["@carol:example.org"]);
+
+        room.set_typing(Vec::new());
+        assert!(room.typing_users().is_empty());
+    }
+
+
+    #[test]
+    fn appending_an_event_twice_keeps_one_copy() {
+        let mut room = Room::<String>::new("!room:example.org".to_string());
+        assert!(!room.contains_event("$root"));
+
+        room.append_timeline(root());
+        room.append_timeline(reaction("$r1", "@alice:example.org"));
+        room.append_timeline(root());
+
+        assert_eq!(ids(room.recent_messages(usize::MAX)), ["$root", "$r1"]);
+        assert!(room.contains_event("$root"));
+        assert!(room.contains_event("$r1"));
+        assert!(!room.contains_event("$r2"));
+        assert!(!room.contains_event(""));
+    }
+
+
+    #[test]
+    fn reconcile_echo_replaces_the_matching_echo() {
+        let mut echo = root();
+        echo.id = String::new();
+        echo.unsigned.insert("local_only".to_string(), "kept".to_string());
+
+        let mut room = Room::<String>::new("!room:example.org".to_string());
+        room.add_local_echo("txn1", echo);
+        room.append_timeline(reaction("$r1", "@bob:example.org"));
+
+        let mut confirmed = root();
+        confirmed.unsigned.insert("transaction_id".to_string(), "txn1".to_string());
+        room.reconcile_echo(confirmed);
+
+        let timeline = room.recent_messages(usize::MAX);
+        assert_eq!(ids(timeline), ["$root", "$r1"]);
+        assert_eq!(timeline[0].unsigned["transaction_id"], "txn1");
+        assert_eq!(timeline[0].unsigned["local_only"], "kept");
+    }
+
+    #[test]
+    fn reconcile_echo_appends_events_without_an_echo() {
+        let mut room = Room::<String>::new("!room:example.org".to_string());
+        room.add_local_echo("txn1", reaction("", "@alice:example.org"));
+
+        let mut other_device = root();
+        other_device.unsigned.insert("transaction_id".to_string(), "txn2".to_string());
+        room.reconcile_echo(other_device);
+        room.reconcile_echo(reaction("$r2", "@bob:example.org"));
+
+        assert_eq!(ids(room.recent_messages(usize::MAX)), ["", "$root", "$r2"]);
     }
 }
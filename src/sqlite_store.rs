@@ -0,0 +1,391 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::path::Path;
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::room::Room;
+use crate::store::{StoreError, Storer};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS filters (
+        user_id TEXT PRIMARY KEY NOT NULL,
+        filter_id TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS next_batch (
+        user_id TEXT PRIMARY KEY NOT NULL,
+        next_batch_token TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS prev_batch (
+        room_id TEXT PRIMARY KEY NOT NULL,
+        prev_batch_token TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS rooms (
+        room_id TEXT PRIMARY KEY NOT NULL,
+        room TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS account_data (
+        user_id TEXT NOT NULL,
+        room_id TEXT NOT NULL,
+        etype TEXT NOT NULL,
+        content TEXT NOT NULL,
+        PRIMARY KEY (user_id, room_id, etype)
+    );
+";
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(err: rusqlite::Error) -> Self {
+        StoreError::Backend(err.to_string())
+    }
+}
+
+/// A [`Storer`] that persists filters, batch tokens and rooms in SQLite, so a
+/// client can resume syncing after a restart. Rooms are stored as JSON.
+pub struct SqliteStore<T> {
+    connection: Connection,
+    _content: PhantomData<T>,
+}
+
+impl<T> SqliteStore<T> {
+    /// Opens (or creates) the database at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StoreError> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    /// Opens a private in-memory database, mostly useful for tests.
+    pub fn open_in_memory() -> Result<Self, StoreError> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    /// Uses an existing connection, creating the store's tables if needed.
+    pub fn from_connection(connection: Connection) -> Result<Self, StoreError> {
+        connection.execute_batch(SCHEMA)?;
+
+        Ok(Self {
+            connection,
+            _content: PhantomData,
+        })
+    }
+
+    fn load_value(&self, sql: &str, key: &str) -> Result<Option<String>, StoreError> {
+        let value = self
+            .connection
+            .query_row(sql, params![key], |row| row.get(0))
+            .optional()?;
+
+        Ok(value)
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> SqliteStore<T> {
+    /// Global account data is stored with an empty `room_id`.
+    fn save_account_data_row(
+        &mut self,
+        user_id: &str,
+        room_id: &str,
+        etype: &str,
+        content: &HashMap<String, T>,
+    ) -> Result<(), StoreError> {
+        let serialized = serde_json::to_string(content)
+            .map_err(|err| StoreError::Serialization(err.to_string()))?;
+
+        self.connection.execute(
+            "INSERT OR REPLACE INTO account_data (user_id, room_id, etype, content)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![user_id, room_id, etype, serialized],
+        )?;
+        Ok(())
+    }
+
+    fn load_account_data_row(
+        &self,
+        user_id: &str,
+        room_id: &str,
+        etype: &str,
+    ) -> Result<Option<HashMap<String, T>>, StoreError> {
+        let serialized: Option<String> = self
+            .connection
+            .query_row(
+                "SELECT content FROM account_data
+                 WHERE user_id = ?1 AND room_id = ?2 AND etype = ?3",
+                params![user_id, room_id, etype],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        serialized
+            .map(|serialized| serde_json::from_str(&serialized))
+            .transpose()
+            .map_err(|err| StoreError::Serialization(err.to_string()))
+    }
+}
+
+impl<T: Clone + Serialize + DeserializeOwned> Storer<T> for SqliteStore<T> {
+    fn save_filter_id(&mut self, user_id: String, filter_id: String) -> Result<(), StoreError> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO filters (user_id, filter_id) VALUES (?1, ?2)",
+            params![user_id, filter_id],
+        )?;
+        Ok(())
+    }
+
+    fn load_filter_id(&self, user_id: String) -> Result<Option<String>, StoreError> {
+        self.load_value("SELECT filter_id FROM filters WHERE user_id = ?1", &user_id)
+    }
+
+    fn save_next_batch(&mut self, user_id: String, next_batch_token: String) -> Result<(), StoreError> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO next_batch (user_id, next_batch_token) VALUES (?1, ?2)",
+            params![user_id, next_batch_token],
+        )?;
+        Ok(())
+    }
+
+    fn load_next_batch(&self, user_id: String) -> Result<Option<String>, StoreError> {
+        self.load_value("SELECT next_batch_token FROM next_batch WHERE user_id = ?1", &user_id)
+    }
+
+    fn save_prev_batch(
+        &mut self,
+        room_id: String,
+        prev_batch: Option<String>,
+    ) -> Result<(), StoreError> {
+        match prev_batch {
+            Some(prev_batch) => self.connection.execute(
+                "INSERT OR REPLACE INTO prev_batch (room_id, prev_batch_token) VALUES (?1, ?2)",
+                params![room_id, prev_batch],
+            )?,
+            None => self
+                .connection
+                .execute("DELETE FROM prev_batch WHERE room_id = ?1", params![room_id])?,
+        };
+        Ok(())
+    }
+
+    fn load_prev_batch(&self, room_id: String) -> Result<Option<String>, StoreError> {
+        self.load_value("SELECT prev_batch_token FROM prev_batch WHERE room_id = ?1", &room_id)
+    }
+
+    fn save_room(&mut self, room: Room<T>) -> Result<(), StoreError> {
+        let serialized = serde_json::to_string(&room)
+            .map_err(|err| StoreError::Serialization(err.to_string()))?;
+
+        self.connection.execute(
+            "INSERT OR REPLACE INTO rooms (room_id, room) VALUES (?1, ?2)",
+            params![room.id, serialized],
+        )?;
+        Ok(())
+    }
+
+    fn load_room(&self, room_id: String) -> Result<Option<Room<T>>, StoreError> {
+        let Some(serialized) = self.load_value("SELECT room FROM rooms WHERE room_id = ?1", &room_id)?
+        else {
+            return Ok(None);
+        };
+
+        serde_json::from_str(&serialized)
+            .map(Some)
+            .map_err(|err| StoreError::Serialization(err.to_string()))
+    }
+
+    fn delete_room(&mut self, room_id: &str) -> Result<(), StoreError> {
+        self.connection
+            .execute("DELETE FROM rooms WHERE room_id = ?1", params![room_id])?;
+        self.connection
+            .execute("DELETE FROM prev_batch WHERE room_id = ?1", params![room_id])?;
+        Ok(())
+    }
+
+    fn list_room_ids(&self) -> Result<Vec<String>, StoreError> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT room_id FROM rooms ORDER BY room_id")?;
+        let room_ids = statement
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+
+        Ok(room_ids)
+    }
+
+    fn clear(&mut self) -> Result<(), StoreError> {
+        self.connection
+            .execute_batch(
+                "DELETE FROM filters; DELETE FROM next_batch; DELETE FROM prev_batch;
+                 DELETE FROM rooms; DELETE FROM account_data;",
+            )?;
+        Ok(())
+    }
+
+    fn save_account_data(
+        &mut self,
+        user_id: String,
+        etype: String,
+        content: HashMap<String, T>,
+    ) -> Result<(), StoreError> {
+        self.save_account_data_row(&user_id, "", &etype, &content)
+    }
+
+    fn load_account_data(
+        &self,
+        user_id: String,
+        etype: String,
+    ) -> Result<Option<HashMap<String, T>>, StoreError> {
+        self.load_account_data_row(&user_id, "", &etype)
+    }
+
+    fn save_room_account_data(
+        &mut self,
+        user_id: String,
+        room_id: String,
+        etype: String,
+        content: HashMap<String, T>,
+    ) -> Result<(), StoreError> {
+        self.save_account_data_row(&user_id, &room_id, &etype, &content)
+    }
+
+    fn load_room_account_data(
+        &self,
+        user_id: String,
+        room_id: String,
+        etype: String,
+    ) -> Result<Option<HashMap<String, T>>, StoreError> {
+        self.load_account_data_row(&user_id, &room_id, &etype)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::consts::{STATE_NAME, TAG_FAVOURITE};
+    use crate::events::EventBuilder;
+
+    /// A database path of its own for each test, removed when dropped.
+    struct TempDb(PathBuf);
+
+    impl TempDb {
+        fn new(name: &str) -> TempDb {
+            let path = std::env::temp_dir()
+                .join(format!("matrux-sqlite-{}-{}.db", name, std::process::id()));
+            let _ = fs::remove_file(&path);
+            TempDb(path)
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn named_room(room_id: &str, name: &str) -> Room<String> {
+        let mut content = HashMap::new();
+        content.insert("name".to_string(), name.to_string());
+
+        let mut room = Room::new(room_id.to_string());
+        room.update_state(
+            EventBuilder::new()
+                .etype(STATE_NAME.to_string())
+                .state_key(String::new())
+                .content(content)
+                .build(),
+        );
+        room
+    }
+
+    #[test]
+    fn data_survives_reopening_the_database() {
+        let db = TempDb::new("reopen");
+        let user_id = "@alice:example.org".to_string();
+        let room_id = "!room:example.org".to_string();
+
+        let mut tags = HashMap::new();
+        tags.insert(TAG_FAVOURITE.to_string(), r#"{"order":0.5}"#.to_string());
+
+        {
+            let mut store = SqliteStore::<String>::open(&db.0).unwrap();
+            store.save_room(named_room(&room_id, "Lobby")).unwrap();
+            store.save_filter_id(user_id.clone(), "filter-1".to_string()).unwrap();
+            store.save_next_batch(user_id.clone(), "s72594_4483_1934".to_string()).unwrap();
+            store.save_prev_batch(room_id.clone(), Some("t392-516".to_string())).unwrap();
+            store
+                .save_account_data(user_id.clone(), "m.push_rules".to_string(), HashMap::new())
+                .unwrap();
+            store
+                .save_room_account_data(
+                    user_id.clone(),
+                    room_id.clone(),
+                    "m.tag".to_string(),
+                    tags.clone(),
+                )
+                .unwrap();
+        }
+
+        let store = SqliteStore::<String>::open(&db.0).unwrap();
+        let room = store.load_room(room_id.clone()).unwrap().unwrap();
+
+        assert_eq!(room.id, room_id);
+        assert_eq!(room.display_name(), "Lobby");
+        assert_eq!(store.list_room_ids().unwrap(), vec![room_id.clone()]);
+        assert_eq!(store.load_filter_id(user_id.clone()).unwrap().as_deref(), Some("filter-1"));
+        assert_eq!(
+            store.load_next_batch(user_id.clone()).unwrap().as_deref(),
+            Some("s72594_4483_1934")
+        );
+        assert_eq!(store.load_prev_batch(room_id.clone()).unwrap().as_deref(), Some("t392-516"));
+        assert_eq!(
+            store.load_account_data(user_id.clone(), "m.push_rules".to_string()).unwrap(),
+            Some(HashMap::new())
+        );
+        assert_eq!(
+            store
+                .load_room_account_data(user_id.clone(), room_id.clone(), "m.tag".to_string())
+                .unwrap(),
+            Some(tags)
+        );
+        assert_eq!(store.load_account_data(user_id, "m.tag".to_string()).unwrap(), None);
+    }
+
+    #[test]
+    fn clearing_prev_batch_deletes_it() {
+        let db = TempDb::new("prev-batch");
+        let room_id = "!room:example.org".to_string();
+
+        let mut store = SqliteStore::<String>::open(&db.0).unwrap();
+        store.save_prev_batch(room_id.clone(), Some("t1".to_string())).unwrap();
+        store.save_prev_batch(room_id.clone(), None).unwrap();
+        assert_eq!(store.load_prev_batch(room_id.clone()).unwrap(), None);
+
+        let rows: i64 = store
+            .connection
+            .query_row("SELECT COUNT(*) FROM prev_batch", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(rows, 0);
+    }
+
+    #[test]
+    fn deleting_a_room_removes_its_prev_batch() {
+        let db = TempDb::new("delete-room");
+        let room_id = "!room:example.org".to_string();
+        let other_id = "!other:example.org".to_string();
+
+        let mut store = SqliteStore::<String>::open(&db.0).unwrap();
+        store.save_room(named_room(&room_id, "Lobby")).unwrap();
+        store.save_room(named_room(&other_id, "Other")).unwrap();
+        store.save_prev_batch(room_id.clone(), Some("t1".to_string())).unwrap();
+        store.save_prev_batch(other_id.clone(), Some("t2".to_string())).unwrap();
+
+        store.delete_room(&room_id).unwrap();
+        drop(store);
+
+        let store = SqliteStore::<String>::open(&db.0).unwrap();
+        assert!(store.load_room(room_id.clone()).unwrap().is_none());
+        assert_eq!(store.load_prev_batch(room_id).unwrap(), None);
+        assert_eq!(store.list_room_ids().unwrap(), vec![other_id.clone()]);
+        assert_eq!(store.load_prev_batch(other_id).unwrap().as_deref(), Some("t2"));
+    }
+}
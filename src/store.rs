@@ -1,46 +1,259 @@
 use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 use crate::room::Room;
 
+/// An error raised by a [`Storer`] backend.
+#[derive(Debug)]
+pub enum StoreError {
+    /// Reading from or writing to the underlying storage failed.
+    Io(io::Error),
+    /// A stored value could not be encoded or decoded.
+    Serialization(String),
+    /// Any other backend-specific failure.
+    Backend(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Io(err) => write!(f, "store I/O error: {}", err),
+            StoreError::Serialization(message) => write!(f, "store serialization error: {}", message),
+            StoreError::Backend(message) => write!(f, "store backend error: {}", message),
+        }
+    }
+}
+
+impl Error for StoreError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            StoreError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for StoreError {
+    fn from(err: io::Error) -> Self {
+        StoreError::Io(err)
+    }
+}
+
+/// Persists the state a client needs to resume syncing. Loads return `Ok(None)`
+/// when nothing has been stored under the given key.
 pub trait Storer<T: Clone> {
-    fn save_filter_id(&mut self, user_id: String, filter_id: String);
-    fn load_filter_id(self, user_id: String) -> String;
-    fn save_next_batch(&mut self, user_id: String, next_batch_token: String);
-    fn load_next_batch(self, user_id: String) -> String;
-    fn save_room(&mut self, room: Room<T>);
-    fn load_room(self, room_id: String) -> Room<T>;
+    fn save_filter_id(&mut self, user_id: String, filter_id: String) -> Result<(), StoreError>;
+    fn load_filter_id(&self, user_id: String) -> Result<Option<String>, StoreError>;
+    fn save_next_batch(&mut self, user_id: String, next_batch_token: String) -> Result<(), StoreError>;
+    fn load_next_batch(&self, user_id: String) -> Result<Option<String>, StoreError>;
+    /// Saves the token `/messages` continues backwards from in a room; see
+    /// [`Room::prev_batch`]. `None` clears it once the start of the room is reached.
+    fn save_prev_batch(
+        &mut self,
+        room_id: String,
+        prev_batch: Option<String>,
+    ) -> Result<(), StoreError>;
+    fn load_prev_batch(&self, room_id: String) -> Result<Option<String>, StoreError>;
+    fn save_room(&mut self, room: Room<T>) -> Result<(), StoreError>;
+    fn load_room(&self, room_id: String) -> Result<Option<Room<T>>, StoreError>;
+    /// Forgets a room, e.g. after leaving it. Deleting an unknown room is not an error.
+    fn delete_room(&mut self, room_id: &str) -> Result<(), StoreError>;
+    fn list_room_ids(&self) -> Result<Vec<String>, StoreError>;
+    /// Removes everything held by the store.
+    fn clear(&mut self) -> Result<(), StoreError>;
+    /// Saves global account data such as `m.direct`, replacing any previous content.
+    fn save_account_data(
+        &mut self,
+        user_id: String,
+        etype: String,
+        content: HashMap<String, T>,
+    ) -> Result<(), StoreError>;
+    fn load_account_data(
+        &self,
+        user_id: String,
+        etype: String,
+    ) -> Result<Option<HashMap<String, T>>, StoreError>;
+    /// Saves account data scoped to a single room, such as `m.fully_read`.
+    fn save_room_account_data(
+        &mut self,
+        user_id: String,
+        room_id: String,
+        etype: String,
+        content: HashMap<String, T>,
+    ) -> Result<(), StoreError>;
+    fn load_room_account_data(
+        &self,
+        user_id: String,
+        room_id: String,
+        etype: String,
+    ) -> Result<Option<HashMap<String, T>>, StoreError>;
 }
 
-struct InMemoryStore<T: Clone> {
+/// Account data content keyed by event type.
+type AccountData<T> = HashMap<String, HashMap<String, T>>;
+
+/// A [`Storer`] that keeps everything in memory and forgets it on drop.
+///
+/// # Examples
+///
+/// ```rust
+/// use matrux::room::Room;
+/// use matrux::store::{InMemoryStore, Storer};
+///
+/// let mut store: InMemoryStore<String> = InMemoryStore::new();
+/// store.save_room(Room::new("!room:example.org".to_string())).unwrap();
+///
+/// assert_eq!(store.len(), 1);
+/// for room in store.rooms() {
+///     assert_eq!(room.id, "!room:example.org");
+/// }
+/// ```
+pub struct InMemoryStore<T: Clone> {
     filters: HashMap<String, String>,
     next_batch: HashMap<String, String>,
+    prev_batch: HashMap<String, String>,
     rooms: HashMap<String, Room<T>>,
+    account_data: HashMap<String, AccountData<T>>,
+    room_account_data: HashMap<String, HashMap<String, AccountData<T>>>,
+    /// The most rooms to hold, if limited.
+    capacity: Option<usize>,
+    /// Ticks on every room access, so rooms can be ordered by recency. Atomic so
+    /// `load_room` can record an access through `&self`.
+    clock: AtomicU64,
+    last_access: HashMap<String, AtomicU64>,
 }
 
 impl<T: Clone> Storer<T> for InMemoryStore<T> {
-    fn save_filter_id(&mut self, user_id: String, filter_id: String) {
+    fn save_filter_id(&mut self, user_id: String, filter_id: String) -> Result<(), StoreError> {
         self.filters.insert(user_id, filter_id);
+        Ok(())
     }
 
-    fn load_filter_id(self, user_id: String) -> String {
-        self.filters.get(&user_id).unwrap().to_owned()
+    fn load_filter_id(&self, user_id: String) -> Result<Option<String>, StoreError> {
+        Ok(self.filters.get(&user_id).cloned())
     }
 
-    fn save_next_batch(&mut self, user_id: String, next_batch_token: String) {
+    fn save_next_batch(&mut self, user_id: String, next_batch_token: String) -> Result<(), StoreError> {
         self.next_batch.insert(user_id, next_batch_token);
+        Ok(())
+    }
+
+    fn load_next_batch(&self, user_id: String) -> Result<Option<String>, StoreError> {
+        Ok(self.next_batch.get(&user_id).cloned())
+    }
+
+    fn save_prev_batch(
+        &mut self,
+        room_id: String,
+        prev_batch: Option<String>,
+    ) -> Result<(), StoreError> {
+        match prev_batch {
+            Some(prev_batch) => self.prev_batch.insert(room_id, prev_batch),
+            None => self.prev_batch.remove(&room_id),
+        };
+        Ok(())
+    }
+
+    fn load_prev_batch(&self, room_id: String) -> Result<Option<String>, StoreError> {
+        Ok(self.prev_batch.get(&room_id).cloned())
+    }
+
+    fn save_room(&mut self, room: Room<T>) -> Result<(), StoreError> {
+        let room_id = room.id.clone();
+        self.rooms.insert(room_id.clone(), room);
+        self.last_access.insert(room_id, AtomicU64::new(self.tick()));
+        self.evict();
+        Ok(())
+    }
+
+    fn load_room(&self, room_id: String) -> Result<Option<Room<T>>, StoreError> {
+        if let Some(last_access) = self.last_access.get(&room_id) {
+            last_access.store(self.tick(), Ordering::Relaxed);
+        }
+        Ok(self.rooms.get(&room_id).cloned())
+    }
+
+    fn delete_room(&mut self, room_id: &str) -> Result<(), StoreError> {
+        self.rooms.remove(room_id);
+        self.last_access.remove(room_id);
+        self.prev_batch.remove(room_id);
+        Ok(())
+    }
+
+    fn list_room_ids(&self) -> Result<Vec<String>, StoreError> {
+        let mut room_ids: Vec<String> = self.rooms.keys().cloned().collect();
+        room_ids.sort();
+        Ok(room_ids)
+    }
+
+    fn clear(&mut self) -> Result<(), StoreError> {
+        self.filters.clear();
+        self.next_batch.clear();
+        self.prev_batch.clear();
+        self.rooms.clear();
+        self.last_access.clear();
+        self.account_data.clear();
+        self.room_account_data.clear();
+        Ok(())
     }
 
-    fn load_next_batch(self, user_id: String) -> String {
-        self.next_batch.get(&user_id).unwrap().to_owned()
+    fn save_account_data(
+        &mut self,
+        user_id: String,
+        etype: String,
+        content: HashMap<String, T>,
+    ) -> Result<(), StoreError> {
+        self.account_data
+            .entry(user_id)
+            .or_default()
+            .insert(etype, content);
+        Ok(())
     }
 
-    fn save_room(&mut self, room: Room<T>) {
-        let room_id = &room.id;
-        self.rooms.insert(room_id.to_string(), room);
+    fn load_account_data(
+        &self,
+        user_id: String,
+        etype: String,
+    ) -> Result<Option<HashMap<String, T>>, StoreError> {
+        Ok(self
+            .account_data
+            .get(&user_id)
+            .and_then(|account_data| account_data.get(&etype))
+            .cloned())
     }
 
-    fn load_room(self, room_id: String) -> Room<T> {
-        self.rooms.get(&room_id).unwrap().to_owned().clone()
+    fn save_room_account_data(
+        &mut self,
+        user_id: String,
+        room_id: String,
+        etype: String,
+        content: HashMap<String, T>,
+    ) -> Result<(), StoreError> {
+        self.room_account_data
+            .entry(user_id)
+            .or_default()
+            .entry(room_id)
+            .or_default()
+            .insert(etype, content);
+        Ok(())
+    }
+
+    fn load_room_account_data(
+        &self,
+        user_id: String,
+        room_id: String,
+        etype: String,
+    ) -> Result<Option<HashMap<String, T>>, StoreError> {
+        Ok(self
+            .room_account_data
+            .get(&user_id)
+            .and_then(|rooms| rooms.get(&room_id))
+            .and_then(|account_data| account_data.get(&etype))
+            .cloned())
     }
 }
 
@@ -49,7 +262,385 @@ impl<T: Clone> InMemoryStore<T> {
         Self {
             filters: HashMap::new(),
             next_batch: HashMap::new(),
+            prev_batch: HashMap::new(),
             rooms: HashMap::new(),
+            account_data: HashMap::new(),
+            room_account_data: HashMap::new(),
+            capacity: None,
+            clock: AtomicU64::new(0),
+            last_access: HashMap::new(),
+        }
+    }
+
+    /// A store holding at most `capacity` rooms. Saving one more evicts the room
+    /// that was least recently saved or loaded. Only room snapshots are evicted;
+    /// batch tokens, filters and account data are kept.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use matrux::room::Room;
+    /// use matrux::store::{InMemoryStore, Storer};
+    ///
+    /// let mut store: InMemoryStore<String> = InMemoryStore::with_capacity(2);
+    /// store.save_next_batch("@alice:example.org".to_string(), "s1".to_string()).unwrap();
+    ///
+    /// for room_id in ["!a:example.org", "!b:example.org"] {
+    ///     store.save_room(Room::new(room_id.to_string())).unwrap();
+    /// }
+    /// store.load_room("!a:example.org".to_string()).unwrap();
+    /// store.save_room(Room::new("!c:example.org".to_string())).unwrap();
+    ///
+    /// assert_eq!(store.list_room_ids().unwrap(), ["!a:example.org", "!c:example.org"]);
+    /// assert!(store.load_next_batch("@alice:example.org".to_string()).unwrap().is_some());
+    /// ```
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            capacity: Some(capacity),
+            ..Self::new()
+        }
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Drops least recently used rooms until the store is within its capacity.
+    fn evict(&mut self) {
+        let Some(capacity) = self.capacity else {
+            return;
+        };
+
+        while self.rooms.len() > capacity {
+            let oldest = self
+                .last_access
+                .iter()
+                .min_by_key(|(_, last_access)| last_access.load(Ordering::Relaxed))
+                .map(|(room_id, _)| room_id.clone());
+
+            let Some(oldest) = oldest else {
+                return;
+            };
+
+            self.rooms.remove(&oldest);
+            self.last_access.remove(&oldest);
+        }
+    }
+
+    /// The number of rooms held by the store.
+    pub fn len(&self) -> usize {
+        self.rooms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rooms.is_empty()
+    }
+
+    pub fn rooms(&self) -> impl Iterator<Item = &Room<T>> {
+        self.rooms.values()
+    }
+}
+
+impl<T: Clone> Default for InMemoryStore<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Shares a [`Storer`] between threads, e.g. a sync task and a UI thread. Clones
+/// share the wrapped store.
+///
+/// Loads take a read lock, so they can run at the same time, and saves take a
+/// write lock.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::thread;
+/// use matrux::room::Room;
+/// use matrux::store::{InMemoryStore, SharedStore, Storer};
+///
+/// let store = SharedStore::new(InMemoryStore::<String>::new());
+///
+/// let mut writer = store.clone();
+/// let saving = thread::spawn(move || {
+///     for i in 0..100 {
+///         writer.save_room(Room::new(format!("!{}:example.org", i))).unwrap();
+///     }
+/// });
+///
+/// let reader = store.clone();
+/// let loading = thread::spawn(move || {
+///     for i in 0..100 {
+///         reader.load_room(format!("!{}:example.org", i)).unwrap();
+///     }
+/// });
+///
+/// saving.join().unwrap();
+/// loading.join().unwrap();
+/// assert_eq!(store.list_room_ids().unwrap().len(), 100);
+/// ```
+#[derive(Debug)]
+pub struct SharedStore<S> {
+    store: Arc<RwLock<S>>,
+}
+
+impl<S> SharedStore<S> {
+    pub fn new(store: S) -> Self {
+        SharedStore {
+            store: Arc::new(RwLock::new(store)),
+        }
+    }
+
+    fn read(&self) -> Result<RwLockReadGuard<'_, S>, StoreError> {
+        self.store
+            .read()
+            .map_err(|_| StoreError::Backend("store lock poisoned".to_string()))
+    }
+
+    fn write(&self) -> Result<RwLockWriteGuard<'_, S>, StoreError> {
+        self.store
+            .write()
+            .map_err(|_| StoreError::Backend("store lock poisoned".to_string()))
+    }
+}
+
+impl<S> Clone for SharedStore<S> {
+    fn clone(&self) -> Self {
+        SharedStore {
+            store: Arc::clone(&self.store),
         }
     }
 }
+
+impl<T: Clone, S: Storer<T>> Storer<T> for SharedStore<S> {
+    fn save_filter_id(&mut self, user_id: String, filter_id: String) -> Result<(), StoreError> {
+        self.write()?.save_filter_id(user_id, filter_id)
+    }
+
+    fn load_filter_id(&self, user_id: String) -> Result<Option<String>, StoreError> {
+        self.read()?.load_filter_id(user_id)
+    }
+
+    fn save_next_batch(&mut self, user_id: String, next_batch_token: String) -> Result<(), StoreError> {
+        self.write()?.save_next_batch(user_id, next_batch_token)
+    }
+
+    fn load_next_batch(&self, user_id: String) -> Result<Option<String>, StoreError> {
+        self.read()?.load_next_batch(user_id)
+    }
+
+    fn save_prev_batch(
+        &mut self,
+        room_id: String,
+        prev_batch: Option<String>,
+    ) -> Result<(), StoreError> {
+        self.write()?.save_prev_batch(room_id, prev_batch)
+    }
+
+    fn load_prev_batch(&self, room_id: String) -> Result<Option<String>, StoreError> {
+        self.read()?.load_prev_batch(room_id)
+    }
+
+    fn save_room(&mut self, room: Room<T>) -> Result<(), StoreError> {
+        self.write()?.save_room(room)
+    }
+
+    fn load_room(&self, room_id: String) -> Result<Option<Room<T>>, StoreError> {
+        self.read()?.load_room(room_id)
+    }
+
+    fn delete_room(&mut self, room_id: &str) -> Result<(), StoreError> {
+        self.write()?.delete_room(room_id)
+    }
+
+    fn list_room_ids(&self) -> Result<Vec<String>, StoreError> {
+        self.read()?.list_room_ids()
+    }
+
+    fn clear(&mut self) -> Result<(), StoreError> {
+        self.write()?.clear()
+    }
+
+    fn save_account_data(
+        &mut self,
+        user_id: String,
+        etype: String,
+        content: HashMap<String, T>,
+    ) -> Result<(), StoreError> {
+        self.write()?.save_account_data(user_id, etype, content)
+    }
+
+    fn load_account_data(
+        &self,
+        user_id: String,
+        etype: String,
+    ) -> Result<Option<HashMap<String, T>>, StoreError> {
+        self.read()?.load_account_data(user_id, etype)
+    }
+
+    fn save_room_account_data(
+        &mut self,
+        user_id: String,
+        room_id: String,
+        etype: String,
+        content: HashMap<String, T>,
+    ) -> Result<(), StoreError> {
+        self.write()?.save_room_account_data(user_id, room_id, etype, content)
+    }
+
+    fn load_room_account_data(
+        &self,
+        user_id: String,
+        room_id: String,
+        etype: String,
+    ) -> Result<Option<HashMap<String, T>>, StoreError> {
+        self.read()?.load_room_account_data(user_id, room_id, etype)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> InMemoryStore<String> {
+        InMemoryStore::new()
+    }
+
+    #[test]
+    fn rooms_can_be_loaded_more_than_once() {
+        let mut store = store();
+        let mut room = Room::new("!room:example.org".to_string());
+        room.set_prev_batch(Some("t1".to_string()));
+        store.save_room(room).unwrap();
+
+        for _ in 0..2 {
+            let room = store
+                .load_room("!room:example.org".to_string())
+                .unwrap()
+                .unwrap();
+            assert_eq!(room.id, "!room:example.org");
+            assert_eq!(room.prev_batch(), Some("t1"));
+        }
+        assert!(store
+            .load_room("!other:example.org".to_string())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn missing_values_load_as_none() {
+        let mut store = store();
+        assert!(matches!(
+            store.load_filter_id("@alice:example.org".to_string()),
+            Ok(None)
+        ));
+        assert!(matches!(
+            store.load_next_batch("@alice:example.org".to_string()),
+            Ok(None)
+        ));
+
+        store
+            .save_filter_id("@alice:example.org".to_string(), "filter".to_string())
+            .unwrap();
+        assert_eq!(
+            store
+                .load_filter_id("@alice:example.org".to_string())
+                .unwrap()
+                .as_deref(),
+            Some("filter")
+        );
+        assert!(matches!(
+            store.load_filter_id("@bob:example.org".to_string()),
+            Ok(None)
+        ));
+    }
+
+    #[test]
+    fn deleted_rooms_are_no_longer_listed() {
+        let mut store = store();
+        store
+            .save_room(Room::new("!books:example.org".to_string()))
+            .unwrap();
+        store
+            .save_room(Room::new("!films:example.org".to_string()))
+            .unwrap();
+        assert_eq!(
+            store.list_room_ids().unwrap(),
+            ["!books:example.org", "!films:example.org"]
+        );
+
+        store.delete_room("!books:example.org").unwrap();
+        assert_eq!(store.list_room_ids().unwrap(), ["!films:example.org"]);
+        assert!(store
+            .load_room("!books:example.org".to_string())
+            .unwrap()
+            .is_none());
+
+        store.clear().unwrap();
+        assert!(store.list_room_ids().unwrap().is_empty());
+    }
+
+    #[test]
+    fn account_data_round_trips() {
+        let mut direct = HashMap::new();
+        direct.insert(
+            "@bob:example.org".to_string(),
+            r#"["!dm:example.org"]"#.to_string(),
+        );
+
+        let mut store = store();
+        store
+            .save_account_data(
+                "@alice:example.org".to_string(),
+                "m.direct".to_string(),
+                direct.clone(),
+            )
+            .unwrap();
+        let loaded = store
+            .load_account_data("@alice:example.org".to_string(), "m.direct".to_string())
+            .unwrap();
+        assert_eq!(loaded, Some(direct));
+        assert!(store
+            .load_account_data("@bob:example.org".to_string(), "m.direct".to_string())
+            .unwrap()
+            .is_none());
+
+        let mut fully_read = HashMap::new();
+        fully_read.insert("event_id".to_string(), "$read".to_string());
+        store
+            .save_room_account_data(
+                "@alice:example.org".to_string(),
+                "!room:example.org".to_string(),
+                "m.fully_read".to_string(),
+                fully_read.clone(),
+            )
+            .unwrap();
+        let loaded = store
+            .load_room_account_data(
+                "@alice:example.org".to_string(),
+                "!room:example.org".to_string(),
+                "m.fully_read".to_string(),
+            )
+            .unwrap();
+        assert_eq!(loaded, Some(fully_read));
+    }
+
+    #[test]
+    fn prev_batch_round_trips() {
+        let mut store = store();
+        let room_id = "!room:example.org".to_string();
+        assert_eq!(store.load_prev_batch(room_id.clone()).unwrap(), None);
+
+        store
+            .save_prev_batch(room_id.clone(), Some("t42-older".to_string()))
+            .unwrap();
+        assert_eq!(
+            store.load_prev_batch(room_id.clone()).unwrap().as_deref(),
+            Some("t42-older")
+        );
+
+        store.save_prev_batch(room_id.clone(), None).unwrap();
+        assert_eq!(store.load_prev_batch(room_id).unwrap(), None);
+    }
+}
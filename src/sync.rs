@@ -1 +1,334 @@
-// trait Syncer
\ No newline at end of file
+use std::collections::{HashMap, HashSet};
+
+use serde_json::Value;
+
+use crate::consts::{EVENT_RECEIPT, EVENT_TYPING, MEMBERSHIP_INVITE, MEMBERSHIP_JOIN};
+use crate::events::{Event, ParseError};
+use crate::room::{Receipt, Room};
+
+/// The rooms and batch token returned by `/sync`.
+///
+/// Each room holds only what this response delivered; use [`Room`] snapshots from
+/// earlier responses as the base to merge into.
+#[derive(Clone, Default)]
+pub struct SyncResponse {
+    /// The token to pass as `since` on the next sync.
+    pub next_batch: String,
+    pub joined: HashMap<String, Room<String>>,
+    /// Rooms the user has been invited to, built from their stripped `invite_state`.
+    pub invited: HashMap<String, Room<String>>,
+    pub left: HashMap<String, Room<String>>,
+}
+
+/// Controls what [`SyncResponse::from_json_with`] keeps. The default keeps
+/// everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Event types to drop while parsing, e.g. `m.typing` for a client that never
+    /// shows typing notifications. Applies to state, timeline and ephemeral events.
+    pub ignore_types: HashSet<String>,
+}
+
+impl ParseOptions {
+    fn keeps(&self, raw: &Value) -> bool {
+        raw["type"]
+            .as_str()
+            .is_none_or(|etype| !self.ignore_types.contains(etype))
+    }
+}
+
+impl SyncResponse {
+    /// Parses a `/sync` response body.
+    ///
+    /// Only a missing `next_batch` is an error. Events that can't be parsed are
+    /// skipped rather than failing the whole response, since one malformed event
+    /// shouldn't stop a client from syncing.
+    pub fn from_json(value: &Value) -> Result<SyncResponse, ParseError> {
+        SyncResponse::from_json_with(value, &ParseOptions::default())
+    }
+
+    /// Parses a `/sync` response body like [`SyncResponse::from_json`], dropping
+    /// the events `options` ignores.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use matrux::sync::{ParseOptions, SyncResponse};
+    /// use serde_json::json;
+    ///
+    /// let body = json!({
+    ///     "next_batch": "s1",
+    ///     "rooms": { "join": { "!room:example.org": { "ephemeral": { "events": [
+    ///         { "type": "m.typing", "content": { "user_ids": ["@alice:example.org"] } }
+    ///     ] } } } }
+    /// });
+    ///
+    /// let mut options = ParseOptions::default();
+    /// options.ignore_types.insert("m.typing".to_string());
+    ///
+    /// let response = SyncResponse::from_json_with(&body, &options).unwrap();
+    /// assert!(response.joined["!room:example.org"].typing_users().is_empty());
+    ///
+    /// let response = SyncResponse::from_json(&body).unwrap();
+    /// assert_eq!(response.joined["!room:example.org"].typing_users(), ["@alice:example.org"]);
+    /// ```
+    pub fn from_json_with(value: &Value, options: &ParseOptions) -> Result<SyncResponse, ParseError> {
+        if !value.is_object() {
+            return Err(ParseError::NotAnObject);
+        }
+
+        let next_batch = match value.get("next_batch") {
+            Some(Value::String(next_batch)) => next_batch.clone(),
+            Some(_) => return Err(ParseError::InvalidField("next_batch")),
+            None => return Err(ParseError::MissingField("next_batch")),
+        };
+        let rooms = &value["rooms"];
+
+        Ok(SyncResponse {
+            next_batch,
+            joined: parse_rooms(&rooms["join"], options, parse_room),
+            invited: parse_rooms(&rooms["invite"], options, parse_invited_room),
+            left: parse_rooms(&rooms["leave"], options, parse_room),
+        })
+    }
+}
+
+fn parse_rooms(
+    rooms: &Value,
+    options: &ParseOptions,
+    parse: fn(&str, &Value, &ParseOptions) -> Room<String>,
+) -> HashMap<String, Room<String>> {
+    rooms
+        .as_object()
+        .map(|rooms| {
+            rooms
+                .iter()
+                .map(|(room_id, room)| (room_id.clone(), parse(room_id, room, options)))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn parse_room(room_id: &str, value: &Value, options: &ParseOptions) -> Room<String> {
+    let mut room = Room::new(room_id.to_string());
+
+    for raw in events(&value["state"], options) {
+        if let Some(event) = parse_event(room_id, raw) {
+            room.update_state(event);
+        }
+    }
+
+    let mut first_event_id = None;
+
+    for raw in events(&value["timeline"], options) {
+        if let Some(event) = parse_event(room_id, raw) {
+            first_event_id.get_or_insert_with(|| event.id.clone());
+            room.append_timeline(event);
+        }
+    }
+
+    room.set_prev_batch(value["timeline"]["prev_batch"].as_str().map(String::from));
+
+    // The server left events out between this batch and the previous one.
+    if let Some(first_event_id) = first_event_id.filter(|_| value["timeline"]["limited"] == true) {
+        room.insert_gap(&first_event_id, room.prev_batch().map(String::from));
+    }
+
+    room.set_full_member_list(has_full_member_list(&room, &value["summary"]));
+
+    for ephemeral in events(&value["ephemeral"], options) {
+        match ephemeral["type"].as_str() {
+            Some(EVENT_RECEIPT) => add_receipts(&mut room, &ephemeral["content"]),
+            Some(EVENT_TYPING) => room.set_typing(
+                ephemeral["content"]["user_ids"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_str)
+                    .map(String::from)
+                    .collect(),
+            ),
+            _ => {}
+        }
+    }
+
+    let unread = &value["unread_notifications"];
+    room.set_unread_counts(
+        unread["notification_count"].as_u64().unwrap_or(0),
+        unread["highlight_count"].as_u64().unwrap_or(0),
+    );
+    room
+}
+
+/// Servers only send `summary` when members are lazy-loaded. Without it the sync
+/// carries every member; with it, the member counts tell whether all joined and
+/// invited members have been delivered.
+fn has_full_member_list(room: &Room<String>, summary: &Value) -> bool {
+    if summary.is_null() {
+        return true;
+    }
+
+    let (Some(joined), Some(invited)) = (
+        summary["m.joined_member_count"].as_u64(),
+        summary["m.invited_member_count"].as_u64(),
+    ) else {
+        return false;
+    };
+
+    let members = room.members();
+    let count = |membership: &str| {
+        members
+            .iter()
+            .filter(|(_, member_membership)| member_membership == membership)
+            .count() as u64
+    };
+
+    count(MEMBERSHIP_JOIN) >= joined && count(MEMBERSHIP_INVITE) >= invited
+}
+
+/// Receipt content maps event ids to receipt types to users. Private receipts are
+/// only ever sent to their own user and count the same as public ones.
+fn add_receipts(room: &mut Room<String>, content: &Value) {
+    let Some(content) = content.as_object() else {
+        return;
+    };
+
+    for (event_id, receipt_types) in content {
+        for receipt_type in ["m.read", "m.read.private"] {
+            let Some(users) = receipt_types[receipt_type].as_object() else {
+                continue;
+            };
+
+            for (user_id, receipt) in users {
+                room.set_receipt(
+                    event_id,
+                    Receipt {
+                        user_id: user_id.clone(),
+                        ts: receipt["ts"].as_i64().unwrap_or(0),
+                    },
+                );
+            }
+        }
+    }
+}
+
+/// Invites only carry stripped state: `type`, `state_key`, `sender` and `content`.
+fn parse_invited_room(room_id: &str, value: &Value, options: &ParseOptions) -> Room<String> {
+    let events = events(&value["invite_state"], options)
+        .filter_map(|raw| {
+            let (Some(etype), Some(state_key)) = (raw["type"].as_str(), raw["state_key"].as_str())
+            else {
+                return None;
+            };
+
+            let content = match &raw["content"] {
+                Value::Object(content) => content.clone().into_iter().collect(),
+                _ => HashMap::new(),
+            };
+
+            Some(Event::from(Event {
+                etype: etype.to_string(),
+                state_key: state_key.to_string(),
+                has_state_key: true,
+                sender: raw["sender"].as_str().unwrap_or_default().to_string(),
+                room_id: room_id.to_string(),
+                content,
+                ..Event::<Value>::default()
+            }))
+        })
+        .collect();
+
+    Room::from_invite_state(room_id.to_string(), events)
+}
+
+fn parse_event(room_id: &str, raw: &Value) -> Option<Event<String>> {
+    let mut event = Event::from_sync_json(raw).ok()?;
+
+    if event.room_id.is_empty() {
+        event.room_id = room_id.to_string();
+    }
+
+    Some(Event::from(event))
+}
+
+fn events<'a>(section: &'a Value, options: &'a ParseOptions) -> impl Iterator<Item = &'a Value> {
+    section["events"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|raw| options.keeps(raw))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn joined_room(room: Value) -> Room<String> {
+        let body = json!({
+            "next_batch": "s1",
+            "rooms": { "join": { "!room:example.org": room } },
+        });
+
+        let mut response = SyncResponse::from_json(&body).unwrap();
+        response.joined.remove("!room:example.org").unwrap()
+    }
+
+    #[test]
+    fn unread_counts_are_parsed_and_cleared_by_mark_read() {
+        let mut room = joined_room(json!({
+            "unread_notifications": { "notification_count": 3, "highlight_count": 1 },
+        }));
+        assert_eq!(room.notification_count(), 3);
+        assert_eq!(room.highlight_count(), 1);
+
+        room.mark_read("$1");
+        assert_eq!(room.notification_count(), 0);
+        assert_eq!(room.highlight_count(), 0);
+        assert_eq!(room.read_marker(), Some("$1"));
+    }
+
+    #[test]
+    fn missing_unread_counts_are_zero() {
+        let room = joined_room(json!({}));
+        assert_eq!(room.notification_count(), 0);
+        assert_eq!(room.highlight_count(), 0);
+    }
+
+
+    fn message(id: &str, time_stamp: i64) -> Value {
+        json!({
+            "type": "m.room.message",
+            "event_id": id,
+            "sender": "@carol:example.org",
+            "origin_server_ts": time_stamp,
+            "content": { "msgtype": "m.text", "body": "hello" },
+        })
+    }
+
+    #[test]
+    fn public_and_private_receipts_are_both_read() {
+        let room = joined_room(json!({
+            "timeline": { "events": [message("$1", 10), message("$2", 20)] },
+            "ephemeral": { "events": [{
+                "type": "m.receipt",
+                "content": {
+                    "$1": { "m.read": { "@alice:example.org": { "ts": 100 } } },
+                    "$2": { "m.read.private": { "@bob:example.org": { "ts": 200 } } },
+                },
+            }] },
+        }));
+
+        let alice = Receipt {
+            user_id: "@alice:example.org".to_string(),
+            ts: 100,
+        };
+        let bob = Receipt {
+            user_id: "@bob:example.org".to_string(),
+            ts: 200,
+        };
+        assert_eq!(room.read_receipts("$1"), [alice, bob.clone()]);
+        assert_eq!(room.read_receipts("$2"), [bob]);
+    }
+}